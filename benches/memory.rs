@@ -0,0 +1,60 @@
+//! Memory used by parsed formulas, comparing expression trees with
+//! flat trees. Run with `cargo bench --bench memory`.
+
+extern crate expr;
+
+use expr::flat::FlatTree;
+use expr::parser::{parse, parse_flat};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator keeping track of the number of bytes in use.
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        IN_USE.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const FORMULAS: usize = 100_000;
+
+fn formula(n: usize) -> String {
+    format!(
+        "(price_{} * quantity - discount) * (1 + tax_rate) + max(shipping, {}) / 2",
+        n % 100,
+        n
+    )
+}
+
+/// Bytes in use after building the formulas with `build`.
+fn measure<T>(build: impl Fn(&str) -> T) -> usize {
+    let texts: Vec<String> = (0..FORMULAS).map(formula).collect();
+    let before = IN_USE.load(Ordering::Relaxed);
+    let mut parsed = Vec::with_capacity(FORMULAS);
+    for text in &texts {
+        parsed.push(build(text));
+    }
+    IN_USE.load(Ordering::Relaxed) - before
+}
+
+fn main() {
+    let tree = measure(|text| parse(text).unwrap());
+    let flat = measure(|text| parse_flat(text).unwrap());
+    let converted = measure(|text| FlatTree::from(&parse(text).unwrap()));
+    println!("{} formulas", FORMULAS);
+    println!("expression trees: {:>10} bytes", tree);
+    println!("flat trees:       {:>10} bytes", flat);
+    println!("converted:        {:>10} bytes", converted);
+}
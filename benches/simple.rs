@@ -12,6 +12,7 @@ fn bench_simple(c: &mut Criterion) {
     });
 }
 
+#[allow(clippy::approx_constant)]
 fn bench_vars(c: &mut Criterion) {
     let mut vars: HashMap<String, f64> = HashMap::new();
     vars.insert("x".to_string(), 12.0);
@@ -0,0 +1,56 @@
+#[macro_use]
+extern crate criterion;
+extern crate expr;
+
+use criterion::{black_box, Criterion};
+use expr::parse;
+use expr::tokens::{Token, Tokenizer};
+
+/// Long input with many names and operators, like a generated formula.
+fn long_input() -> String {
+    (0..2000)
+        .map(|n| format!("weight_{} * (value_{} - offset) / 2", n % 50, n))
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+fn bench_tokenize(c: &mut Criterion) {
+    let text = long_input();
+    c.bench_function("tokenize long", |b| {
+        b.iter(|| {
+            let mut tokens = Tokenizer::new(&text);
+            while tokens.peek().is_some() {
+                black_box(tokens.next());
+            }
+        })
+    });
+}
+
+/// Baseline for `tokenize long`, using the algorithm of the tokenizer
+/// before lookahead was buffered and tokens borrowed from the input:
+/// every token is peeked by cloning the tokenizer and reading the
+/// token again, and the text of every token is copied.
+fn bench_tokenize_baseline(c: &mut Criterion) {
+    let text = long_input();
+    c.bench_function("tokenize long baseline", |b| {
+        b.iter(|| {
+            let mut tokens = Tokenizer::new(&text);
+            while tokens.clone().next().map(Token::into_owned).is_some() {
+                black_box(tokens.next().map(Token::into_owned));
+            }
+        })
+    });
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let text = long_input();
+    c.bench_function("parse long", |b| b.iter(|| parse(&text).unwrap()));
+}
+
+criterion_group!(
+    benches,
+    bench_tokenize,
+    bench_tokenize_baseline,
+    bench_parse
+);
+criterion_main!(benches);
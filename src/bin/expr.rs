@@ -1,6 +1,6 @@
 extern crate expr;
 
-use expr::eval;
+use expr::{eval, parse};
 use std::collections::HashMap;
 use std::env::args;
 
@@ -8,6 +8,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = args().collect::<Vec<String>>();
     if args.len() < 2 {
         println!("Usage: expr <expression> [ <variable>=<value> ... ]");
+        println!("       expr --info <expression>");
+    } else if args[1] == "--info" && args.len() == 3 {
+        let tree = parse(&args[2])?;
+        println!("expression: {}", tree);
+        println!("variables: {}", tree.free_variables().join(", "));
+        println!("functions: {}", tree.functions().join(", "));
+        println!("nodes: {}", tree.node_count());
+        println!("depth: {}", tree.depth());
+        println!("constant: {}", tree.is_constant());
     } else {
         let expr = &args[1];
         let mut map = HashMap::new();
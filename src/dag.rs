@@ -0,0 +1,337 @@
+//! Shared representation of expression trees.
+//!
+//! Generated formulas often repeat the same subexpression, like
+//! `(x - mu) / sigma`, many times. A [`Dag`] stores each distinct
+//! subtree once, so that the nodes form a directed acyclic graph
+//! where a shared subtree has several parents, and evaluating the DAG
+//! computes each shared node only once.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::dag::Dag;
+//! # use expr::parse;
+//! # use std::collections::HashMap;
+//! let tree = parse("((x - mu) / sigma) ^ 2 + ((x - mu) / sigma) * 3").unwrap();
+//! let dag = Dag::new(&tree);
+//! assert_eq!(dag.report().deduplicated(), 5);
+//!
+//! let mut vars = HashMap::new();
+//! vars.insert("x".to_string(), 7.0);
+//! vars.insert("mu".to_string(), 5.0);
+//! vars.insert("sigma".to_string(), 2.0);
+//! assert_eq!(dag.eval(&vars), tree.eval(&vars));
+//! ```
+
+use crate::spreadsheet::CellRef;
+use crate::tree::{apply, call, range_values, truth, Context, ExprTree, Radix, Result};
+use std::collections::HashMap;
+use std::mem::{discriminant, Discriminant};
+
+/// Identifier of a node in a DAG.
+pub type NodeId = usize;
+
+/// Node of a DAG.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node<'a> {
+    /// One of the subtrees that the node stands for. Its operands are
+    /// the subtrees of the operand nodes.
+    pub tree: &'a ExprTree,
+    /// Nodes of the operands, in order.
+    pub operands: Vec<NodeId>,
+}
+
+/// Expression tree where identical subtrees are stored once.
+///
+/// The nodes are stored in an order where the operands of a node
+/// come before the node, so the last node is the root.
+#[derive(Debug, Clone)]
+pub struct Dag<'a> {
+    nodes: Vec<Node<'a>>,
+    tree_nodes: usize,
+}
+
+/// What identifies a node, together with its operand nodes.
+///
+/// Numbers are compared like the `Hash` of `ExprTree` hashes them, by
+/// their bits except that `-0` is the same as `0`, so subtrees that
+/// are equal trees share a node. A negative zero literal, which the
+/// parser does not produce since it reads `-0` as a negated `0`, can
+/// therefore evaluate with the sign of a `0` found before it. NaN
+/// literals with the same bits share a node even though they are not
+/// equal, since they evaluate the same.
+#[derive(PartialEq, Eq, Hash)]
+enum Label<'a> {
+    Var(&'a str),
+    Path(&'a [String]),
+    Float(u64),
+    Integer(u64, Radix),
+    Call(&'a str),
+    Cell(&'a Option<String>, CellRef),
+    Range(&'a Option<String>, CellRef, CellRef),
+    Operator(Discriminant<ExprTree>),
+}
+
+impl<'a> Label<'a> {
+    fn new(tree: &'a ExprTree) -> Label<'a> {
+        match tree {
+            ExprTree::Var(name) => Label::Var(name),
+            ExprTree::Path(path) => Label::Path(path),
+            ExprTree::Float(num) if *num == 0.0 => Label::Float(0.0f64.to_bits()),
+            ExprTree::Float(num) => Label::Float(num.to_bits()),
+            ExprTree::Integer(num, radix) => Label::Integer(*num, *radix),
+            ExprTree::Call(name, _) => Label::Call(name),
+            ExprTree::Cell(sheet, cell) => Label::Cell(sheet, *cell),
+            ExprTree::Range(sheet, start, end) => Label::Range(sheet, *start, *end),
+            tree => Label::Operator(discriminant(tree)),
+        }
+    }
+}
+
+/// Number of nodes before and after deduplication.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Report {
+    /// Number of nodes of the tree.
+    pub tree_nodes: usize,
+    /// Number of nodes of the DAG.
+    pub dag_nodes: usize,
+    /// Number of nodes of the DAG with more than one parent.
+    pub shared_nodes: usize,
+}
+
+impl Report {
+    /// Number of tree nodes removed by deduplication.
+    pub fn deduplicated(&self) -> usize {
+        self.tree_nodes - self.dag_nodes
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} after deduplication ({} removed, {} shared)",
+            self.tree_nodes,
+            self.dag_nodes,
+            self.deduplicated(),
+            self.shared_nodes
+        )
+    }
+}
+
+impl<'a> Dag<'a> {
+    /// Build a DAG from a tree, storing identical subtrees once.
+    pub fn new(tree: &'a ExprTree) -> Dag<'a> {
+        let mut dag = Dag {
+            nodes: Vec::new(),
+            tree_nodes: 0,
+        };
+        let mut index = HashMap::new();
+        dag.intern(tree, &mut index);
+        dag
+    }
+
+    /// Add the nodes of a tree that are not already in the DAG,
+    /// giving the node of the tree.
+    fn intern(
+        &mut self,
+        tree: &'a ExprTree,
+        index: &mut HashMap<(Label<'a>, Vec<NodeId>), NodeId>,
+    ) -> NodeId {
+        self.tree_nodes += 1;
+        let operands: Vec<NodeId> = tree
+            .operands()
+            .map(|operand| self.intern(operand, index))
+            .collect();
+        let nodes = &mut self.nodes;
+        *index
+            .entry((Label::new(tree), operands.clone()))
+            .or_insert_with(|| {
+                nodes.push(Node { tree, operands });
+                nodes.len() - 1
+            })
+    }
+
+    /// Nodes of the DAG, where the operands of a node come before the
+    /// node.
+    pub fn nodes(&self) -> &[Node<'a>] {
+        &self.nodes
+    }
+
+    /// Node of the whole tree.
+    pub fn root(&self) -> NodeId {
+        self.nodes.len() - 1
+    }
+
+    /// Get the number of nodes before and after deduplication.
+    pub fn report(&self) -> Report {
+        let mut parents = vec![0; self.nodes.len()];
+        for node in &self.nodes {
+            for &operand in &node.operands {
+                parents[operand] += 1;
+            }
+        }
+        Report {
+            tree_nodes: self.tree_nodes,
+            dag_nodes: self.nodes.len(),
+            shared_nodes: parents.into_iter().filter(|&count| count > 1).count(),
+        }
+    }
+
+    /// Evaluate the DAG like [`ExprTree::eval`], computing the value of
+    /// each node at most once.
+    pub fn eval<C: Context + ?Sized>(&self, ctx: &C) -> Result<f64> {
+        let mut values = vec![None; self.nodes.len()];
+        self.value(ctx, self.root(), &mut values)
+    }
+
+    /// Get the value of a node, computing it if it is not already
+    /// computed. Only the nodes needed are computed, so the logical
+    /// operators still only evaluate the right operand when needed.
+    fn value<C: Context + ?Sized>(
+        &self,
+        ctx: &C,
+        id: NodeId,
+        values: &mut Vec<Option<f64>>,
+    ) -> Result<f64> {
+        if let Some(value) = values[id] {
+            return Ok(value);
+        }
+        let node = &self.nodes[id];
+        let value = match node.tree {
+            ExprTree::And(..) => {
+                let lhs = self.value(ctx, node.operands[0], values)?;
+                truth(lhs != 0.0 && self.value(ctx, node.operands[1], values)? != 0.0)
+            }
+            ExprTree::Or(..) => {
+                let lhs = self.value(ctx, node.operands[0], values)?;
+                truth(lhs != 0.0 || self.value(ctx, node.operands[1], values)? != 0.0)
+            }
+            ExprTree::Call(name, _) => {
+                let mut args = Vec::new();
+                for &operand in &node.operands {
+                    match self.nodes[operand].tree {
+                        ExprTree::Range(sheet, start, end) => {
+                            args.extend(range_values(ctx, sheet, start, end)?)
+                        }
+                        _ => args.push(self.value(ctx, operand, values)?),
+                    }
+                }
+                call(ctx, name, &args)?
+            }
+            tree => {
+                let mut args = Vec::with_capacity(node.operands.len());
+                for &operand in &node.operands {
+                    args.push(self.value(ctx, operand, values)?);
+                }
+                apply(ctx, tree, &args)?
+            }
+        };
+        values[id] = Some(value);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tree::Entry;
+    use std::cell::Cell;
+
+    /// Context counting the calls of the function `f`.
+    struct Counter {
+        calls: Cell<usize>,
+    }
+
+    impl Context for Counter {
+        fn lookup(&self, name: &str) -> Option<Entry<'_>> {
+            match name {
+                "x" => Some(Entry::Number(3.0)),
+                _ => None,
+            }
+        }
+
+        fn call(&self, name: &str, args: &[f64]) -> Option<Result<f64>> {
+            match name {
+                "f" => {
+                    self.calls.set(self.calls.get() + 1);
+                    Some(Ok(args.iter().sum()))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn sharing() {
+        let tree = parse("(x - 1) * (x - 1) + (x - 1) / 2").unwrap();
+        let dag = Dag::new(&tree);
+        let report = dag.report();
+        assert_eq!(report.tree_nodes, 13);
+        assert_eq!(report.dag_nodes, 7);
+        assert_eq!(report.deduplicated(), 6);
+        assert_eq!(report.shared_nodes, 1);
+        assert_eq!(
+            report.to_string(),
+            "13 nodes, 7 after deduplication (6 removed, 1 shared)"
+        );
+        assert_eq!(dag.nodes()[dag.root()].tree, &tree);
+        for (id, node) in dag.nodes().iter().enumerate() {
+            assert!(node.operands.iter().all(|&operand| operand < id));
+        }
+
+        // Numbers are only shared if they are written the same way.
+        let tree = parse("0x10 + 16 + 16.0").unwrap();
+        assert_eq!(Dag::new(&tree).report().dag_nodes, 4);
+    }
+
+    #[test]
+    fn evaluation() {
+        let ctx = Counter {
+            calls: Cell::new(0),
+        };
+        let tree = parse("f(x, 1) * f(x, 1) + f(x, 1) - f(1, x)").unwrap();
+        assert_eq!(tree.eval(&ctx), Ok(16.0));
+        assert_eq!(ctx.calls.get(), 4);
+
+        ctx.calls.set(0);
+        assert_eq!(Dag::new(&tree).eval(&ctx), Ok(16.0));
+        assert_eq!(ctx.calls.get(), 2);
+
+        let tree = parse("x < 0 and f(y) or not (x < 0)").unwrap();
+        assert_eq!(Dag::new(&tree).eval(&ctx), Ok(1.0));
+        let tree = parse("f(y) + 1").unwrap();
+        assert_eq!(Dag::new(&tree).eval(&ctx), tree.eval(&ctx));
+    }
+
+    #[test]
+    fn hashing() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let hash = |text: &str| {
+            let mut hasher = DefaultHasher::new();
+            parse(text).unwrap().hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash("(a + b) * f(c)"), hash("(a+b)*f(c)"));
+        assert_eq!(hash("0 * x"), hash("0.0 * x"));
+        assert_eq!(
+            {
+                let mut hasher = DefaultHasher::new();
+                ExprTree::Float(-0.0).hash(&mut hasher);
+                hasher.finish()
+            },
+            hash("0")
+        );
+        assert_ne!(hash("a + b"), hash("b + a"));
+        assert_ne!(hash("f(a, b)"), hash("f(a)"));
+
+        let zeros = ExprTree::Add(
+            Box::new(ExprTree::Float(0.0)),
+            Box::new(ExprTree::Float(-0.0)),
+        );
+        assert_eq!(Dag::new(&zeros).report().deduplicated(), 1);
+    }
+}
@@ -0,0 +1,592 @@
+//! Dialects of the expression language.
+//!
+//! A dialect gives both the grammar used to parse expressions and the
+//! notation used to print them, so a formula can be translated from
+//! one dialect to another by parsing it using one dialect and
+//! printing it using another.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::dialect::Dialect;
+//! # use expr::parser::parse_with;
+//! let tree = parse_with("AND(a <> 1; b ^ 2 > 4)", &Dialect::Spreadsheet).unwrap();
+//! assert_eq!(tree.display(Dialect::CLike).to_string(), "a != 1 && b ** 2 > 4");
+//! assert_eq!(tree.display(Dialect::Math).to_string(), "a != 1 and b ^ 2 > 4");
+//! ```
+
+use crate::flat::Kind;
+use crate::parser::{default_config, Assoc, Binary, Builtin, Grammar, ParserConfig, Unary};
+use crate::spreadsheet::{CellRef, SheetName};
+use crate::tokens::{Identifier, Token};
+use crate::tree::{ExprTree, Operands};
+use crate::visit::{fold_operands, Fold, Folded};
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+/// Dialect of the expression language.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Dialect {
+    /// Mathematical notation, which is the default grammar of the
+    /// parser. It uses `^` for power, `==` or `=` for equality, and
+    /// the words `and`, `or`, and `not` for the logical operators.
+    Math,
+    /// Notation of C-like programming languages. It uses `**` for
+    /// power, `^` for bitwise xor, `==` for equality, `&&`, `||`, and
+    /// `!` for the logical operators, and `%` for modulo. The
+    /// operators have the same precedence as in C, so comparisons
+    /// bind tighter than the bitwise operators.
+    CLike,
+    /// Notation of spreadsheet formulas. It uses `^` for power, `=`
+    /// and `<>` for equality and inequality, and functions like
+    /// `AND`, `OR`, and `MOD` for the operators that spreadsheets do
+    /// not have. Arguments are separated by `;`. As in spreadsheets,
+    /// negation binds tighter than power, so `-2^2` is 4. Formulas
+    /// can start with `=` and refer to cells and ranges, like
+    /// `=SUM(A1:A10)`.
+    Spreadsheet,
+}
+
+impl Dialect {
+    /// Get the parser configuration for the dialect.
+    pub fn config(self) -> ParserConfig {
+        match self {
+            Dialect::Math => ParserConfig::new(),
+            Dialect::CLike => ParserConfig::empty()
+                .with_infix("||", 50, Assoc::Left, Binary::Node(Kind::Or))
+                .with_infix("&&", 60, Assoc::Left, Binary::Node(Kind::And))
+                .with_infix("|", 70, Assoc::Left, Binary::Node(Kind::BitOr))
+                .with_infix("^", 80, Assoc::Left, Binary::Node(Kind::BitXor))
+                .with_infix("&", 90, Assoc::Left, Binary::Node(Kind::BitAnd))
+                .with_infix("==", 100, Assoc::Left, Binary::Node(Kind::Eq))
+                .with_infix("!=", 100, Assoc::Left, Binary::Node(Kind::Ne))
+                .with_infix("<", 110, Assoc::Left, Binary::Node(Kind::Lt))
+                .with_infix("<=", 110, Assoc::Left, Binary::Node(Kind::Le))
+                .with_infix(">", 110, Assoc::Left, Binary::Node(Kind::Gt))
+                .with_infix(">=", 110, Assoc::Left, Binary::Node(Kind::Ge))
+                .with_infix("<<", 120, Assoc::Left, Binary::Node(Kind::Shl))
+                .with_infix(">>", 120, Assoc::Left, Binary::Node(Kind::Shr))
+                .with_infix("+", 130, Assoc::Left, Binary::Node(Kind::Add))
+                .with_infix("-", 130, Assoc::Left, Binary::Node(Kind::Sub))
+                .with_infix("*", 140, Assoc::Left, Binary::Node(Kind::Mul))
+                .with_infix("/", 140, Assoc::Left, Binary::Node(Kind::Div))
+                .with_infix("%", 140, Assoc::Left, Binary::Node(Kind::Mod))
+                .with_prefix("-", 150, Unary::Node(Kind::Neg))
+                .with_prefix("+", 150, Unary::Operand)
+                .with_prefix("!", 150, Unary::Node(Kind::Not))
+                .with_prefix("~", 150, Unary::Node(Kind::BitNot))
+                .with_infix("**", 160, Assoc::Right, Binary::Node(Kind::Pow))
+                .with_builtin("abs", Builtin::Unary(Kind::Abs))
+                .with_builtin("sqrt", Builtin::Unary(Kind::Sqrt))
+                .with_builtin("fact", Builtin::Unary(Kind::Factorial))
+                .with_builtin("percent", Builtin::Unary(Kind::Percent))
+                .with_builtin("idiv", Builtin::Binary(Kind::IntDiv)),
+            Dialect::Spreadsheet => ParserConfig::empty()
+                .with_spreadsheet(true)
+                .with_separator(";")
+                .with_infix("=", 10, Assoc::Left, Binary::Node(Kind::Eq))
+                .with_infix("<>", 10, Assoc::Left, Binary::Node(Kind::Ne))
+                .with_infix("<", 10, Assoc::Left, Binary::Node(Kind::Lt))
+                .with_infix("<=", 10, Assoc::Left, Binary::Node(Kind::Le))
+                .with_infix(">", 10, Assoc::Left, Binary::Node(Kind::Gt))
+                .with_infix(">=", 10, Assoc::Left, Binary::Node(Kind::Ge))
+                .with_infix("+", 30, Assoc::Left, Binary::Node(Kind::Add))
+                .with_infix("-", 30, Assoc::Left, Binary::Node(Kind::Sub))
+                .with_infix("*", 40, Assoc::Left, Binary::Node(Kind::Mul))
+                .with_infix("/", 40, Assoc::Left, Binary::Node(Kind::Div))
+                .with_infix("^", 50, Assoc::Left, Binary::Node(Kind::Pow))
+                .with_postfix("%", 60, Unary::Node(Kind::Percent))
+                .with_prefix("-", 70, Unary::Node(Kind::Neg))
+                .with_prefix("+", 70, Unary::Operand)
+                .with_builtin("AND", Builtin::Binary(Kind::And))
+                .with_builtin("OR", Builtin::Binary(Kind::Or))
+                .with_builtin("NOT", Builtin::Unary(Kind::Not))
+                .with_builtin("ABS", Builtin::Unary(Kind::Abs))
+                .with_builtin("SQRT", Builtin::Unary(Kind::Sqrt))
+                .with_builtin("FACT", Builtin::Unary(Kind::Factorial))
+                .with_builtin("POWER", Builtin::Binary(Kind::Pow))
+                .with_builtin("MOD", Builtin::Binary(Kind::Mod))
+                .with_builtin("QUOTIENT", Builtin::Binary(Kind::IntDiv))
+                .with_builtin("BITAND", Builtin::Binary(Kind::BitAnd))
+                .with_builtin("BITOR", Builtin::Binary(Kind::BitOr))
+                .with_builtin("BITXOR", Builtin::Binary(Kind::BitXor))
+                .with_builtin("BITLSHIFT", Builtin::Binary(Kind::Shl))
+                .with_builtin("BITRSHIFT", Builtin::Binary(Kind::Shr))
+                .with_builtin("BITNOT", Builtin::Unary(Kind::BitNot)),
+        }
+    }
+
+    /// Get the notation of an expression tree node.
+    fn notation(self, tree: &ExprTree) -> Notation {
+        use self::Notation::*;
+        use crate::parser::Assoc::*;
+
+        match (self, tree) {
+            (_, ExprTree::Var(..))
+            | (_, ExprTree::Path(..))
+            | (_, ExprTree::Float(..))
+            | (_, ExprTree::Integer(..))
+            | (_, ExprTree::Call(..))
+            | (_, ExprTree::Cell(..))
+            | (_, ExprTree::Range(..)) => Atom,
+
+            (Dialect::Math, ExprTree::Or(..)) => Infix("or", 4, Left),
+            (Dialect::Math, ExprTree::And(..)) => Infix("and", 6, Left),
+            (Dialect::Math, ExprTree::Not(..)) => Prefix("not", 8),
+            (Dialect::Math, ExprTree::Eq(..)) => Infix("==", 10, NonAssoc),
+            (Dialect::Math, ExprTree::Ne(..)) => Infix("!=", 10, NonAssoc),
+            (Dialect::Math, ExprTree::Lt(..)) => Infix("<", 10, NonAssoc),
+            (Dialect::Math, ExprTree::Le(..)) => Infix("<=", 10, NonAssoc),
+            (Dialect::Math, ExprTree::Gt(..)) => Infix(">", 10, NonAssoc),
+            (Dialect::Math, ExprTree::Ge(..)) => Infix(">=", 10, NonAssoc),
+            (Dialect::Math, ExprTree::BitOr(..)) => Infix("|", 20, Left),
+            (Dialect::Math, ExprTree::BitXor(..)) => Infix("xor", 30, Left),
+            (Dialect::Math, ExprTree::BitAnd(..)) => Infix("&", 40, Left),
+            (Dialect::Math, ExprTree::Shl(..)) => Infix("<<", 50, Left),
+            (Dialect::Math, ExprTree::Shr(..)) => Infix(">>", 50, Left),
+            (Dialect::Math, ExprTree::Add(..)) => Infix("+", 60, Left),
+            (Dialect::Math, ExprTree::Sub(..)) => Infix("-", 60, Left),
+            (Dialect::Math, ExprTree::Mul(..)) => Infix("*", 70, Left),
+            (Dialect::Math, ExprTree::Div(..)) => Infix("/", 70, Left),
+            (Dialect::Math, ExprTree::Mod(..)) => Infix("mod", 70, Left),
+            (Dialect::Math, ExprTree::IntDiv(..)) => Infix("div", 70, Left),
+            (Dialect::Math, ExprTree::Neg(..)) => Prefix("-", 80),
+            (Dialect::Math, ExprTree::BitNot(..)) => Prefix("~", 80),
+            (Dialect::Math, ExprTree::Sqrt(..)) => Prefix("√", 80),
+            (Dialect::Math, ExprTree::Pow(..)) => Infix("^", 90, Right),
+            (Dialect::Math, ExprTree::Factorial(..)) => Postfix("!", 100),
+            (Dialect::Math, ExprTree::Percent(..)) => Postfix("%", 100),
+            // Written as a call, which parses back whether or not bars
+            // are enabled.
+            (Dialect::Math, ExprTree::Abs(..)) => Function("abs"),
+
+            (Dialect::CLike, ExprTree::Or(..)) => Infix("||", 50, Left),
+            (Dialect::CLike, ExprTree::And(..)) => Infix("&&", 60, Left),
+            (Dialect::CLike, ExprTree::BitOr(..)) => Infix("|", 70, Left),
+            (Dialect::CLike, ExprTree::BitXor(..)) => Infix("^", 80, Left),
+            (Dialect::CLike, ExprTree::BitAnd(..)) => Infix("&", 90, Left),
+            (Dialect::CLike, ExprTree::Eq(..)) => Infix("==", 100, Left),
+            (Dialect::CLike, ExprTree::Ne(..)) => Infix("!=", 100, Left),
+            (Dialect::CLike, ExprTree::Lt(..)) => Infix("<", 110, Left),
+            (Dialect::CLike, ExprTree::Le(..)) => Infix("<=", 110, Left),
+            (Dialect::CLike, ExprTree::Gt(..)) => Infix(">", 110, Left),
+            (Dialect::CLike, ExprTree::Ge(..)) => Infix(">=", 110, Left),
+            (Dialect::CLike, ExprTree::Shl(..)) => Infix("<<", 120, Left),
+            (Dialect::CLike, ExprTree::Shr(..)) => Infix(">>", 120, Left),
+            (Dialect::CLike, ExprTree::Add(..)) => Infix("+", 130, Left),
+            (Dialect::CLike, ExprTree::Sub(..)) => Infix("-", 130, Left),
+            (Dialect::CLike, ExprTree::Mul(..)) => Infix("*", 140, Left),
+            (Dialect::CLike, ExprTree::Div(..)) => Infix("/", 140, Left),
+            (Dialect::CLike, ExprTree::Mod(..)) => Infix("%", 140, Left),
+            (Dialect::CLike, ExprTree::Neg(..)) => Prefix("-", 150),
+            (Dialect::CLike, ExprTree::Not(..)) => Prefix("!", 150),
+            (Dialect::CLike, ExprTree::BitNot(..)) => Prefix("~", 150),
+            (Dialect::CLike, ExprTree::Pow(..)) => Infix("**", 160, Right),
+            (Dialect::CLike, ExprTree::Abs(..)) => Function("abs"),
+            (Dialect::CLike, ExprTree::Sqrt(..)) => Function("sqrt"),
+            (Dialect::CLike, ExprTree::Factorial(..)) => Function("fact"),
+            (Dialect::CLike, ExprTree::Percent(..)) => Function("percent"),
+            (Dialect::CLike, ExprTree::IntDiv(..)) => Function("idiv"),
+
+            (Dialect::Spreadsheet, ExprTree::Eq(..)) => Infix("=", 10, Left),
+            (Dialect::Spreadsheet, ExprTree::Ne(..)) => Infix("<>", 10, Left),
+            (Dialect::Spreadsheet, ExprTree::Lt(..)) => Infix("<", 10, Left),
+            (Dialect::Spreadsheet, ExprTree::Le(..)) => Infix("<=", 10, Left),
+            (Dialect::Spreadsheet, ExprTree::Gt(..)) => Infix(">", 10, Left),
+            (Dialect::Spreadsheet, ExprTree::Ge(..)) => Infix(">=", 10, Left),
+            (Dialect::Spreadsheet, ExprTree::Add(..)) => Infix("+", 30, Left),
+            (Dialect::Spreadsheet, ExprTree::Sub(..)) => Infix("-", 30, Left),
+            (Dialect::Spreadsheet, ExprTree::Mul(..)) => Infix("*", 40, Left),
+            (Dialect::Spreadsheet, ExprTree::Div(..)) => Infix("/", 40, Left),
+            (Dialect::Spreadsheet, ExprTree::Pow(..)) => Infix("^", 50, Left),
+            (Dialect::Spreadsheet, ExprTree::Percent(..)) => Postfix("%", 60),
+            (Dialect::Spreadsheet, ExprTree::Neg(..)) => Prefix("-", 70),
+            (Dialect::Spreadsheet, ExprTree::And(..)) => Function("AND"),
+            (Dialect::Spreadsheet, ExprTree::Or(..)) => Function("OR"),
+            (Dialect::Spreadsheet, ExprTree::Not(..)) => Function("NOT"),
+            (Dialect::Spreadsheet, ExprTree::Abs(..)) => Function("ABS"),
+            (Dialect::Spreadsheet, ExprTree::Sqrt(..)) => Function("SQRT"),
+            (Dialect::Spreadsheet, ExprTree::Factorial(..)) => Function("FACT"),
+            (Dialect::Spreadsheet, ExprTree::Mod(..)) => Function("MOD"),
+            (Dialect::Spreadsheet, ExprTree::IntDiv(..)) => Function("QUOTIENT"),
+            (Dialect::Spreadsheet, ExprTree::BitAnd(..)) => Function("BITAND"),
+            (Dialect::Spreadsheet, ExprTree::BitOr(..)) => Function("BITOR"),
+            (Dialect::Spreadsheet, ExprTree::BitXor(..)) => Function("BITXOR"),
+            (Dialect::Spreadsheet, ExprTree::Shl(..)) => Function("BITLSHIFT"),
+            (Dialect::Spreadsheet, ExprTree::Shr(..)) => Function("BITRSHIFT"),
+            (Dialect::Spreadsheet, ExprTree::BitNot(..)) => Function("BITNOT"),
+        }
+    }
+
+    /// Binding power of an expression tree node, where atoms bind
+    /// tightest.
+    fn power(self, tree: &ExprTree) -> u32 {
+        match self.notation(tree) {
+            Notation::Prefix(_, power)
+            | Notation::Postfix(_, power)
+            | Notation::Infix(_, power, _) => power,
+            Notation::Atom | Notation::Function(_) => u32::MAX,
+        }
+    }
+
+    /// Check if the dialect reads cell references, in which case names
+    /// like `A1` have to be quoted.
+    fn cells(self) -> bool {
+        self == Dialect::Spreadsheet
+    }
+
+    /// Separator between function arguments.
+    fn separator(self) -> &'static str {
+        match self {
+            Dialect::Math | Dialect::CLike => ", ",
+            Dialect::Spreadsheet => "; ",
+        }
+    }
+}
+
+impl Grammar for Dialect {
+    /// Get the configuration of the dialect, which is built once for
+    /// each dialect.
+    fn config(&self) -> Cow<'_, ParserConfig> {
+        static CLIKE: OnceLock<ParserConfig> = OnceLock::new();
+        static SPREADSHEET: OnceLock<ParserConfig> = OnceLock::new();
+        let config = match self {
+            Dialect::Math => default_config(),
+            Dialect::CLike => CLIKE.get_or_init(|| Dialect::CLike.config()),
+            Dialect::Spreadsheet => SPREADSHEET.get_or_init(|| Dialect::Spreadsheet.config()),
+        };
+        Cow::Borrowed(config)
+    }
+}
+
+/// How an expression tree node is written in a dialect.
+enum Notation {
+    Atom,
+    Prefix(&'static str, u32),
+    Postfix(&'static str, u32),
+    Infix(&'static str, u32, Assoc),
+    Function(&'static str),
+}
+
+/// Display wrapper writing an expression tree in a dialect.
+pub struct DialectDisplay<'a> {
+    tree: &'a ExprTree,
+    dialect: Dialect,
+}
+
+impl ExprTree {
+    /// Get a wrapper for displaying the expression using a dialect,
+    /// adding only the parentheses needed to parse back into the
+    /// same tree using that dialect.
+    pub fn display(&self, dialect: Dialect) -> DialectDisplay<'_> {
+        DialectDisplay {
+            tree: self,
+            dialect,
+        }
+    }
+}
+
+impl std::fmt::Display for DialectDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Printer {
+            f,
+            dialect: self.dialect,
+        }
+        .fold(self.tree)
+    }
+}
+
+/// Fold writing a tree to a formatter.
+///
+/// Operators are written between and around their operands, so the
+/// printer decides when the operands are folded, and only atoms are
+/// written by `fold_node`.
+struct Printer<'a, 'b> {
+    f: &'a mut std::fmt::Formatter<'b>,
+    dialect: Dialect,
+}
+
+impl Printer<'_, '_> {
+    fn operand(&mut self, expr: &ExprTree, paren: bool) -> std::fmt::Result {
+        if paren {
+            write!(self.f, "(")?;
+            self.fold(expr)?;
+            write!(self.f, ")")
+        } else {
+            self.fold(expr)
+        }
+    }
+
+    /// Write a name so that it is read back as the same name, quoting
+    /// names that would be read as cells, and function names that
+    /// would be read in upper case.
+    fn name(&mut self, name: &str, call: bool) -> std::fmt::Result {
+        let quote = self.dialect.cells()
+            && (CellRef::read(name).is_some() || call && name.to_uppercase() != name);
+        if quote {
+            write!(self.f, "{}", Token::Quoted(name.into()))
+        } else {
+            write!(self.f, "{}", Identifier(name))
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Operands<'_>) -> std::fmt::Result {
+        self.name(name, true)?;
+        write!(self.f, "(")?;
+        for (index, arg) in args.enumerate() {
+            if index > 0 {
+                write!(self.f, "{}", self.dialect.separator())?;
+            }
+            self.fold(arg)?;
+        }
+        write!(self.f, ")")
+    }
+}
+
+impl Fold for Printer<'_, '_> {
+    type Output = std::fmt::Result;
+
+    fn fold(&mut self, tree: &ExprTree) -> std::fmt::Result {
+        let dialect = self.dialect;
+        let mut operands = tree.operands();
+        let mut operand = || operands.next().expect("missing operand");
+        match dialect.notation(tree) {
+            Notation::Atom => match tree {
+                ExprTree::Call(name, _) => self.call(name, tree.operands()),
+                tree => fold_operands(self, tree),
+            },
+            Notation::Prefix(op, power) => {
+                let expr = operand();
+                write!(self.f, "{}", op)?;
+                if op.ends_with(char::is_alphabetic) {
+                    write!(self.f, " ")?;
+                }
+                // The operand cannot start with a prefix operator
+                // binding equally strong, so `-(-x)` keeps them.
+                self.operand(expr, dialect.power(expr) <= power)
+            }
+            Notation::Postfix(op, power) => {
+                let expr = operand();
+                self.operand(expr, dialect.power(expr) < power)?;
+                write!(self.f, "{}", op)
+            }
+            Notation::Infix(op, power, assoc) => {
+                let (lhs, rhs) = (operand(), operand());
+                let (left, right) = match assoc {
+                    Assoc::Left => (power, power + 1),
+                    Assoc::Right => (power + 1, power),
+                    Assoc::NonAssoc => (power + 1, power + 1),
+                };
+                // The right operand of a right-associative operator
+                // can have a prefix operator, as in `2 ^ -x`.
+                let prefix = match dialect.notation(rhs) {
+                    Notation::Prefix(..) => assoc == Assoc::Right,
+                    _ => false,
+                };
+                self.operand(lhs, dialect.power(lhs) < left)?;
+                write!(self.f, " {} ", op)?;
+                self.operand(rhs, dialect.power(rhs) < right && !prefix)
+            }
+            Notation::Function(name) => self.call(name, tree.operands()),
+        }
+    }
+
+    fn fold_node(
+        &mut self,
+        tree: &ExprTree,
+        _operands: Folded<std::fmt::Result>,
+    ) -> std::fmt::Result {
+        // Dialects without cell references write them in brackets,
+        // which they do not read, rather than as names.
+        let (open, close) = if self.dialect.cells() {
+            ("", "")
+        } else {
+            ("[", "]")
+        };
+        match tree {
+            ExprTree::Var(name) => self.name(name, false),
+            ExprTree::Path(path) => {
+                for (index, member) in path.iter().enumerate() {
+                    if index > 0 {
+                        write!(self.f, ".")?;
+                    }
+                    self.name(member, false)?;
+                }
+                Ok(())
+            }
+            ExprTree::Float(num) => write!(self.f, "{}", num),
+            ExprTree::Integer(num, radix) => {
+                write!(self.f, "0{}{}", radix.letter(), radix.digits(*num))
+            }
+            ExprTree::Cell(sheet, cell) => {
+                write!(self.f, "{}", open)?;
+                if let Some(sheet) = sheet {
+                    write!(self.f, "{}", SheetName(sheet))?;
+                }
+                write!(self.f, "{}{}", cell, close)
+            }
+            ExprTree::Range(sheet, start, end) => {
+                write!(self.f, "{}", open)?;
+                if let Some(sheet) = sheet {
+                    write!(self.f, "{}", SheetName(sheet))?;
+                }
+                write!(self.f, "{}:{}{}", start, end, close)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dialect;
+    use crate::parser::{parse, parse_with};
+    use crate::spreadsheet::CellRef;
+    use crate::tree::ExprTree;
+
+    /// Parse the text in one dialect and check how it is printed in
+    /// each dialect, and that the printed text parses back into the
+    /// same tree.
+    fn check(from: Dialect, text: &str, math: &str, c: &str, sheet: &str) {
+        let tree = parse_with(text, &from).unwrap();
+        for &(dialect, expected) in &[
+            (Dialect::Math, math),
+            (Dialect::CLike, c),
+            (Dialect::Spreadsheet, sheet),
+        ] {
+            let printed = tree.display(dialect).to_string();
+            assert_eq!(printed, expected);
+            assert_eq!(parse_with(&printed, &dialect), Ok(tree.clone()));
+        }
+    }
+
+    #[test]
+    fn translate() {
+        check(
+            Dialect::Math,
+            "a ^ 2 + b",
+            "a ^ 2 + b",
+            "a ** 2 + b",
+            "a ^ 2 + b",
+        );
+        check(Dialect::Math, "-x ^ 2", "-x ^ 2", "-x ** 2", "-(x ^ 2)");
+        check(
+            Dialect::Spreadsheet,
+            "-x^2",
+            "(-x) ^ 2",
+            "(-x) ** 2",
+            "-x ^ 2",
+        );
+        check(
+            Dialect::CLike,
+            "a ^ b | c & d",
+            "a xor b | c & d",
+            "a ^ b | c & d",
+            "BITOR(BITXOR(a; b); BITAND(c; d))",
+        );
+        check(
+            Dialect::CLike,
+            "a == 1 || !b && c < d",
+            "a == 1 or not b and c < d",
+            "a == 1 || !b && c < d",
+            "OR(a = 1; AND(NOT(b); c < d))",
+        );
+        check(
+            Dialect::CLike,
+            "(a & 1) == 0",
+            "a & 1 == 0",
+            "(a & 1) == 0",
+            "BITAND(a; 1) = 0",
+        );
+        check(
+            Dialect::Spreadsheet,
+            "MOD(a; 3) * QUOTIENT(b; 2) <> 0",
+            "a mod 3 * (b div 2) != 0",
+            "a % 3 * idiv(b, 2) != 0",
+            "MOD(a; 3) * QUOTIENT(b; 2) <> 0",
+        );
+        check(
+            Dialect::Math,
+            "not (a and b) or n! > 2",
+            "not (a and b) or n! > 2",
+            "!(a && b) || fact(n) > 2",
+            "OR(NOT(AND(a; b)); FACT(n) > 2)",
+        );
+        check(
+            Dialect::Math,
+            "MAX(a, b%)",
+            "MAX(a, b%)",
+            "MAX(a, percent(b))",
+            "MAX(a; b%)",
+        );
+        check(
+            Dialect::CLike,
+            "0b101 | 0o17 ^ 0xFF",
+            "0b101 | 0o17 xor 0xff",
+            "0b101 | 0o17 ^ 0xff",
+            "BITOR(0b101; BITXOR(0o17; 0xff))",
+        );
+    }
+
+    #[test]
+    fn separators() {
+        let tree = parse_with("max(a; b)", &Dialect::Spreadsheet).unwrap();
+        assert_eq!(tree.to_string(), "MAX(a, b)");
+        assert!(parse_with("max(a, b)", &Dialect::Spreadsheet).is_err());
+        assert!(parse_with("max(a; b)", &Dialect::Math).is_err());
+        assert!(parse_with("a = b", &Dialect::CLike).is_err());
+        assert!(parse_with("a && b", &Dialect::Math).is_err());
+    }
+
+    #[test]
+    fn logic_words() {
+        let tree = parse_with("and || not", &Dialect::CLike).unwrap();
+        assert_eq!(tree, parse("`and` or `not`").unwrap());
+        let tree = parse_with("OR(or; 1)", &Dialect::Spreadsheet).unwrap();
+        assert_eq!(tree, parse("`or` or 1").unwrap());
+        assert!(parse_with("a and b", &Dialect::CLike).is_err());
+    }
+
+    #[test]
+    fn integer_words() {
+        let tree = parse_with("mod % div ^ xor", &Dialect::CLike).unwrap();
+        assert_eq!(tree, parse("`mod` mod `div` xor `xor`").unwrap());
+        let tree = parse_with("MOD(mod; div)", &Dialect::Spreadsheet).unwrap();
+        assert_eq!(tree, parse("`mod` mod `div`").unwrap());
+        assert!(parse_with("a mod b", &Dialect::CLike).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let var = |name: &str| ExprTree::Var(name.to_string());
+        let tree = ExprTree::Add(
+            Box::new(ExprTree::Call(
+                "max".to_string(),
+                vec![var("A1"), var("x1"), var("XFD1048576")],
+            )),
+            Box::new(ExprTree::Mul(
+                Box::new(ExprTree::Path(vec!["a".to_string(), "B2".to_string()])),
+                Box::new(ExprTree::Call("AB1".to_string(), vec![var("ABCD1")])),
+            )),
+        );
+        for &dialect in &[Dialect::Math, Dialect::CLike, Dialect::Spreadsheet] {
+            let printed = tree.display(dialect).to_string();
+            assert_eq!(
+                parse_with(&printed, &dialect),
+                Ok(tree.clone()),
+                "{}",
+                printed
+            );
+        }
+        assert_eq!(
+            tree.display(Dialect::Spreadsheet).to_string(),
+            "`max`(`A1`; `x1`; `XFD1048576`) + a.`B2` * `AB1`(ABCD1)"
+        );
+
+        let cell = ExprTree::Cell(None, CellRef::new(1, 1));
+        assert_eq!(cell.display(Dialect::Spreadsheet).to_string(), "A1");
+        assert_eq!(cell.to_string(), "[A1]");
+        assert!(parse(&cell.to_string()).is_err());
+        assert!(parse_with(&cell.display(Dialect::CLike).to_string(), &Dialect::CLike).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Versioned serialization of expression trees.
+//!
+//! With the `serde` feature enabled, [`ExprTree`], [`Token`], and the
+//! error types implement `Serialize` and `Deserialize`. To store a
+//! tree, wrap it in a [`Document`], which adds the version of the
+//! format so that a reader can reject trees using node kinds that it
+//! does not know instead of misreading them.
+//!
+//! # Format
+//!
+//! A document is an object with the fields `version` and `expr`,
+//! written in that order. Every node of the tree is an object where
+//! `node` is the kind of node and `args` holds its contents:
+//!
+//! | Node | `node` | `args` |
+//! |------|--------|--------|
+//! | variable | `"var"` | name |
+//! | path | `"path"` | array of names |
+//! | number | `"float"` | number |
+//! | integer | `"integer"` | array of the number and the radix, `"binary"`, `"octal"`, or `"hexadecimal"` |
+//! | operator | `"neg"`, `"add"`, `"bit_and"`, `"int_div"`, ... | operand, or array of two operands |
+//! | call | `"call"` | array of the name and an array of arguments |
+//! | cell | `"cell"` | array of the sheet name, or `null`, and the cell |
+//! | range | `"range"` | array of the sheet name, or `null`, and the first and last cell |
+//!
+//! The kinds of operators are the names of the [`ExprTree`] variants
+//! in snake case, and a cell is an object with the fields `column`,
+//! `row`, `absolute_column`, and `absolute_row`. Numbers that are not
+//! finite, which JSON cannot represent, are written as the strings
+//! `"inf"`, `"-inf"`, and `"nan"`.
+//!
+//! Tokens are written the same way using the fields `token` and
+//! `value`, and errors use the default representation of `serde`,
+//! like `{"NoValue": {"path": "x", "missing": "x"}}`.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::document::Document;
+//! # use expr::parse;
+//! let document = Document::new(parse("-x + 1").unwrap());
+//! let json = serde_json::to_string(&document).unwrap();
+//! assert_eq!(
+//!     json,
+//!     concat!(
+//!         r#"{"version":1,"expr":{"node":"add","args":["#,
+//!         r#"{"node":"neg","args":{"node":"var","args":"x"}},"#,
+//!         r#"{"node":"float","args":1.0}]}}"#
+//!     )
+//! );
+//! assert_eq!(serde_json::from_str::<Document>(&json).unwrap(), document);
+//! ```
+//!
+//! [`Token`]: crate::tokens::Token
+
+use crate::tree::ExprTree;
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Serialize;
+
+/// Version of the format written by this version of the library.
+///
+/// The version is increased when node kinds are added, and documents
+/// with a newer version are rejected when read.
+pub const VERSION: u32 = 1;
+
+/// Expression tree together with the version of the format.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Document {
+    pub version: u32,
+    pub expr: ExprTree,
+}
+
+impl Document {
+    /// Create a document using the current version of the format.
+    pub fn new(expr: ExprTree) -> Document {
+        Document {
+            version: VERSION,
+            expr,
+        }
+    }
+}
+
+impl From<ExprTree> for Document {
+    fn from(expr: ExprTree) -> Document {
+        Document::new(expr)
+    }
+}
+
+/// Serialization of floating-point numbers writing the numbers that are
+/// not finite as strings, used for the numbers of trees, tokens, and
+/// errors.
+pub(crate) mod float {
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(num: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if num.is_nan() {
+            serializer.serialize_str("nan")
+        } else if num.is_infinite() && *num > 0.0 {
+            serializer.serialize_str("inf")
+        } else if num.is_infinite() {
+            serializer.serialize_str("-inf")
+        } else {
+            serializer.serialize_f64(*num)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        deserializer.deserialize_any(FloatVisitor)
+    }
+
+    struct FloatVisitor;
+
+    impl<'de> Visitor<'de> for FloatVisitor {
+        type Value = f64;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "a number, \"inf\", \"-inf\", or \"nan\"")
+        }
+
+        fn visit_f64<E: de::Error>(self, num: f64) -> Result<f64, E> {
+            Ok(num)
+        }
+
+        fn visit_i64<E: de::Error>(self, num: i64) -> Result<f64, E> {
+            Ok(num as f64)
+        }
+
+        fn visit_u64<E: de::Error>(self, num: u64) -> Result<f64, E> {
+            Ok(num as f64)
+        }
+
+        fn visit_str<E: de::Error>(self, text: &str) -> Result<f64, E> {
+            match text {
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                "nan" => Ok(f64::NAN),
+                _ => Err(E::invalid_value(de::Unexpected::Str(text), &self)),
+            }
+        }
+    }
+}
+
+/// Check that a document version can be read.
+fn supported<E: de::Error>(version: u32) -> Result<u32, E> {
+    if version > VERSION {
+        Err(E::custom(format!(
+            "unsupported version {}, expected at most {}",
+            version, VERSION
+        )))
+    } else {
+        Ok(version)
+    }
+}
+
+impl<'de> Deserialize<'de> for Document {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Document, D::Error> {
+        deserializer.deserialize_struct("Document", &["version", "expr"], DocumentVisitor)
+    }
+}
+
+/// Visitor reading the version before the tree, so that a document
+/// with a newer version is rejected because of the version rather
+/// than because of a node kind that is not known.
+struct DocumentVisitor;
+
+impl<'de> Visitor<'de> for DocumentVisitor {
+    type Value = Document;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a document with a version and an expression")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Document, A::Error> {
+        let version = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let version = supported(version)?;
+        let expr = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(Document { version, expr })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Document, A::Error> {
+        let mut version = None;
+        let mut expr = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => version = Some(supported(map.next_value()?)?),
+                "expr" => expr = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Document {
+            version: version.ok_or_else(|| de::Error::missing_field("version"))?,
+            expr: expr.ok_or_else(|| de::Error::missing_field("expr"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{self, parse, parse_with};
+    use crate::tokens::{Token, Tokenizer};
+    use crate::tree;
+    use std::collections::HashMap;
+
+    fn round_trip(text: &str) {
+        let document = Document::new(parse(text).unwrap());
+        let json = serde_json::to_string(&document).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Document>(&json).unwrap(),
+            document,
+            "{}",
+            json
+        );
+    }
+
+    #[test]
+    fn trees() {
+        round_trip("x");
+        round_trip("order.total * (1 - discount) ^ 2");
+        round_trip("f() + g(a, 0x10) mod 3 div 2");
+        round_trip("not (a < b or a >= c) and ~(x << 2 | y & z xor w) == 0");
+        round_trip("√x! + 5% - abs(y)");
+
+        let tree = parse_with(
+            "=SUM('My Sheet'!$A$1:B2; C3)",
+            &crate::dialect::Dialect::Spreadsheet,
+        )
+        .unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"node":"call","args":["SUM",[{"node":"range","args":["My Sheet","#,
+                r#"{"column":1,"row":1,"absolute_column":true,"absolute_row":true},"#,
+                r#"{"column":2,"row":2,"absolute_column":false,"absolute_row":false}]},"#,
+                r#"{"node":"cell","args":[null,"#,
+                r#"{"column":3,"row":3,"absolute_column":false,"absolute_row":false}]}]]}"#
+            )
+        );
+        assert_eq!(serde_json::from_str::<ExprTree>(&json).unwrap(), tree);
+    }
+
+    #[test]
+    fn non_finite() {
+        let infinity = parse("1 / 0")
+            .unwrap()
+            .eval(&HashMap::<String, f64>::new())
+            .unwrap();
+        let document = Document::new(ExprTree::Sub(
+            Box::new(ExprTree::Float(infinity)),
+            Box::new(ExprTree::Float(-infinity)),
+        ));
+        let json = serde_json::to_string(&document).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"version":1,"expr":{"node":"sub","args":["#,
+                r#"{"node":"float","args":"inf"},{"node":"float","args":"-inf"}]}}"#
+            )
+        );
+        assert_eq!(serde_json::from_str::<Document>(&json).unwrap(), document);
+
+        let json = serde_json::to_string(&ExprTree::Float(f64::NAN)).unwrap();
+        assert_eq!(json, r#"{"node":"float","args":"nan"}"#);
+        let tree = serde_json::from_str::<ExprTree>(&json).unwrap();
+        assert!(matches!(tree, ExprTree::Float(num) if num.is_nan()));
+
+        let error = tree::Error::NotAnInteger(infinity);
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(json, r#"{"NotAnInteger":"inf"}"#);
+        assert_eq!(serde_json::from_str::<tree::Error>(&json).unwrap(), error);
+        let json = r#"{"node":"float","args":"infinity"}"#;
+        assert!(serde_json::from_str::<ExprTree>(json).is_err());
+    }
+
+    #[test]
+    fn versions() {
+        let json = r#"{"expr":{"node":"var","args":"x"},"comment":"ignored","version":1}"#;
+        assert_eq!(
+            serde_json::from_str::<Document>(json).unwrap(),
+            Document::new(ExprTree::Var("x".to_string()))
+        );
+
+        let json = r#"{"version":2,"expr":{"node":"tensor","args":[]}}"#;
+        let error = serde_json::from_str::<Document>(json).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("unsupported version 2, expected at most 1"));
+
+        let json = r#"{"expr":{"node":"var","args":"x"}}"#;
+        assert!(serde_json::from_str::<Document>(json).is_err());
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<Token<'_>> = Tokenizer::new("`unit price` * 2 >= x²").collect();
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"[{"token":"quoted","value":"unit price"},{"token":"star"},"#,
+                r#"{"token":"float","value":2.0},{"token":"greater_equal"},"#,
+                r#"{"token":"symbol","value":"x"},{"token":"superscript","value":2}]"#
+            )
+        );
+        assert_eq!(
+            serde_json::from_str::<Vec<Token<'_>>>(&json).unwrap(),
+            tokens
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse("1 + (2").unwrap_err();
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(serde_json::from_str::<parser::Error>(&json).unwrap(), error);
+        let error = parse("f(a b)").unwrap_err();
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(serde_json::from_str::<parser::Error>(&json).unwrap(), error);
+        let json = json.replace(r#""rule":"expr""#, r#""rule":"sum""#);
+        assert!(serde_json::from_str::<parser::Error>(&json).is_err());
+
+        let error = crate::eval("y + 1", &HashMap::new()).unwrap_err();
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(json, r#"{"Eval":{"NoValue":{"path":"y","missing":"y"}}}"#);
+        assert_eq!(serde_json::from_str::<crate::Error>(&json).unwrap(), error);
+        let json = serde_json::to_string(&tree::Error::DivisionByZero).unwrap();
+        assert_eq!(
+            serde_json::from_str::<tree::Error>(&json).unwrap(),
+            tree::Error::DivisionByZero
+        );
+    }
+}
@@ -0,0 +1,651 @@
+//! Compact representation of expression trees.
+//!
+//! An [`ExprTree`] allocates every node separately and owns the names
+//! it uses, which adds up when holding many parsed formulas. A
+//! [`FlatTree`] stores all nodes of an expression in one vector,
+//! where operands are referred to by `u32` indexes, names are stored
+//! once, and numbers are kept in a separate table of literals.
+//!
+//! The nodes are stored in an order where the operands of a node come
+//! before the node, so the last node is the root. Use
+//! [`parse_flat`](crate::parser::parse_flat) to parse directly into a
+//! flat tree, or convert from and to an [`ExprTree`] without losing
+//! anything.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::flat::{FlatTree, Kind};
+//! # use expr::parse;
+//! let tree = parse("price * (1 + rate) ^ years - price").unwrap();
+//! let flat = FlatTree::from(&tree);
+//! assert_eq!(flat.names(), ["price", "rate", "years"]);
+//! assert_eq!(flat.node(flat.root()).kind, Kind::Sub);
+//! assert_eq!(flat.to_tree(), tree);
+//! ```
+
+use crate::parser::{Binary, Builder, Unary};
+use crate::spreadsheet::CellRef;
+use crate::tree::{ExprTree, Radix};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// Identifier of a node in a flat tree.
+pub type NodeId = u32;
+
+/// Kind of a node, which tells what its fields refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Var,
+    Path,
+    Float,
+    Integer,
+    Neg,
+    BitNot,
+    Abs,
+    Sqrt,
+    Factorial,
+    Percent,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    IntDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Not,
+    And,
+    Or,
+    Call,
+    Cell,
+    Range,
+}
+
+impl Kind {
+    /// Kind of the root node of a tree.
+    pub fn of(tree: &ExprTree) -> Kind {
+        match tree {
+            ExprTree::Var(_) => Kind::Var,
+            ExprTree::Path(_) => Kind::Path,
+            ExprTree::Float(_) => Kind::Float,
+            ExprTree::Integer(..) => Kind::Integer,
+            ExprTree::Neg(_) => Kind::Neg,
+            ExprTree::BitNot(_) => Kind::BitNot,
+            ExprTree::Abs(_) => Kind::Abs,
+            ExprTree::Sqrt(_) => Kind::Sqrt,
+            ExprTree::Factorial(_) => Kind::Factorial,
+            ExprTree::Percent(_) => Kind::Percent,
+            ExprTree::Add(..) => Kind::Add,
+            ExprTree::Sub(..) => Kind::Sub,
+            ExprTree::Mul(..) => Kind::Mul,
+            ExprTree::Div(..) => Kind::Div,
+            ExprTree::Mod(..) => Kind::Mod,
+            ExprTree::IntDiv(..) => Kind::IntDiv,
+            ExprTree::BitAnd(..) => Kind::BitAnd,
+            ExprTree::BitOr(..) => Kind::BitOr,
+            ExprTree::BitXor(..) => Kind::BitXor,
+            ExprTree::Shl(..) => Kind::Shl,
+            ExprTree::Shr(..) => Kind::Shr,
+            ExprTree::Pow(..) => Kind::Pow,
+            ExprTree::Eq(..) => Kind::Eq,
+            ExprTree::Ne(..) => Kind::Ne,
+            ExprTree::Lt(..) => Kind::Lt,
+            ExprTree::Le(..) => Kind::Le,
+            ExprTree::Gt(..) => Kind::Gt,
+            ExprTree::Ge(..) => Kind::Ge,
+            ExprTree::Not(_) => Kind::Not,
+            ExprTree::And(..) => Kind::And,
+            ExprTree::Or(..) => Kind::Or,
+            ExprTree::Call(..) => Kind::Call,
+            ExprTree::Cell(..) => Kind::Cell,
+            ExprTree::Range(..) => Kind::Range,
+        }
+    }
+
+    /// Number of operands of nodes of this kind. It is zero for the
+    /// kinds whose fields are not operands, including calls.
+    pub fn arity(self) -> usize {
+        match self {
+            Kind::Neg
+            | Kind::BitNot
+            | Kind::Abs
+            | Kind::Sqrt
+            | Kind::Factorial
+            | Kind::Percent
+            | Kind::Not => 1,
+            Kind::Add
+            | Kind::Sub
+            | Kind::Mul
+            | Kind::Div
+            | Kind::Mod
+            | Kind::IntDiv
+            | Kind::BitAnd
+            | Kind::BitOr
+            | Kind::BitXor
+            | Kind::Shl
+            | Kind::Shr
+            | Kind::Pow
+            | Kind::Eq
+            | Kind::Ne
+            | Kind::Lt
+            | Kind::Le
+            | Kind::Gt
+            | Kind::Ge
+            | Kind::And
+            | Kind::Or => 2,
+            Kind::Var
+            | Kind::Path
+            | Kind::Float
+            | Kind::Integer
+            | Kind::Call
+            | Kind::Cell
+            | Kind::Range => 0,
+        }
+    }
+
+    /// Build a node of a kind with one operand.
+    pub(crate) fn unary(self, operand: ExprTree) -> ExprTree {
+        let operand = Box::new(operand);
+        match self {
+            Kind::Neg => ExprTree::Neg(operand),
+            Kind::BitNot => ExprTree::BitNot(operand),
+            Kind::Abs => ExprTree::Abs(operand),
+            Kind::Sqrt => ExprTree::Sqrt(operand),
+            Kind::Factorial => ExprTree::Factorial(operand),
+            Kind::Percent => ExprTree::Percent(operand),
+            Kind::Not => ExprTree::Not(operand),
+            kind => unreachable!("{:?} does not have one operand", kind),
+        }
+    }
+
+    /// Build a node of a kind with two operands.
+    pub(crate) fn binary(self, lhs: ExprTree, rhs: ExprTree) -> ExprTree {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match self {
+            Kind::Add => ExprTree::Add(lhs, rhs),
+            Kind::Sub => ExprTree::Sub(lhs, rhs),
+            Kind::Mul => ExprTree::Mul(lhs, rhs),
+            Kind::Div => ExprTree::Div(lhs, rhs),
+            Kind::Mod => ExprTree::Mod(lhs, rhs),
+            Kind::IntDiv => ExprTree::IntDiv(lhs, rhs),
+            Kind::BitAnd => ExprTree::BitAnd(lhs, rhs),
+            Kind::BitOr => ExprTree::BitOr(lhs, rhs),
+            Kind::BitXor => ExprTree::BitXor(lhs, rhs),
+            Kind::Shl => ExprTree::Shl(lhs, rhs),
+            Kind::Shr => ExprTree::Shr(lhs, rhs),
+            Kind::Pow => ExprTree::Pow(lhs, rhs),
+            Kind::Eq => ExprTree::Eq(lhs, rhs),
+            Kind::Ne => ExprTree::Ne(lhs, rhs),
+            Kind::Lt => ExprTree::Lt(lhs, rhs),
+            Kind::Le => ExprTree::Le(lhs, rhs),
+            Kind::Gt => ExprTree::Gt(lhs, rhs),
+            Kind::Ge => ExprTree::Ge(lhs, rhs),
+            Kind::And => ExprTree::And(lhs, rhs),
+            Kind::Or => ExprTree::Or(lhs, rhs),
+            kind => unreachable!("{:?} does not have two operands", kind),
+        }
+    }
+}
+
+/// Node of a flat tree.
+///
+/// What the fields refer to depends on the kind of node:
+///
+/// - `Var`: `first` is the name.
+/// - `Path`: `first` is the start of the list of names.
+/// - `Float`: `first` is the literal.
+/// - `Integer`: `first` is the literal and `second` is the base of
+///   its radix.
+/// - `Call`: `first` is the name and `second` is the start of the
+///   list of arguments.
+/// - `Cell` and `Range`: `first` is the reference.
+/// - Operators: `first` is the first operand and `second`, if there
+///   is one, is the second operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub kind: Kind,
+    pub first: u32,
+    pub second: u32,
+}
+
+/// Cell or range of cells referred to by a `Cell` or `Range` node.
+/// For a cell, the end is the same as the start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reference {
+    /// Name of the sheet, if any.
+    pub sheet: Option<u32>,
+    pub start: CellRef,
+    pub end: CellRef,
+}
+
+/// Expression tree stored in a few vectors. It always has at least
+/// one node, the root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatTree {
+    nodes: Vec<Node>,
+    names: Vec<String>,
+    /// Numbers of `Float` and `Integer` nodes, where a float is stored
+    /// using its bits.
+    literals: Vec<u64>,
+    /// Lists of names of paths and arguments of calls, each starting
+    /// with the number of elements.
+    lists: Vec<u32>,
+    references: Vec<Reference>,
+}
+
+impl FlatTree {
+    /// Nodes of the tree, where the operands of a node come before the
+    /// node.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Node of the whole tree.
+    pub fn root(&self) -> NodeId {
+        index(self.nodes.len() - 1)
+    }
+
+    pub fn node(&self, id: NodeId) -> Node {
+        self.nodes[id as usize]
+    }
+
+    /// Distinct names used in the tree, in order of first use. When a
+    /// custom operator drops one of its operands, the names used only
+    /// by that operand are still listed.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn name(&self, name: u32) -> &str {
+        &self.names[name as usize]
+    }
+
+    /// Get a list of a `Path` or `Call` node.
+    pub fn list(&self, start: u32) -> &[u32] {
+        let start = start as usize;
+        let len = self.lists[start] as usize;
+        &self.lists[start + 1..start + 1 + len]
+    }
+
+    pub fn reference(&self, reference: u32) -> Reference {
+        self.references[reference as usize]
+    }
+
+    /// Get the operands of a node, in order.
+    pub fn operands(&self, id: NodeId) -> Vec<NodeId> {
+        let node = self.node(id);
+        match node.kind {
+            Kind::Call => self.list(node.second).to_vec(),
+            kind => [node.first, node.second][..kind.arity()].to_vec(),
+        }
+    }
+
+    /// Number of bytes used by the vectors of the tree, including the
+    /// names.
+    pub fn heap_size(&self) -> usize {
+        use std::mem::size_of;
+        self.nodes.capacity() * size_of::<Node>()
+            + self.names.capacity() * size_of::<String>()
+            + self.names.iter().map(String::capacity).sum::<usize>()
+            + self.literals.capacity() * size_of::<u64>()
+            + self.lists.capacity() * size_of::<u32>()
+            + self.references.capacity() * size_of::<Reference>()
+    }
+
+    /// Convert to an expression tree.
+    pub fn to_tree(&self) -> ExprTree {
+        self.subtree(self.root())
+    }
+
+    /// Convert the subtree of a node to an expression tree.
+    pub fn subtree(&self, id: NodeId) -> ExprTree {
+        let node = self.node(id);
+        let name = |name: u32| self.name(name).to_string();
+        let operand = |id: u32| self.subtree(id);
+        match node.kind {
+            Kind::Var => ExprTree::Var(name(node.first)),
+            Kind::Path => ExprTree::Path(self.list(node.first).iter().map(|&n| name(n)).collect()),
+            Kind::Float => ExprTree::Float(f64::from_bits(self.literals[node.first as usize])),
+            Kind::Integer => ExprTree::Integer(
+                self.literals[node.first as usize],
+                Radix::from_base(node.second).expect("radix of an integer"),
+            ),
+            Kind::Call => ExprTree::Call(
+                name(node.first),
+                self.list(node.second)
+                    .iter()
+                    .map(|&arg| self.subtree(arg))
+                    .collect(),
+            ),
+            Kind::Cell | Kind::Range => {
+                let reference = self.reference(node.first);
+                let sheet = reference.sheet.map(name);
+                if node.kind == Kind::Cell {
+                    ExprTree::Cell(sheet, reference.start)
+                } else {
+                    ExprTree::Range(sheet, reference.start, reference.end)
+                }
+            }
+            kind if kind.arity() == 1 => kind.unary(operand(node.first)),
+            kind => kind.binary(operand(node.first), operand(node.second)),
+        }
+    }
+
+    /// First node of the subtree of a node.
+    fn start(&self, id: NodeId) -> NodeId {
+        match self.operands(id).first() {
+            Some(&operand) => self.start(operand),
+            None => id,
+        }
+    }
+}
+
+impl From<&ExprTree> for FlatTree {
+    fn from(tree: &ExprTree) -> FlatTree {
+        let mut builder = FlatBuilder::new();
+        builder.push(tree);
+        builder.finish()
+    }
+}
+
+impl From<&FlatTree> for ExprTree {
+    fn from(flat: &FlatTree) -> ExprTree {
+        flat.to_tree()
+    }
+}
+
+/// Convert the length of a vector into an index.
+fn index(len: usize) -> u32 {
+    u32::try_from(len).expect("too many nodes for a flat tree")
+}
+
+/// Builder of a flat tree, interning the names.
+pub(crate) struct FlatBuilder {
+    tree: FlatTree,
+    interned: HashMap<String, u32>,
+}
+
+impl FlatBuilder {
+    pub(crate) fn new() -> FlatBuilder {
+        FlatBuilder {
+            tree: FlatTree {
+                nodes: Vec::new(),
+                names: Vec::new(),
+                literals: Vec::new(),
+                lists: Vec::new(),
+                references: Vec::new(),
+            },
+            interned: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> FlatTree {
+        self.tree.nodes.shrink_to_fit();
+        self.tree.names.shrink_to_fit();
+        self.tree.literals.shrink_to_fit();
+        self.tree.lists.shrink_to_fit();
+        self.tree.references.shrink_to_fit();
+        self.tree
+    }
+
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.interned.get(name) {
+            return id;
+        }
+        let id = index(self.tree.names.len());
+        self.tree.names.push(name.to_string());
+        self.interned.insert(name.to_string(), id);
+        id
+    }
+
+    fn add(&mut self, kind: Kind, first: u32, second: u32) -> NodeId {
+        self.tree.nodes.push(Node {
+            kind,
+            first,
+            second,
+        });
+        index(self.tree.nodes.len() - 1)
+    }
+
+    fn literal(&mut self, bits: u64) -> u32 {
+        self.tree.literals.push(bits);
+        index(self.tree.literals.len() - 1)
+    }
+
+    fn list(&mut self, elements: Vec<u32>) -> u32 {
+        let start = index(self.tree.lists.len());
+        self.tree.lists.push(index(elements.len()));
+        self.tree.lists.extend(elements);
+        start
+    }
+
+    fn reference(&mut self, sheet: &Option<String>, start: CellRef, end: CellRef) -> u32 {
+        let sheet = sheet.as_ref().map(|sheet| self.intern(sheet));
+        self.tree.references.push(Reference { sheet, start, end });
+        index(self.tree.references.len() - 1)
+    }
+
+    /// Remove the nodes starting with `start`, and the literals, lists,
+    /// and references that they use. Names are kept interned, since the
+    /// tree added in place of the nodes usually uses them again.
+    fn truncate(&mut self, start: NodeId) {
+        let tree = &mut self.tree;
+        for node in tree.nodes.split_off(start as usize).iter().rev() {
+            match node.kind {
+                Kind::Float | Kind::Integer => tree.literals.truncate(node.first as usize),
+                Kind::Path => tree.lists.truncate(node.first as usize),
+                Kind::Call => tree.lists.truncate(node.second as usize),
+                Kind::Cell | Kind::Range => tree.references.truncate(node.first as usize),
+                _ => {}
+            }
+        }
+    }
+
+    /// Add the nodes of a tree, giving the node of the tree.
+    pub(crate) fn push(&mut self, tree: &ExprTree) -> NodeId {
+        let operands: Vec<NodeId> = match tree {
+            // The arguments are added by `call` below.
+            ExprTree::Call(..) => Vec::new(),
+            tree => tree.operands().map(|operand| self.push(operand)).collect(),
+        };
+        match tree {
+            ExprTree::Var(name) => {
+                let name = self.intern(name);
+                self.add(Kind::Var, name, 0)
+            }
+            ExprTree::Path(path) => {
+                let names = path.iter().map(|name| self.intern(name)).collect();
+                let list = self.list(names);
+                self.add(Kind::Path, list, 0)
+            }
+            ExprTree::Float(num) => {
+                let literal = self.literal(num.to_bits());
+                self.add(Kind::Float, literal, 0)
+            }
+            ExprTree::Integer(num, radix) => {
+                let literal = self.literal(*num);
+                self.add(Kind::Integer, literal, radix.base())
+            }
+            ExprTree::Call(name, args) => {
+                let args = args.iter().map(|arg| self.push(arg)).collect();
+                self.call(name.clone(), args)
+            }
+            ExprTree::Cell(sheet, cell) => {
+                let reference = self.reference(sheet, *cell, *cell);
+                self.add(Kind::Cell, reference, 0)
+            }
+            ExprTree::Range(sheet, start, end) => {
+                let reference = self.reference(sheet, *start, *end);
+                self.add(Kind::Range, reference, 0)
+            }
+            tree => {
+                let second = operands.get(1).copied().unwrap_or(0);
+                self.add(Kind::of(tree), operands[0], second)
+            }
+        }
+    }
+
+    /// Add the tree built by a constructor of the operator table. The
+    /// operand nodes are converted back to trees and replaced by the
+    /// tree built.
+    fn build<F>(&mut self, build: F, operands: &[NodeId]) -> NodeId
+    where
+        F: FnOnce(Vec<ExprTree>) -> ExprTree,
+    {
+        let trees = operands.iter().map(|&id| self.tree.subtree(id)).collect();
+        let start = self.tree.start(operands[0]);
+        self.truncate(start);
+        self.push(&build(trees))
+    }
+}
+
+impl Builder for FlatBuilder {
+    type Node = NodeId;
+
+    fn leaf(&mut self, tree: ExprTree) -> NodeId {
+        self.push(&tree)
+    }
+
+    fn unary(&mut self, build: &Unary, operand: NodeId) -> NodeId {
+        match build {
+            Unary::Node(kind) => self.add(*kind, operand, 0),
+            Unary::Operand => operand,
+            Unary::Build(build) => {
+                self.build(|mut trees| build(Box::new(trees.remove(0))), &[operand])
+            }
+            Unary::Call(name) => self.call(name.clone(), vec![operand]),
+        }
+    }
+
+    fn binary(&mut self, build: &Binary, lhs: NodeId, rhs: NodeId) -> NodeId {
+        match build {
+            Binary::Node(kind) => self.add(*kind, lhs, rhs),
+            Binary::Build(build) => {
+                let build = |mut trees: Vec<ExprTree>| {
+                    let rhs = trees.remove(1);
+                    build(Box::new(trees.remove(0)), Box::new(rhs))
+                };
+                self.build(build, &[lhs, rhs])
+            }
+            Binary::Call(name) => self.call(name.clone(), vec![lhs, rhs]),
+        }
+    }
+
+    fn call(&mut self, name: String, args: Vec<NodeId>) -> NodeId {
+        let name = self.intern(&name);
+        let list = self.list(args);
+        self.add(Kind::Call, name, list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::{parse, parse_flat, parse_flat_with, parse_with, Binary, ParserConfig};
+
+    #[test]
+    fn conversion() {
+        let texts = [
+            "x",
+            "-0.0 + 0x10 * 2.5",
+            "order.customer.discount * order.total",
+            "f() + g(a, b.c, -d) * h(f(x))",
+            "not (x < 1 or x >= 2) and y != 0",
+            "√x! + 5% - ~(a << 2 | b >> 1 & c xor d)",
+            "a mod b div c ^ -d",
+        ];
+        for text in &texts {
+            let tree = parse(text).unwrap();
+            let flat = FlatTree::from(&tree);
+            assert_eq!(flat.to_tree(), tree, "{}", text);
+            assert_eq!(parse_flat(text).unwrap(), flat, "{}", text);
+            assert_eq!(ExprTree::from(&flat), tree);
+        }
+
+        let tree = ExprTree::Float(f64::NAN);
+        assert!(matches!(FlatTree::from(&tree).to_tree(), ExprTree::Float(num) if num.is_nan()));
+    }
+
+    #[test]
+    fn layout() {
+        let flat = parse_flat("x * x + f(x, 2)").unwrap();
+        assert_eq!(flat.names(), ["x", "f"]);
+        assert_eq!(flat.nodes().len(), 7);
+        for id in 0..flat.root() {
+            assert!(flat.operands(id).iter().all(|&operand| operand < id));
+        }
+        let root = flat.node(flat.root());
+        assert_eq!(root.kind, Kind::Add);
+        let call = flat.node(root.second);
+        assert_eq!(call.kind, Kind::Call);
+        assert_eq!(flat.name(call.first), "f");
+        assert_eq!(flat.list(call.second).len(), 2);
+    }
+
+    #[test]
+    fn dialects() {
+        let texts = [
+            "=SUM(Data!A1:B$2; C3) * 2 + MOD(x; 3)",
+            "=-A1% + POWER(2; B1) <> 5",
+        ];
+        for text in &texts {
+            let tree = parse_with(text, &Dialect::Spreadsheet).unwrap();
+            let flat = parse_flat_with(text, &Dialect::Spreadsheet).unwrap();
+            assert_eq!(flat.to_tree(), tree, "{}", text);
+            assert_eq!(flat, FlatTree::from(&tree));
+        }
+
+        // Constructors building more than one node are handled by
+        // building the tree.
+        let config = ParserConfig::new().with_infix(
+            "<>",
+            100,
+            crate::parser::Assoc::Left,
+            Binary::Build(|lhs, rhs| ExprTree::Not(Box::new(ExprTree::Eq(lhs, rhs)))),
+        );
+        for text in &["1 + a <> f(b) * 2", "`1` <> 2", "`0` <> `1`"] {
+            let tree = parse_with(text, &config).unwrap();
+            let flat = parse_flat_with(text, &config).unwrap();
+            assert_eq!(flat.to_tree(), tree, "{}", text);
+            assert_eq!(flat, FlatTree::from(&tree));
+        }
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(Kind::Neg.arity(), 1);
+        assert_eq!(Kind::Pow.arity(), 2);
+        assert_eq!(Kind::Call.arity(), 0);
+        let x = || ExprTree::Var("x".to_string());
+        assert_eq!(Kind::Not.unary(x()), ExprTree::Not(Box::new(x())));
+        assert_eq!(
+            Kind::Shl.binary(x(), x()),
+            ExprTree::Shl(Box::new(x()), Box::new(x()))
+        );
+
+        // Unary plus builds no node, and the names of a dropped operand
+        // stay in the table.
+        let config = Dialect::CLike.config().with_infix(
+            "<-",
+            100,
+            crate::parser::Assoc::Left,
+            Binary::Build(|lhs, _| *lhs),
+        );
+        let flat = parse_flat_with("+a <- b + c", &config).unwrap();
+        assert_eq!(flat.to_tree(), parse("a").unwrap());
+        assert_eq!(flat.nodes().len(), 1);
+        assert_eq!(flat.names(), ["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,554 @@
+//! Integer evaluation of expression trees.
+//!
+//! Expressions are evaluated using integers of a selected width,
+//! which is useful for computing things like register masks where
+//! floating-point numbers would lose precision. Values read from the
+//! context and floating-point literals have to be integers.
+
+use crate::dialect::Dialect;
+use crate::tree::{
+    call, cell_value, range_values, resolve, variable, Context, Error, ExprTree, Result,
+};
+use crate::visit::{fold_operands, Fold, Folded};
+use std::convert::TryFrom;
+
+/// Width and signedness of the integers used for evaluation.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Width {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl Width {
+    pub fn bits(self) -> u32 {
+        match self {
+            Width::I8 | Width::U8 => 8,
+            Width::I16 | Width::U16 => 16,
+            Width::I32 | Width::U32 => 32,
+            Width::I64 | Width::U64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        match self {
+            Width::I8 | Width::I16 | Width::I32 | Width::I64 => true,
+            Width::U8 | Width::U16 | Width::U32 | Width::U64 => false,
+        }
+    }
+
+    pub fn min(self) -> i128 {
+        if self.is_signed() {
+            -(1 << (self.bits() - 1))
+        } else {
+            0
+        }
+    }
+
+    pub fn max(self) -> i128 {
+        if self.is_signed() {
+            (1 << (self.bits() - 1)) - 1
+        } else {
+            (1 << self.bits()) - 1
+        }
+    }
+}
+
+/// What to do when the result of an operation does not fit in the
+/// selected width.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Overflow {
+    /// Wrap around using two's complement arithmetic.
+    Wrapping,
+    /// Clamp the result to the smallest or largest value.
+    Saturating,
+    /// Fail with `Error::Overflow`.
+    Error,
+}
+
+/// Integer evaluation mode.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct IntMode {
+    pub width: Width,
+    pub overflow: Overflow,
+}
+
+impl IntMode {
+    pub fn new(width: Width, overflow: Overflow) -> IntMode {
+        IntMode { width, overflow }
+    }
+
+    /// Fit a value into the width according to the overflow policy.
+    fn fit(&self, value: i128) -> Result<i128> {
+        let (min, max) = (self.width.min(), self.width.max());
+        if min <= value && value <= max {
+            return Ok(value);
+        }
+        match self.overflow {
+            Overflow::Wrapping => {
+                let modulus = 1i128 << self.width.bits();
+                let value = value.rem_euclid(modulus);
+                Ok(if value > max { value - modulus } else { value })
+            }
+            Overflow::Saturating => Ok(value.clamp(min, max)),
+            Overflow::Error => Err(Error::Overflow),
+        }
+    }
+
+    /// Convert a floating-point number to an integer in the width.
+    fn convert(&self, num: f64) -> Result<i128> {
+        if num.fract() != 0.0 || !num.is_finite() {
+            return Err(Error::NotAnInteger(num));
+        }
+        self.fit(num as i128)
+    }
+
+    fn mul(&self, lhs: i128, rhs: i128) -> Result<i128> {
+        // Operands of 64 bits can overflow even an `i128`, but since
+        // 2^128 is a multiple of the modulus, wrapping first gives the
+        // same result.
+        match lhs.checked_mul(rhs) {
+            Some(value) => self.fit(value),
+            None => match self.overflow {
+                Overflow::Wrapping => self.fit(lhs.wrapping_mul(rhs)),
+                Overflow::Saturating if (lhs < 0) != (rhs < 0) => Ok(self.width.min()),
+                Overflow::Saturating => Ok(self.width.max()),
+                Overflow::Error => Err(Error::Overflow),
+            },
+        }
+    }
+
+    fn div(&self, lhs: i128, rhs: i128) -> Result<i128> {
+        if rhs == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        self.fit(lhs / rhs)
+    }
+
+    fn floor_div(&self, lhs: i128, rhs: i128) -> Result<i128> {
+        if rhs == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        let quotient = lhs / rhs;
+        if lhs % rhs != 0 && (lhs < 0) != (rhs < 0) {
+            self.fit(quotient - 1)
+        } else {
+            self.fit(quotient)
+        }
+    }
+
+    fn floor_mod(&self, lhs: i128, rhs: i128) -> Result<i128> {
+        if rhs == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        let remainder = lhs % rhs;
+        if remainder != 0 && (remainder < 0) != (rhs < 0) {
+            Ok(remainder + rhs)
+        } else {
+            Ok(remainder)
+        }
+    }
+
+    /// Get the shift amount, or `None` if it is out of range for the
+    /// width and the overflow policy does not wrap it.
+    fn shift_amount(&self, amount: i128) -> Result<Option<u32>> {
+        let bits = self.width.bits();
+        match u32::try_from(amount) {
+            Ok(amount) if amount < bits => Ok(Some(amount)),
+            _ => match self.overflow {
+                Overflow::Wrapping => Ok(Some(amount.rem_euclid(i128::from(bits)) as u32)),
+                Overflow::Saturating => Ok(None),
+                Overflow::Error => Err(Error::Overflow),
+            },
+        }
+    }
+
+    fn shl(&self, lhs: i128, rhs: i128) -> Result<i128> {
+        match self.shift_amount(rhs)? {
+            // Values have at most 64 bits and are shifted at most 63
+            // bits, so this cannot overflow the `i128`.
+            Some(amount) => self.fit(lhs << amount),
+            None if lhs == 0 => Ok(0),
+            None if lhs < 0 => Ok(self.width.min()),
+            None => Ok(self.width.max()),
+        }
+    }
+
+    fn shr(&self, lhs: i128, rhs: i128) -> Result<i128> {
+        match self.shift_amount(rhs)? {
+            Some(amount) => Ok(lhs >> amount),
+            None if lhs < 0 => Ok(-1),
+            None => Ok(0),
+        }
+    }
+
+    fn not(&self, value: i128) -> i128 {
+        if self.width.is_signed() {
+            !value
+        } else {
+            self.width.max() - value
+        }
+    }
+
+    /// Power by repeated squaring. Negative exponents give the
+    /// reciprocal truncated towards zero.
+    fn pow(&self, base: i128, exponent: i128) -> Result<i128> {
+        if exponent < 0 {
+            return match base {
+                0 => Err(Error::DivisionByZero),
+                1 => Ok(1),
+                -1 if exponent % 2 == 0 => Ok(1),
+                -1 => Ok(-1),
+                _ => Ok(0),
+            };
+        }
+        let (mut result, mut base, mut exponent) = (1, base, exponent);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = self.mul(result, base)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = self.mul(base, base)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Integer square root, rounded down.
+    fn sqrt(&self, value: i128) -> Result<i128> {
+        if value < 0 {
+            return Err(Error::OutOfDomain {
+                function: "sqrt".to_string(),
+                value: value as f64,
+            });
+        }
+        // Start from the floating-point estimate and correct it, since
+        // it can be off by one for large values.
+        let mut root = (value as f64).sqrt() as i128;
+        while root * root > value {
+            root -= 1;
+        }
+        while (root + 1) * (root + 1) <= value {
+            root += 1;
+        }
+        Ok(root)
+    }
+
+    fn factorial(&self, value: i128) -> Result<i128> {
+        if value < 0 {
+            return Err(Error::OutOfDomain {
+                function: "factorial".to_string(),
+                value: value as f64,
+            });
+        }
+        let mut product = 1;
+        for n in 2..=value {
+            product = self.mul(product, n)?;
+            // Once the product wraps to zero, which happens when it has
+            // as many factors of two as the width has bits, or saturates
+            // to the largest value, further factors leave it unchanged.
+            // Checked overflow fails within the first 34 factors, so the
+            // loop is short for any operand.
+            let saturated = self.overflow == Overflow::Saturating && product == self.width.max();
+            if product == 0 || saturated {
+                break;
+            }
+        }
+        Ok(product)
+    }
+}
+
+impl Default for IntMode {
+    fn default() -> IntMode {
+        IntMode::new(Width::I64, Overflow::Error)
+    }
+}
+
+impl ExprTree {
+    /// Evaluate the expression using integer arithmetic.
+    ///
+    /// Division using `/` truncates towards zero, while `div` and
+    /// `mod` use floored division. The percent operator divides by
+    /// 100, truncating the result. The result is returned as an
+    /// `i128`, which can hold values of every width. Functions are
+    /// called with the arguments as floating-point numbers, which fails
+    /// for integers above 2^53 that have no exact value, and have to
+    /// return an integer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use expr::parse;
+    /// # use expr::integer::{IntMode, Overflow, Width};
+    /// # use std::collections::HashMap;
+    /// let tree = parse("(flags >> 4) & 0xF | mode << 8").unwrap();
+    /// let mut map = HashMap::new();
+    /// map.insert("flags".to_string(), 0xAB as f64);
+    /// map.insert("mode".to_string(), 3.0);
+    /// let mode = IntMode::new(Width::U16, Overflow::Error);
+    /// assert_eq!(tree.eval_int(&map, &mode), Ok(0x30A));
+    /// ```
+    pub fn eval_int<C: Context + ?Sized>(&self, ctx: &C, mode: &IntMode) -> Result<i128> {
+        IntEvaluator { ctx, mode }.fold(self)
+    }
+}
+
+/// Convert an integer argument to a floating-point number, failing
+/// instead of rounding when it has no exact value.
+fn to_float(value: i128) -> Result<f64> {
+    let num = value as f64;
+    if num as i128 != value {
+        return Err(Error::NotAFloat(value));
+    }
+    Ok(num)
+}
+
+/// Fold evaluating a tree using integer arithmetic, handling the
+/// logical operators and calls before their operands are folded, like
+/// the floating-point evaluation does.
+struct IntEvaluator<'a, C: ?Sized> {
+    ctx: &'a C,
+    mode: &'a IntMode,
+}
+
+impl<C: Context + ?Sized> Fold for IntEvaluator<'_, C> {
+    type Output = Result<i128>;
+
+    fn fold(&mut self, tree: &ExprTree) -> Result<i128> {
+        match tree {
+            ExprTree::And(lhs, rhs) => Ok(i128::from(self.fold(lhs)? != 0 && self.fold(rhs)? != 0)),
+            ExprTree::Or(lhs, rhs) => Ok(i128::from(self.fold(lhs)? != 0 || self.fold(rhs)? != 0)),
+            ExprTree::Call(name, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg {
+                        ExprTree::Range(sheet, start, end) => {
+                            values.extend(range_values(self.ctx, sheet, start, end)?)
+                        }
+                        arg => values.push(to_float(self.fold(arg)?)?),
+                    }
+                }
+                self.mode.convert(call(self.ctx, name, &values)?)
+            }
+            tree => fold_operands(self, tree),
+        }
+    }
+
+    fn fold_node(&mut self, tree: &ExprTree, operands: Folded<Result<i128>>) -> Result<i128> {
+        let (ctx, mode) = (self.ctx, self.mode);
+        match operands {
+            Folded::Zero => match tree {
+                ExprTree::Float(num) => mode.convert(*num),
+                ExprTree::Integer(num, _) => mode.fit(i128::from(*num)),
+                ExprTree::Var(name) => mode.convert(variable(ctx, name)?),
+                ExprTree::Path(path) => mode.convert(resolve(ctx, path)?),
+                ExprTree::Cell(sheet, cell) => mode.convert(cell_value(ctx, sheet, cell)?),
+                ExprTree::Range(..) => Err(Error::UnexpectedRange(
+                    tree.display(Dialect::Spreadsheet).to_string(),
+                )),
+                tree => unreachable!("{:?} has operands", tree),
+            },
+            Folded::One(value) => mode.unary(tree, value?),
+            Folded::Two([lhs, rhs]) => mode.binary(tree, lhs?, rhs?),
+            Folded::Many(_) => unreachable!("{:?} has more than two operands", tree),
+        }
+    }
+}
+
+impl IntMode {
+    /// Compute the value of a node with one operand.
+    fn unary(&self, tree: &ExprTree, value: i128) -> Result<i128> {
+        match tree {
+            ExprTree::Neg(_) => self.fit(-value),
+            ExprTree::BitNot(_) => Ok(self.not(value)),
+            ExprTree::Abs(_) => self.fit(value.abs()),
+            ExprTree::Sqrt(_) => self.sqrt(value),
+            ExprTree::Factorial(_) => self.factorial(value),
+            ExprTree::Percent(_) => self.div(value, 100),
+            ExprTree::Not(_) => Ok(i128::from(value == 0)),
+            tree => unreachable!("{:?} does not have one operand", tree),
+        }
+    }
+
+    /// Compute the value of a node with two operands.
+    fn binary(&self, tree: &ExprTree, lhs: i128, rhs: i128) -> Result<i128> {
+        match tree {
+            ExprTree::Add(..) => self.fit(lhs + rhs),
+            ExprTree::Sub(..) => self.fit(lhs - rhs),
+            ExprTree::Mul(..) => self.mul(lhs, rhs),
+            ExprTree::Div(..) => self.div(lhs, rhs),
+            ExprTree::IntDiv(..) => self.floor_div(lhs, rhs),
+            ExprTree::Mod(..) => self.floor_mod(lhs, rhs),
+            ExprTree::BitAnd(..) => Ok(lhs & rhs),
+            ExprTree::BitOr(..) => Ok(lhs | rhs),
+            ExprTree::BitXor(..) => Ok(lhs ^ rhs),
+            ExprTree::Shl(..) => self.shl(lhs, rhs),
+            ExprTree::Shr(..) => self.shr(lhs, rhs),
+            ExprTree::Pow(..) => self.pow(lhs, rhs),
+            ExprTree::Eq(..) => Ok(i128::from(lhs == rhs)),
+            ExprTree::Ne(..) => Ok(i128::from(lhs != rhs)),
+            ExprTree::Lt(..) => Ok(i128::from(lhs < rhs)),
+            ExprTree::Le(..) => Ok(i128::from(lhs <= rhs)),
+            ExprTree::Gt(..) => Ok(i128::from(lhs > rhs)),
+            ExprTree::Ge(..) => Ok(i128::from(lhs >= rhs)),
+            tree => unreachable!("{:?} does not have two operands", tree),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::parser::{parse_with, ParserConfig};
+    use std::collections::HashMap;
+
+    fn eval(text: &str, width: Width, overflow: Overflow) -> Result<i128> {
+        let map: HashMap<String, f64> = HashMap::new();
+        parse(text)
+            .unwrap()
+            .eval_int(&map, &IntMode::new(width, overflow))
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!((Width::I8.min(), Width::I8.max()), (-128, 127));
+        assert_eq!((Width::U8.min(), Width::U8.max()), (0, 255));
+        assert_eq!(Width::I64.min(), i128::from(i64::MIN));
+        assert_eq!(Width::U64.max(), i128::from(u64::MAX));
+    }
+
+    #[test]
+    fn arithmetic() {
+        use Overflow::Error as Fail;
+        assert_eq!(eval("7 / 2", Width::I32, Fail), Ok(3));
+        assert_eq!(eval("-7 / 2", Width::I32, Fail), Ok(-3));
+        assert_eq!(eval("-7 div 2", Width::I32, Fail), Ok(-4));
+        assert_eq!(eval("-7 mod 2", Width::I32, Fail), Ok(1));
+        assert_eq!(eval("7 mod -2", Width::I32, Fail), Ok(-1));
+        assert_eq!(eval("5!", Width::I32, Fail), Ok(120));
+        assert_eq!(eval("2550%", Width::I32, Fail), Ok(25));
+        assert_eq!(eval("1 / 0", Width::I32, Fail), Err(Error::DivisionByZero));
+        assert_eq!(
+            eval("1 mod 0", Width::I32, Fail),
+            Err(Error::DivisionByZero)
+        );
+        assert_eq!(
+            eval("1.5 + 1", Width::I32, Fail),
+            Err(Error::NotAnInteger(1.5))
+        );
+        assert_eq!(eval("√17", Width::I32, Fail), Ok(4));
+        assert_eq!(
+            eval("√(0 - 4)", Width::I32, Fail),
+            Err(Error::OutOfDomain {
+                function: "sqrt".to_string(),
+                value: -4.0
+            })
+        );
+    }
+
+    #[test]
+    fn call_arguments() {
+        use Overflow::Error as Fail;
+        assert_eq!(
+            eval("f(0x20_0000_0000_0000)", Width::I64, Fail),
+            Err(Error::NoFunction("f".to_string()))
+        );
+        assert_eq!(
+            eval("f(0x20_0000_0000_0001)", Width::I64, Fail),
+            Err(Error::NotAFloat((1 << 53) + 1))
+        );
+    }
+
+    #[test]
+    fn bitwise() {
+        use Overflow::Error as Fail;
+        assert_eq!(eval("0xF0 | 0x0F", Width::U8, Fail), Ok(0xFF));
+        assert_eq!(eval("0xF0 & 0x3C", Width::U8, Fail), Ok(0x30));
+        assert_eq!(eval("0xF0 xor 0xFF", Width::U8, Fail), Ok(0x0F));
+        assert_eq!(eval("~0", Width::U8, Fail), Ok(0xFF));
+        assert_eq!(eval("~0", Width::I8, Fail), Ok(-1));
+        assert_eq!(eval("~0", Width::U64, Fail), Ok(i128::from(u64::MAX)));
+        assert_eq!(eval("-16 >> 2", Width::I32, Fail), Ok(-4));
+        assert_eq!(eval("1 << 7", Width::U8, Fail), Ok(128));
+        assert_eq!(eval("1 << 7", Width::I8, Fail), Err(Error::Overflow));
+        assert_eq!(eval("1 << 8", Width::U8, Fail), Err(Error::Overflow));
+        assert_eq!(
+            eval("0xFFFF_FFFF_FFFF_FFFF >> 60", Width::U64, Fail),
+            Ok(0xF)
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(eval("200 + 100", Width::U8, Overflow::Wrapping), Ok(44));
+        assert_eq!(eval("200 + 100", Width::U8, Overflow::Saturating), Ok(255));
+        assert_eq!(
+            eval("200 + 100", Width::U8, Overflow::Error),
+            Err(Error::Overflow)
+        );
+        assert_eq!(eval("100 + 100", Width::I8, Overflow::Wrapping), Ok(-56));
+        assert_eq!(
+            eval("-100 - 100", Width::I8, Overflow::Saturating),
+            Ok(-128)
+        );
+        assert_eq!(
+            eval("0 - 1", Width::U32, Overflow::Wrapping),
+            Ok(0xFFFF_FFFF)
+        );
+        assert_eq!(eval("0 - 1", Width::U32, Overflow::Saturating), Ok(0));
+        assert_eq!(eval("1 << 9", Width::U8, Overflow::Wrapping), Ok(2));
+        assert_eq!(eval("1 << 9", Width::U8, Overflow::Saturating), Ok(255));
+        assert_eq!(eval("0xFF << 4", Width::U8, Overflow::Wrapping), Ok(0xF0));
+        assert_eq!(
+            eval(
+                "0xFFFF_FFFF_FFFF_FFFF * 0xFFFF_FFFF_FFFF_FFFF",
+                Width::U64,
+                Overflow::Wrapping
+            ),
+            Ok(1)
+        );
+        assert_eq!(
+            eval(
+                "0xFFFF_FFFF_FFFF_FFFF * 0xFFFF_FFFF_FFFF_FFFF",
+                Width::U64,
+                Overflow::Saturating
+            ),
+            Ok(i128::from(u64::MAX))
+        );
+        assert_eq!(eval("0x100", Width::U8, Overflow::Wrapping), Ok(0));
+        assert_eq!(eval("6!", Width::I8, Overflow::Error), Err(Error::Overflow));
+        assert_eq!(
+            eval("0xFFFFFFFFFFFFFFFF!", Width::U64, Overflow::Wrapping),
+            Ok(0)
+        );
+        assert_eq!(
+            eval("0xFFFFFFFFFFFFFFFF!", Width::U64, Overflow::Saturating),
+            Ok(u64::MAX.into())
+        );
+        assert_eq!(eval("5!", Width::U8, Overflow::Wrapping), Ok(120));
+        assert_eq!(eval("6!", Width::U8, Overflow::Wrapping), Ok(720 % 256));
+        assert_eq!(
+            eval("(0 - 3)!", Width::I8, Overflow::Error),
+            Err(Error::OutOfDomain {
+                function: "factorial".to_string(),
+                value: -3.0
+            })
+        );
+        assert_eq!(
+            parse_with("|-128|", &ParserConfig::new().with_abs_bars(true))
+                .unwrap()
+                .eval_int(
+                    &HashMap::<String, f64>::new(),
+                    &IntMode::new(Width::I8, Overflow::Saturating)
+                ),
+            Ok(127)
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! LaTeX rendering of expression trees.
+//!
+//! [`Latex`] writes a tree as LaTeX math, to be placed between `$`
+//! signs or in an equation environment. Division is written as a
+//! fraction and powers as superscripts, so their operands need no
+//! parentheses, and the other operators only get parentheses where
+//! the tree needs them.
+//!
+//! Variables named after Greek letters are written as the letters, and
+//! an underscore starts a subscript, so `alpha_1` is written
+//! `\alpha_{1}`. Other names of more than one letter are written in
+//! italics as one word, and calls of the functions LaTeX has a command
+//! for, like `sin` and `log`, use that command, while `sqrt` and `abs`
+//! are written like the operators.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::latex::Latex;
+//! # use expr::parse;
+//! let tree = parse("(alpha + 1) / sqrt(x_1) * y ^ 2").unwrap();
+//! assert_eq!(
+//!     Latex(&tree).to_string(),
+//!     r"\frac{\alpha + 1}{\sqrt{x_{1}}} \cdot y^{2}"
+//! );
+//! ```
+
+use crate::render::{base_parens, greek, infix_parens, power, subscript, FUNCTIONS};
+use crate::tree::ExprTree;
+use crate::visit::{Fold, Folded};
+
+/// Display wrapper writing an expression tree as LaTeX math.
+pub struct Latex<'a>(pub &'a ExprTree);
+
+impl std::fmt::Display for Latex<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Printer.fold(self.0))
+    }
+}
+
+/// Escape the characters with a meaning in LaTeX, for text written
+/// within `\mathit` and similar commands.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        match ch {
+            '_' | '%' | '$' | '#' | '&' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(ch);
+            }
+            '\\' => escaped.push_str(r"\backslash{}"),
+            '^' => escaped.push_str(r"\hat{}"),
+            '~' => escaped.push_str(r"\sim{}"),
+            ' ' => escaped.push_str(r"\ "),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Write a name in LaTeX, using the commands of the Greek letters and
+/// writing a subscript within braces.
+fn symbol(name: &str) -> String {
+    if let Some((base, sub)) = subscript(name) {
+        return format!("{}_{{{}}}", symbol(base), symbol(sub));
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        _ if greek(name).is_some() => format!("\\{}", name),
+        (Some(ch), None) if ch.is_alphanumeric() => name.to_string(),
+        _ if name.chars().all(|ch| ch.is_ascii_digit()) => name.to_string(),
+        _ => format!("\\mathit{{{}}}", escape(name)),
+    }
+}
+
+/// Put text within `\left(` and `\right)` if needed.
+fn parenthesize(text: &str, paren: bool) -> String {
+    if paren {
+        format!("\\left({}\\right)", text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Fold writing an expression tree as LaTeX, where each node is
+/// written using the text of its operands.
+struct Printer;
+
+impl Fold for Printer {
+    type Output = String;
+
+    fn fold_node(&mut self, tree: &ExprTree, texts: Folded<String>) -> String {
+        let operands: Vec<&ExprTree> = tree.operands().collect();
+        let prefix = |op: &str| {
+            let paren = power(operands[0]) <= power(tree);
+            format!("{}{}", op, parenthesize(&texts[0], paren))
+        };
+        let postfix = |op: &str| {
+            let paren = power(operands[0]) <= power(tree);
+            format!("{}{}", parenthesize(&texts[0], paren), op)
+        };
+        let infix = |op: &str| {
+            let (left, right) = infix_parens(tree, operands[0], operands[1]);
+            format!(
+                "{} {} {}",
+                parenthesize(&texts[0], left),
+                op,
+                parenthesize(&texts[1], right)
+            )
+        };
+        match tree {
+            ExprTree::Var(name) => symbol(name),
+            ExprTree::Path(path) => {
+                let members: Vec<String> = path.iter().map(|member| symbol(member)).collect();
+                members.join(".")
+            }
+            ExprTree::Float(num) if num.is_nan() => r"\mathrm{NaN}".to_string(),
+            ExprTree::Float(num) if num.is_infinite() && *num > 0.0 => r"\infty".to_string(),
+            ExprTree::Float(num) if num.is_infinite() => r"-\infty".to_string(),
+            ExprTree::Float(num) => num.to_string(),
+            ExprTree::Integer(num, radix) => {
+                format!("\\mathtt{{0{}{}}}", radix.letter(), radix.digits(*num))
+            }
+            ExprTree::Call(name, _) if name == "sqrt" && texts.len() == 1 => {
+                format!("\\sqrt{{{}}}", texts[0])
+            }
+            ExprTree::Call(name, _) if name == "abs" && texts.len() == 1 => {
+                format!("\\left|{}\\right|", texts[0])
+            }
+            ExprTree::Call(name, _) => {
+                let name = if FUNCTIONS.contains(&name.as_str()) {
+                    format!("\\{}", name)
+                } else {
+                    format!("\\operatorname{{{}}}", escape(name))
+                };
+                format!("{}\\left({}\\right)", name, texts.join(", "))
+            }
+            ExprTree::Cell(sheet, cell) => match sheet {
+                Some(sheet) => format!(
+                    "\\mathrm{{{}!{}}}",
+                    escape(sheet),
+                    escape(&cell.to_string())
+                ),
+                None => format!("\\mathrm{{{}}}", escape(&cell.to_string())),
+            },
+            ExprTree::Range(sheet, start, end) => {
+                let range = escape(&format!("{}:{}", start, end));
+                match sheet {
+                    Some(sheet) => format!("\\mathrm{{{}!{}}}", escape(sheet), range),
+                    None => format!("\\mathrm{{{}}}", range),
+                }
+            }
+            ExprTree::Neg(_) => prefix("-"),
+            ExprTree::BitNot(_) => prefix(r"\sim "),
+            ExprTree::Not(_) => prefix(r"\lnot "),
+            ExprTree::Abs(_) => format!("\\left|{}\\right|", texts[0]),
+            ExprTree::Sqrt(_) => format!("\\sqrt{{{}}}", texts[0]),
+            ExprTree::Factorial(_) => postfix("!"),
+            ExprTree::Percent(_) => postfix(r"\%"),
+            ExprTree::Div(..) => format!("\\frac{{{}}}{{{}}}", texts[0], texts[1]),
+            ExprTree::Pow(..) => {
+                let paren = base_parens(tree, operands[0]);
+                format!("{}^{{{}}}", parenthesize(&texts[0], paren), texts[1])
+            }
+            ExprTree::Add(..) => infix("+"),
+            ExprTree::Sub(..) => infix("-"),
+            ExprTree::Mul(..) => infix(r"\cdot"),
+            ExprTree::Mod(..) => infix(r"\bmod"),
+            ExprTree::IntDiv(..) => infix(r"\operatorname{div}"),
+            ExprTree::BitAnd(..) => infix(r"\mathbin{\&}"),
+            ExprTree::BitOr(..) => infix(r"\mathbin{|}"),
+            ExprTree::BitXor(..) => infix(r"\oplus"),
+            ExprTree::Shl(..) => infix(r"\ll"),
+            ExprTree::Shr(..) => infix(r"\gg"),
+            ExprTree::Eq(..) => infix("="),
+            ExprTree::Ne(..) => infix(r"\neq"),
+            ExprTree::Lt(..) => infix("<"),
+            ExprTree::Le(..) => infix(r"\leq"),
+            ExprTree::Gt(..) => infix(">"),
+            ExprTree::Ge(..) => infix(r"\geq"),
+            ExprTree::And(..) => infix(r"\land"),
+            ExprTree::Or(..) => infix(r"\lor"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::{parse, parse_with};
+
+    fn latex(text: &str) -> String {
+        Latex(&parse(text).unwrap()).to_string()
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(latex("a / b"), r"\frac{a}{b}");
+        assert_eq!(latex("(a + b) / (c - d)"), r"\frac{a + b}{c - d}");
+        assert_eq!(latex("x ^ (n + 1)"), r"x^{n + 1}");
+        assert_eq!(latex("(x ^ 2) ^ 3"), r"\left(x^{2}\right)^{3}");
+        assert_eq!(latex("x ^ 2 ^ 3"), r"x^{2^{3}}");
+        assert_eq!(latex("(-x) ^ 2"), r"\left(-x\right)^{2}");
+        assert_eq!(latex("-x ^ 2"), r"-x^{2}");
+        assert_eq!(latex("(a / b) ^ 2"), r"\left(\frac{a}{b}\right)^{2}");
+        assert_eq!(latex("√(x ^ 2 + y ^ 2)"), r"\sqrt{x^{2} + y^{2}}");
+        assert_eq!(latex("2 * x * y"), r"2 \cdot x \cdot y");
+        assert_eq!(latex("2 * (x * y)"), r"2 \cdot \left(x \cdot y\right)");
+        assert_eq!(latex("(a + b) * c"), r"\left(a + b\right) \cdot c");
+        assert_eq!(latex("a - (b - c)"), r"a - \left(b - c\right)");
+        assert_eq!(latex("a - b - c"), r"a - b - c");
+        assert_eq!(latex("a + -b"), r"a + \left(-b\right)");
+        assert_eq!(latex("-(a + b)"), r"-\left(a + b\right)");
+        let abs = ExprTree::Abs(Box::new(parse("x - 1").unwrap()));
+        let tree = ExprTree::Factorial(Box::new(abs));
+        assert_eq!(Latex(&tree).to_string(), r"\left|x - 1\right|!");
+        assert_eq!(latex("5% * n!"), r"5\% \cdot n!");
+        assert_eq!(latex("abs(x) + sqrt(2)"), r"\left|x\right| + \sqrt{2}");
+        assert_eq!(latex("(n + 1)!"), r"\left(n + 1\right)!");
+        assert_eq!(latex("a mod b div 2"), r"a \bmod b \operatorname{div} 2");
+        assert_eq!(
+            latex("not a <= b and c != 1 or d"),
+            r"\lnot a \leq b \land c \neq 1 \lor d"
+        );
+        assert_eq!(latex("not (a or b)"), r"\lnot \left(a \lor b\right)");
+        assert_eq!(
+            latex("(x >> 4) & 0xf | ~y xor z << 1"),
+            r"x \gg 4 \mathbin{\&} \mathtt{0xf} \mathbin{|} \sim y \oplus z \ll 1"
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(latex("alpha * x_1"), r"\alpha \cdot x_{1}");
+        assert_eq!(
+            latex("Omega + theta_max"),
+            r"\Omega + \theta_{\mathit{max}}"
+        );
+        assert_eq!(latex("x_i_j + x_"), r"x_{i_{j}} + \mathit{x\_}");
+        assert_eq!(
+            latex("rate * `unit price`"),
+            r"\mathit{rate} \cdot \mathit{unit\ price}"
+        );
+        assert_eq!(latex("order.total"), r"\mathit{order}.\mathit{total}");
+        assert_eq!(
+            latex("sin(x) ^ 2 + my_fn(a, b)"),
+            r"\sin\left(x\right)^{2} + \operatorname{my\_fn}\left(a, b\right)"
+        );
+        let tree = parse_with("=SUM('Q1 & Q2'!$A$1:B2) / C3", &Dialect::Spreadsheet).unwrap();
+        assert_eq!(
+            Latex(&tree).to_string(),
+            r"\frac{\operatorname{SUM}\left(\mathrm{Q1\ \&\ Q2!\$A\$1:B2}\right)}{\mathrm{C3}}"
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(latex("2.5 * 1000000"), r"2.5 \cdot 1000000");
+        let tree = ExprTree::Pow(
+            Box::new(ExprTree::Float(-2.0)),
+            Box::new(ExprTree::Float(f64::INFINITY)),
+        );
+        assert_eq!(Latex(&tree).to_string(), r"\left(-2\right)^{\infty}");
+        let tree = ExprTree::Sub(
+            Box::new(ExprTree::Float(f64::NAN)),
+            Box::new(ExprTree::Float(f64::NEG_INFINITY)),
+        );
+        assert_eq!(
+            Latex(&tree).to_string(),
+            r"\mathrm{NaN} - \left(-\infty\right)"
+        );
+    }
+}
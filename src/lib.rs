@@ -1,6 +1,7 @@
 #![warn(rust_2018_idioms)]
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Error {
     Parser(parser::Error),
     Eval(tree::Error),
@@ -8,9 +9,24 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+pub mod dag;
+pub mod dialect;
+#[cfg(feature = "serde")]
+pub mod document;
+pub mod flat;
+pub mod integer;
+pub mod latex;
+pub mod mathml;
 pub mod parser;
+mod render;
+pub mod rewrite;
+pub mod rpn;
+pub mod sexpr;
+pub mod spreadsheet;
 pub mod tokens;
 pub mod tree;
+pub mod visit;
+pub mod workbook;
 
 impl std::convert::From<parser::Error> for Error {
     fn from(error: parser::Error) -> Error {
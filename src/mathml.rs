@@ -0,0 +1,369 @@
+//! MathML output of expression trees.
+//!
+//! [`Presentation`] writes a tree as Presentation MathML, which
+//! browsers render directly when it is embedded in HTML. Division is
+//! written as a fraction and powers as superscripts, and the other
+//! operators get parentheses only where the tree needs them, the same
+//! way as in [`Latex`]. Names of Greek letters are written as the
+//! letters, and an underscore starts a subscript.
+//!
+//! [`Content`] writes Content MathML instead, which describes the
+//! structure of the expression for exchange with other tools. The
+//! operators without a Content MathML element, like the bitwise
+//! operators, are written as `<csymbol>` elements named after the
+//! [`ExprTree`] variant in snake case, like `<csymbol>bit_and</csymbol>`.
+//! This includes `mod` and `div`, which round the quotient down, while
+//! `<rem/>` and `<quotient/>` round it toward zero.
+//!
+//! Both write a complete `<math>` element, with `<`, `>`, `&`, and
+//! quotes escaped in names.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::mathml::{Content, Presentation};
+//! # use expr::parse;
+//! let tree = parse("alpha / 2").unwrap();
+//! assert_eq!(
+//!     Presentation(&tree).to_string(),
+//!     concat!(
+//!         r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#,
+//!         "<mfrac><mi>α</mi><mn>2</mn></mfrac></math>"
+//!     )
+//! );
+//! assert_eq!(
+//!     Content(&tree).to_string(),
+//!     concat!(
+//!         r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#,
+//!         "<apply><divide/><ci>alpha</ci><cn>2</cn></apply></math>"
+//!     )
+//! );
+//! ```
+//!
+//! [`Latex`]: crate::latex::Latex
+
+use crate::render::{base_parens, greek, infix_parens, negative, power, subscript, FUNCTIONS};
+use crate::tree::ExprTree;
+use crate::visit::{Fold, Folded};
+
+/// Display wrapper writing an expression tree as Presentation MathML.
+pub struct Presentation<'a>(pub &'a ExprTree);
+
+/// Display wrapper writing an expression tree as Content MathML.
+pub struct Content<'a>(pub &'a ExprTree);
+
+impl std::fmt::Display for Presentation<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}</math>", MATH, PresentationPrinter.fold(self.0))
+    }
+}
+
+impl std::fmt::Display for Content<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}</math>", MATH, ContentPrinter.fold(self.0))
+    }
+}
+
+const MATH: &str = r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#;
+
+/// Escape the characters with a meaning in XML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Write a name as an `<mi>` element, or as `<msub>` if it has a
+/// subscript, using the Greek letters for their names.
+fn symbol(name: &str) -> String {
+    if let Some((base, sub)) = subscript(name) {
+        return format!("<msub>{}{}</msub>", symbol(base), symbol(sub));
+    }
+    if let Some(letter) = greek(name) {
+        format!("<mi>{}</mi>", letter)
+    } else if name.chars().all(|ch| ch.is_ascii_digit()) {
+        format!("<mn>{}</mn>", name)
+    } else {
+        format!("<mi>{}</mi>", escape(name))
+    }
+}
+
+/// Wrap an element in an `<mrow>` with parenthesis operators if
+/// needed.
+fn parenthesize(element: &str, paren: bool) -> String {
+    if paren {
+        format!("<mrow><mo>(</mo>{}<mo>)</mo></mrow>", element)
+    } else {
+        element.to_string()
+    }
+}
+
+/// Fold writing an expression tree as Presentation MathML, where each
+/// node is written as one element using the elements of its operands.
+struct PresentationPrinter;
+
+impl Fold for PresentationPrinter {
+    type Output = String;
+
+    fn fold_node(&mut self, tree: &ExprTree, texts: Folded<String>) -> String {
+        let operands: Vec<&ExprTree> = tree.operands().collect();
+        let prefix = |op: &str| {
+            let paren = power(operands[0]) <= power(tree);
+            format!(
+                "<mrow><mo>{}</mo>{}</mrow>",
+                op,
+                parenthesize(&texts[0], paren)
+            )
+        };
+        let postfix = |op: &str| {
+            let paren = power(operands[0]) <= power(tree);
+            format!(
+                "<mrow>{}<mo>{}</mo></mrow>",
+                parenthesize(&texts[0], paren),
+                op
+            )
+        };
+        let infix = |op: &str| {
+            let (left, right) = infix_parens(tree, operands[0], operands[1]);
+            format!(
+                "<mrow>{}<mo>{}</mo>{}</mrow>",
+                parenthesize(&texts[0], left),
+                op,
+                parenthesize(&texts[1], right)
+            )
+        };
+        let bars = |text: &str| format!("<mrow><mo>|</mo>{}<mo>|</mo></mrow>", text);
+        match tree {
+            ExprTree::Var(name) => symbol(name),
+            ExprTree::Path(path) => {
+                let members: Vec<String> = path.iter().map(|member| symbol(member)).collect();
+                format!("<mrow>{}</mrow>", members.join("<mo>.</mo>"))
+            }
+            ExprTree::Float(num) if num.is_nan() => "<mi>NaN</mi>".to_string(),
+            ExprTree::Float(num) if num.is_infinite() && *num > 0.0 => "<mi>∞</mi>".to_string(),
+            ExprTree::Float(num) if num.is_infinite() => {
+                "<mrow><mo>-</mo><mi>∞</mi></mrow>".to_string()
+            }
+            ExprTree::Float(num) if negative(tree) => {
+                format!("<mrow><mo>-</mo><mn>{}</mn></mrow>", -num)
+            }
+            ExprTree::Float(num) => format!("<mn>{}</mn>", num),
+            ExprTree::Integer(num, radix) => {
+                format!("<mn>0{}{}</mn>", radix.letter(), radix.digits(*num))
+            }
+            ExprTree::Call(name, _) if name == "sqrt" && texts.len() == 1 => {
+                format!("<msqrt>{}</msqrt>", texts[0])
+            }
+            ExprTree::Call(name, _) if name == "abs" && texts.len() == 1 => bars(&texts[0]),
+            ExprTree::Call(name, _) => format!(
+                "<mrow><mi>{}</mi><mo>&#x2061;</mo><mrow><mo>(</mo>{}<mo>)</mo></mrow></mrow>",
+                escape(name),
+                texts.join("<mo>,</mo>")
+            ),
+            ExprTree::Cell(sheet, cell) => match sheet {
+                Some(sheet) => format!("<mi>{}!{}</mi>", escape(sheet), cell),
+                None => format!("<mi>{}</mi>", cell),
+            },
+            ExprTree::Range(sheet, start, end) => match sheet {
+                Some(sheet) => format!("<mi>{}!{}:{}</mi>", escape(sheet), start, end),
+                None => format!("<mi>{}:{}</mi>", start, end),
+            },
+            ExprTree::Neg(_) => prefix("-"),
+            ExprTree::BitNot(_) => prefix("~"),
+            ExprTree::Not(_) => prefix("¬"),
+            ExprTree::Abs(_) => bars(&texts[0]),
+            ExprTree::Sqrt(_) => format!("<msqrt>{}</msqrt>", texts[0]),
+            ExprTree::Factorial(_) => postfix("!"),
+            ExprTree::Percent(_) => postfix("%"),
+            ExprTree::Div(..) => format!("<mfrac>{}{}</mfrac>", texts[0], texts[1]),
+            ExprTree::Pow(..) => {
+                let paren = base_parens(tree, operands[0]);
+                format!(
+                    "<msup>{}{}</msup>",
+                    parenthesize(&texts[0], paren),
+                    texts[1]
+                )
+            }
+            ExprTree::Add(..) => infix("+"),
+            ExprTree::Sub(..) => infix("-"),
+            ExprTree::Mul(..) => infix("⋅"),
+            ExprTree::Mod(..) => infix("mod"),
+            ExprTree::IntDiv(..) => infix("div"),
+            ExprTree::BitAnd(..) => infix("&amp;"),
+            ExprTree::BitOr(..) => infix("|"),
+            ExprTree::BitXor(..) => infix("⊕"),
+            ExprTree::Shl(..) => infix("&lt;&lt;"),
+            ExprTree::Shr(..) => infix("&gt;&gt;"),
+            ExprTree::Eq(..) => infix("="),
+            ExprTree::Ne(..) => infix("≠"),
+            ExprTree::Lt(..) => infix("&lt;"),
+            ExprTree::Le(..) => infix("≤"),
+            ExprTree::Gt(..) => infix("&gt;"),
+            ExprTree::Ge(..) => infix("≥"),
+            ExprTree::And(..) => infix("∧"),
+            ExprTree::Or(..) => infix("∨"),
+        }
+    }
+}
+
+/// Fold writing an expression tree as Content MathML.
+struct ContentPrinter;
+
+impl Fold for ContentPrinter {
+    type Output = String;
+
+    fn fold_node(&mut self, tree: &ExprTree, texts: Folded<String>) -> String {
+        let apply = |op: &str| format!("<apply>{}{}</apply>", op, texts.concat());
+        match tree {
+            ExprTree::Var(name) => format!("<ci>{}</ci>", escape(name)),
+            ExprTree::Path(path) => format!("<ci>{}</ci>", escape(&path.join("."))),
+            ExprTree::Float(num) if num.is_nan() => "<notanumber/>".to_string(),
+            ExprTree::Float(num) if num.is_infinite() && *num > 0.0 => "<infinity/>".to_string(),
+            ExprTree::Float(num) if num.is_infinite() => {
+                "<apply><minus/><infinity/></apply>".to_string()
+            }
+            ExprTree::Float(num) => format!("<cn>{}</cn>", num),
+            ExprTree::Integer(num, _) => format!(r#"<cn type="integer">{}</cn>"#, num),
+            ExprTree::Call(name, _) if FUNCTIONS.contains(&name.as_str()) => {
+                apply(&format!("<{}/>", name))
+            }
+            ExprTree::Call(name, _) if name == "sqrt" && texts.len() == 1 => apply("<root/>"),
+            ExprTree::Call(name, _) if name == "abs" && texts.len() == 1 => apply("<abs/>"),
+            ExprTree::Call(name, _) => apply(&format!("<ci>{}</ci>", escape(name))),
+            ExprTree::Cell(sheet, cell) => match sheet {
+                Some(sheet) => format!("<ci>{}!{}</ci>", escape(sheet), cell),
+                None => format!("<ci>{}</ci>", cell),
+            },
+            ExprTree::Range(sheet, start, end) => match sheet {
+                Some(sheet) => format!("<ci>{}!{}:{}</ci>", escape(sheet), start, end),
+                None => format!("<ci>{}:{}</ci>", start, end),
+            },
+            ExprTree::Neg(_) | ExprTree::Sub(..) => apply("<minus/>"),
+            ExprTree::Not(_) => apply("<not/>"),
+            ExprTree::Abs(_) => apply("<abs/>"),
+            ExprTree::Sqrt(_) => apply("<root/>"),
+            ExprTree::Factorial(_) => apply("<factorial/>"),
+            ExprTree::Add(..) => apply("<plus/>"),
+            ExprTree::Mul(..) => apply("<times/>"),
+            ExprTree::Div(..) => apply("<divide/>"),
+            ExprTree::Pow(..) => apply("<power/>"),
+            ExprTree::Eq(..) => apply("<eq/>"),
+            ExprTree::Ne(..) => apply("<neq/>"),
+            ExprTree::Lt(..) => apply("<lt/>"),
+            ExprTree::Le(..) => apply("<leq/>"),
+            ExprTree::Gt(..) => apply("<gt/>"),
+            ExprTree::Ge(..) => apply("<geq/>"),
+            ExprTree::And(..) => apply("<and/>"),
+            ExprTree::Or(..) => apply("<or/>"),
+            ExprTree::BitNot(_) => apply("<csymbol>bit_not</csymbol>"),
+            ExprTree::Percent(_) => apply("<csymbol>percent</csymbol>"),
+            ExprTree::Mod(..) => apply("<csymbol>mod</csymbol>"),
+            ExprTree::IntDiv(..) => apply("<csymbol>int_div</csymbol>"),
+            ExprTree::BitAnd(..) => apply("<csymbol>bit_and</csymbol>"),
+            ExprTree::BitOr(..) => apply("<csymbol>bit_or</csymbol>"),
+            ExprTree::BitXor(..) => apply("<csymbol>bit_xor</csymbol>"),
+            ExprTree::Shl(..) => apply("<csymbol>shl</csymbol>"),
+            ExprTree::Shr(..) => apply("<csymbol>shr</csymbol>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::{parse, parse_with};
+
+    /// Write the text as Presentation MathML without the `<math>` element.
+    fn presentation(text: &str) -> String {
+        let math = Presentation(&parse(text).unwrap()).to_string();
+        math[MATH.len()..math.len() - "</math>".len()].to_string()
+    }
+
+    /// Write the text as Content MathML without the `<math>` element.
+    fn content(text: &str) -> String {
+        let math = Content(&parse(text).unwrap()).to_string();
+        math[MATH.len()..math.len() - "</math>".len()].to_string()
+    }
+
+    #[test]
+    fn presentation_markup() {
+        assert_eq!(
+            presentation("(a + 1) / x ^ 2"),
+            "<mfrac><mrow><mi>a</mi><mo>+</mo><mn>1</mn></mrow><msup><mi>x</mi><mn>2</mn></msup></mfrac>"
+        );
+        assert_eq!(
+            presentation("(x ^ 2) ^ 3"),
+            "<msup><mrow><mo>(</mo><msup><mi>x</mi><mn>2</mn></msup><mo>)</mo></mrow><mn>3</mn></msup>"
+        );
+        assert_eq!(
+            presentation("2 * (a - b)"),
+            "<mrow><mn>2</mn><mo>⋅</mo><mrow><mo>(</mo><mrow><mi>a</mi><mo>-</mo><mi>b</mi></mrow><mo>)</mo></mrow></mrow>"
+        );
+        assert_eq!(
+            presentation("a - -1"),
+            "<mrow><mi>a</mi><mo>-</mo><mrow><mo>(</mo><mrow><mo>-</mo><mn>1</mn></mrow><mo>)</mo></mrow></mrow>"
+        );
+        assert_eq!(
+            presentation("√x + n!"),
+            "<mrow><msqrt><mi>x</mi></msqrt><mo>+</mo><mrow><mi>n</mi><mo>!</mo></mrow></mrow>"
+        );
+        assert_eq!(
+            presentation("max(a, 0xff)"),
+            "<mrow><mi>max</mi><mo>&#x2061;</mo><mrow><mo>(</mo><mi>a</mi><mo>,</mo><mn>0xff</mn><mo>)</mo></mrow></mrow>"
+        );
+        assert_eq!(
+            presentation("omega_0 * x_max"),
+            "<mrow><msub><mi>ω</mi><mn>0</mn></msub><mo>⋅</mo><msub><mi>x</mi><mi>max</mi></msub></mrow>"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            presentation("a < b & c"),
+            "<mrow><mi>a</mi><mo>&lt;</mo><mrow><mi>b</mi><mo>&amp;</mo><mi>c</mi></mrow></mrow>"
+        );
+        assert_eq!(
+            presentation("`<b>` + `\"it's\"`(1)"),
+            "<mrow><mi>&lt;b&gt;</mi><mo>+</mo><mrow><mi>&quot;it&apos;s&quot;</mi><mo>&#x2061;</mo><mrow><mo>(</mo><mn>1</mn><mo>)</mo></mrow></mrow></mrow>"
+        );
+        let tree = parse_with("='R&D'!A1:B2", &Dialect::Spreadsheet).unwrap();
+        assert!(Presentation(&tree)
+            .to_string()
+            .contains("<mi>R&amp;D!A1:B2</mi>"));
+        assert_eq!(
+            content("`a&b` >> 1"),
+            "<apply><csymbol>shr</csymbol><ci>a&amp;b</ci><cn>1</cn></apply>"
+        );
+    }
+
+    #[test]
+    fn content_markup() {
+        assert_eq!(
+            content("-x + sin(y) * 2"),
+            "<apply><plus/><apply><minus/><ci>x</ci></apply><apply><times/><apply><sin/><ci>y</ci></apply><cn>2</cn></apply></apply>"
+        );
+        assert_eq!(
+            content("not (a <= b) or √c == 0x10"),
+            "<apply><or/><apply><not/><apply><leq/><ci>a</ci><ci>b</ci></apply></apply><apply><eq/><apply><root/><ci>c</ci></apply><cn type=\"integer\">16</cn></apply></apply>"
+        );
+        assert_eq!(
+            content("order.total mod f(x)"),
+            "<apply><csymbol>mod</csymbol><ci>order.total</ci><apply><ci>f</ci><ci>x</ci></apply></apply>"
+        );
+        assert_eq!(
+            content("a div 2"),
+            "<apply><csymbol>int_div</csymbol><ci>a</ci><cn>2</cn></apply>"
+        );
+    }
+}
@@ -1,16 +1,35 @@
-//! Predictive expression parse for expressions.
+//! Expression parser using precedence climbing over a configurable
+//! table of operators.
 
+use crate::flat::{FlatBuilder, FlatTree, Kind};
+use crate::spreadsheet::CellRef;
 use crate::tokens::{Token, Tokenizer};
 use crate::tree::ExprTree;
+use std::borrow::Cow;
+use std::sync::OnceLock;
 
 /// Parse expression.
 ///
 /// Parse an expression from a string to produce an expression tree.
-/// The grammar is given by the following rules:
+/// The grammar is given by the following rules, which are the
+/// operator table of the default [`ParserConfig`]:
 ///
-/// expr ::= term (("+" | "-") term)*
-/// term ::= factor (("*" | "/") factor)*
-/// factor ::= ("-" | "+")? (number | variable | "(" expr ")")
+/// expr ::= conjunction ("or" conjunction)*
+/// conjunction ::= negation ("and" negation)*
+/// negation ::= "not"? compare
+/// compare ::= or (("==" | "=" | "!=" | "<" | "<=" | ">" | ">=") or)?
+/// or ::= xor ("|" xor)*
+/// xor ::= and ("xor" and)*
+/// and ::= shift ("&" shift)*
+/// shift ::= sum (("<<" | ">>") sum)*
+/// sum ::= term (("+" | "-") term)*
+/// term ::= factor (("*" | "/" | "mod" | "div") factor)*
+/// factor ::= ("-" | "+" | "~" | "√")? power
+/// power ::= postfix ("^" factor)?
+/// postfix ::= primary ("!" | "%" | superscript)*
+/// primary ::= number | integer | path | call | "(" expr ")" | "|" xor "|"
+/// path ::= variable ("." variable)*
+/// call ::= variable "(" (expr ("," expr)*)? ")"
 ///
 /// # Returns
 ///
@@ -27,152 +46,966 @@ use crate::tree::ExprTree;
 /// assert_eq!(tree.eval(&map), Ok(22.0));
 /// ```
 pub fn parse(text: &str) -> Result<ExprTree> {
-    let mut tokens = Tokenizer::new(text);
-    let tree = expr_rule(&mut tokens);
+    parse_with(text, default_config())
+}
+
+/// Get the default configuration, which is built once since building
+/// the operator table takes much longer than parsing a short
+/// expression.
+pub(crate) fn default_config() -> &'static ParserConfig {
+    static CONFIG: OnceLock<ParserConfig> = OnceLock::new();
+    CONFIG.get_or_init(ParserConfig::new)
+}
+
+/// How juxtaposed operands, like `2x` or `3(x + 1)`, are parsed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Juxtaposition {
+    /// Reject juxtaposed operands with an unexpected token error.
+    Reject,
+    /// Multiply juxtaposed operands with the same precedence as `*`,
+    /// so `1/2x` is parsed as `(1/2)*x`.
+    Multiply,
+    /// Multiply juxtaposed operands with higher precedence than `*`
+    /// and `/`, so `1/2x` is parsed as `1/(2*x)`.
+    MultiplyTight,
+}
+
+/// Associativity of an infix operator.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Assoc {
+    /// Group from the left, so `a - b - c` is `(a - b) - c`.
+    Left,
+    /// Group from the right, so `a ^ b ^ c` is `a ^ (b ^ c)`.
+    Right,
+    /// Do not group at all, so `a < b < c` is an error.
+    NonAssoc,
+}
+
+/// Tree built by a prefix or postfix operator from its operand.
+#[derive(Debug, Clone)]
+pub enum Unary {
+    /// Build a node of an operator kind with one operand, like
+    /// `Kind::Neg`. Adding an operator with a kind that takes another
+    /// number of operands panics.
+    Node(Kind),
+    /// Keep the operand itself, like unary plus.
+    Operand,
+    /// Build any tree from the operand using a function. Flat trees
+    /// convert the operand back into a tree to call it.
+    Build(fn(Box<ExprTree>) -> ExprTree),
+    /// Build a call of the named function with the operand as the
+    /// argument.
+    Call(String),
+}
+
+/// Tree built by an infix operator from its operands.
+#[derive(Debug, Clone)]
+pub enum Binary {
+    /// Build a node of an operator kind with two operands, like
+    /// `Kind::Add`. Adding an operator with a kind that takes another
+    /// number of operands panics.
+    Node(Kind),
+    /// Build any tree from the operands using a function. Flat trees
+    /// convert the operands back into trees to call it.
+    Build(fn(Box<ExprTree>, Box<ExprTree>) -> ExprTree),
+    /// Build a call of the named function with the operands as the
+    /// arguments.
+    Call(String),
+}
+
+/// Node built by a call of a function with a fixed number of
+/// arguments, like `ABS(x)` or `MOD(a; b)` in spreadsheets.
+#[derive(Debug, Clone)]
+pub enum Builtin {
+    Unary(Kind),
+    Binary(Kind),
+}
+
+/// Check that a node built by an operator or builtin has the number
+/// of operands it is given.
+fn check_arity(kind: Kind, arity: usize) {
+    assert!(
+        kind.arity() == arity,
+        "{:?} nodes take {} operands, not {}",
+        kind,
+        kind.arity(),
+        arity
+    );
+}
+
+#[derive(Debug, Clone)]
+struct UnaryOperator {
+    token: Token<'static>,
+    power: u32,
+    build: Unary,
+}
+
+impl UnaryOperator {
+    fn new(token: Token<'static>, power: u32, build: Unary) -> UnaryOperator {
+        if let Unary::Node(kind) = build {
+            check_arity(kind, 1);
+        }
+        UnaryOperator {
+            token,
+            power,
+            build,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct InfixOperator {
+    token: Token<'static>,
+    power: u32,
+    assoc: Assoc,
+    build: Binary,
+}
+
+/// Binding power of `*`, used for juxtaposed operands when there is
+/// no `*` operator.
+const MULTIPLY_POWER: u32 = 70;
+
+/// Configuration of the parser.
+///
+/// The default configuration gives the strict grammar used by
+/// [`parse`]. Operators are kept in a table where each operator has
+/// a binding power, and operators with higher binding power bind
+/// tighter. The default operator table is:
+///
+/// | Power | Operators                        | Kind                 |
+/// |-------|----------------------------------|----------------------|
+/// | 4     | `or`                             | infix, left          |
+/// | 6     | `and`                            | infix, left          |
+/// | 8     | `not`                            | prefix               |
+/// | 10    | `==` `=` `!=` `<` `<=` `>` `>=`  | infix, non-assoc     |
+/// | 20    | `\|`                             | infix, left          |
+/// | 30    | `xor`                            | infix, left          |
+/// | 40    | `&`                              | infix, left          |
+/// | 50    | `<<` `>>`                        | infix, left          |
+/// | 60    | `+` `-`                          | infix, left          |
+/// | 70    | `*` `/` `mod` `div`              | infix, left          |
+/// | 80    | `-` `+` `~` `√`                  | prefix               |
+/// | 90    | `^`                              | infix, right         |
+/// | 100   | `!` `%`                          | postfix              |
+///
+/// Superscript powers, like `x²`, bind tighter than any operator.
+/// Operators can be added, or replaced, using
+/// [`with_prefix`](ParserConfig::with_prefix),
+/// [`with_infix`](ParserConfig::with_infix), and
+/// [`with_postfix`](ParserConfig::with_postfix). The configurations
+/// of other dialects are given by [`Dialect::config`](crate::dialect::Dialect::config).
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    juxtaposition: Juxtaposition,
+    abs_bars: bool,
+    spreadsheet: bool,
+    unicode: bool,
+    /// Whether `and`, `or`, or `not` is an operator, in which case all
+    /// three are read as keywords rather than names.
+    logic: bool,
+    /// Whether `mod`, `div`, or `xor` is an operator, in which case all
+    /// three are read as keywords rather than names.
+    integer: bool,
+    prefix: Vec<UnaryOperator>,
+    infix: Vec<InfixOperator>,
+    postfix: Vec<UnaryOperator>,
+    symbols: Vec<String>,
+    functions: Vec<String>,
+    builtins: Vec<(String, Builtin)>,
+    separator: Token<'static>,
+}
+
+impl ParserConfig {
+    pub fn new() -> ParserConfig {
+        ParserConfig::empty()
+            .with_infix("or", 4, Assoc::Left, Binary::Node(Kind::Or))
+            .with_infix("and", 6, Assoc::Left, Binary::Node(Kind::And))
+            .with_prefix("not", 8, Unary::Node(Kind::Not))
+            .with_infix("==", 10, Assoc::NonAssoc, Binary::Node(Kind::Eq))
+            .with_infix("=", 10, Assoc::NonAssoc, Binary::Node(Kind::Eq))
+            .with_infix("!=", 10, Assoc::NonAssoc, Binary::Node(Kind::Ne))
+            .with_infix("<", 10, Assoc::NonAssoc, Binary::Node(Kind::Lt))
+            .with_infix("<=", 10, Assoc::NonAssoc, Binary::Node(Kind::Le))
+            .with_infix(">", 10, Assoc::NonAssoc, Binary::Node(Kind::Gt))
+            .with_infix(">=", 10, Assoc::NonAssoc, Binary::Node(Kind::Ge))
+            .with_infix("|", 20, Assoc::Left, Binary::Node(Kind::BitOr))
+            .with_infix("xor", 30, Assoc::Left, Binary::Node(Kind::BitXor))
+            .with_infix("&", 40, Assoc::Left, Binary::Node(Kind::BitAnd))
+            .with_infix("<<", 50, Assoc::Left, Binary::Node(Kind::Shl))
+            .with_infix(">>", 50, Assoc::Left, Binary::Node(Kind::Shr))
+            .with_infix("+", 60, Assoc::Left, Binary::Node(Kind::Add))
+            .with_infix("-", 60, Assoc::Left, Binary::Node(Kind::Sub))
+            .with_infix("*", 70, Assoc::Left, Binary::Node(Kind::Mul))
+            .with_infix("/", 70, Assoc::Left, Binary::Node(Kind::Div))
+            .with_infix("mod", 70, Assoc::Left, Binary::Node(Kind::Mod))
+            .with_infix("div", 70, Assoc::Left, Binary::Node(Kind::IntDiv))
+            .with_prefix("-", 80, Unary::Node(Kind::Neg))
+            .with_prefix("+", 80, Unary::Operand)
+            .with_prefix("~", 80, Unary::Node(Kind::BitNot))
+            .with_prefix("√", 80, Unary::Node(Kind::Sqrt))
+            .with_infix("^", 90, Assoc::Right, Binary::Node(Kind::Pow))
+            .with_postfix("!", 100, Unary::Node(Kind::Factorial))
+            .with_postfix("%", 100, Unary::Node(Kind::Percent))
+            .with_builtin("abs", Builtin::Unary(Kind::Abs))
+    }
+
+    /// Set how juxtaposed operands are parsed.
+    ///
+    /// An operand is juxtaposed when it directly follows another
+    /// operand and starts with a number, a variable, or an opening
+    /// parenthesis. Signs are always parsed as addition or
+    /// subtraction, so `2 -x` is still `2 - x`.
+    ///
+    /// A name followed by an opening parenthesis is a function call
+    /// when juxtaposition is rejected, but a multiplication when it
+    /// is not, unless the name is registered using
+    /// [`with_function`](ParserConfig::with_function).
+    pub fn with_juxtaposition(mut self, juxtaposition: Juxtaposition) -> ParserConfig {
+        self.juxtaposition = juxtaposition;
+        self
+    }
+
+    /// Enable absolute-value bars, so that `|a - b|` is parsed as the
+    /// absolute value of `a - b`.
+    ///
+    /// A bar where an operand is expected opens an absolute value and
+    /// the next bar at the same nesting level closes it. This means
+    /// that `|` is still the bitwise or operator outside bars, but
+    /// has to be put within parentheses to be used inside bars, as in
+    /// `|(a | b)|`. Juxtaposition does not apply to bars, so `2|x|`
+    /// has to be written `2 * |x|`.
+    pub fn with_abs_bars(mut self, enable: bool) -> ParserConfig {
+        self.abs_bars = enable;
+        self
+    }
+
+    /// Enable spreadsheet mode, to parse formulas like
+    /// `=SUM(A1:A10) * B$2`.
+    ///
+    /// In spreadsheet mode, a formula can start with `=`, cells can
+    /// be referenced using A1 notation with optional `$` markers, and
+    /// rectangular ranges of cells written as `A1:B3`. References can
+    /// be qualified with a sheet name, as in `Sheet2!A1` or
+    /// `'My Sheet'!A1:A3`. Function names are case-insensitive and
+    /// are converted to upper case, so `sum(A1:A3)` is a call of
+    /// `SUM`. See [`spreadsheet`](crate::spreadsheet) for how cells
+    /// are evaluated.
+    pub fn with_spreadsheet(mut self, enable: bool) -> ParserConfig {
+        self.spreadsheet = enable;
+        self
+    }
+
+    /// Accept Unicode aliases for operators and constants, like `×`,
+    /// `√`, `π`, and superscript powers. This is enabled by default,
+    /// see [`Tokenizer::with_unicode`] for the full list.
+    pub fn with_unicode(mut self, enable: bool) -> ParserConfig {
+        self.unicode = enable;
+        self
+    }
+
+    /// Add a prefix operator, replacing any prefix operator with the
+    /// same symbol.
+    ///
+    /// The operand of a prefix operator extends over all operators
+    /// binding at least as tight as `power`. The operand can only
+    /// start with another prefix operator if that binds tighter, so
+    /// `not -x` is accepted but `--x` has to be written `-(-x)`.
+    pub fn with_prefix(mut self, symbol: &str, power: u32, build: Unary) -> ParserConfig {
+        let token = self.operator_token(symbol);
+        self.prefix.retain(|op| op.token != token);
+        self.prefix.push(UnaryOperator::new(token, power, build));
+        self
+    }
+
+    /// Add an infix operator, replacing any infix operator with the
+    /// same symbol.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use expr::parser::{parse_with, Assoc, Binary, ParserConfig};
+    /// let config = ParserConfig::new().with_infix(
+    ///     "+/-",
+    ///     60,
+    ///     Assoc::Left,
+    ///     Binary::Call("tolerance".to_string()),
+    /// );
+    /// let tree = parse_with("10 +/- 2 * e", &config).unwrap();
+    /// assert_eq!(tree.to_string(), "tolerance(10, 2 * e)");
+    /// ```
+    pub fn with_infix(
+        mut self,
+        symbol: &str,
+        power: u32,
+        assoc: Assoc,
+        build: Binary,
+    ) -> ParserConfig {
+        if let Binary::Node(kind) = build {
+            check_arity(kind, 2);
+        }
+        let token = self.operator_token(symbol);
+        self.infix.retain(|op| op.token != token);
+        self.infix.push(InfixOperator {
+            token,
+            power,
+            assoc,
+            build,
+        });
+        self
+    }
+
+    /// Add a postfix operator, replacing any postfix operator with
+    /// the same symbol.
+    pub fn with_postfix(mut self, symbol: &str, power: u32, build: Unary) -> ParserConfig {
+        let token = self.operator_token(symbol);
+        self.postfix.retain(|op| op.token != token);
+        self.postfix.push(UnaryOperator::new(token, power, build));
+        self
+    }
+
+    /// Register a function name, so that the name followed by an
+    /// opening parenthesis is parsed as a call also when
+    /// juxtaposition is enabled.
+    pub fn with_function(mut self, name: &str) -> ParserConfig {
+        self.functions.push(name.to_string());
+        self
+    }
+
+    /// Get the token for an operator symbol.
+    ///
+    /// Symbols that are read as a single operator or name by the
+    /// tokenizer, like `^` or `and`, use that token. Other symbols,
+    /// like `+/-`, are added to the symbols recognized by the
+    /// tokenizer.
+    fn operator_token(&mut self, symbol: &str) -> Token<'static> {
+        assert!(!symbol.is_empty(), "operator symbol cannot be empty");
+        let mut tokens = Tokenizer::new(symbol);
+        match (tokens.next(), tokens.next()) {
+            (Some(Token::Float(_)), _)
+            | (Some(Token::Integer(..)), _)
+            | (Some(Token::Superscript(_)), _)
+            | (Some(Token::Error), _) => {}
+            (Some(token), None) => {
+                if matches!(token, Token::And | Token::Or | Token::Not) {
+                    self.logic = true;
+                }
+                if matches!(token, Token::Mod | Token::Div | Token::Xor) {
+                    self.integer = true;
+                }
+                return token.into_owned();
+            }
+            _ => {}
+        }
+        if !self.symbols.iter().any(|known| known == symbol) {
+            self.symbols.push(symbol.to_string());
+            // Try longer symbols first, so that a symbol is not read
+            // as a shorter symbol that it starts with.
+            self.symbols
+                .sort_by_key(|symbol| std::cmp::Reverse(symbol.len()));
+        }
+        Token::Operator(Cow::Owned(symbol.to_string()))
+    }
+
+    fn prefix(&self, token: &Token<'_>) -> Option<&UnaryOperator> {
+        self.prefix.iter().find(|op| op.token == *token)
+    }
+
+    fn infix(&self, token: &Token<'_>) -> Option<&InfixOperator> {
+        self.infix.iter().find(|op| op.token == *token)
+    }
+
+    fn postfix(&self, token: &Token<'_>) -> Option<&UnaryOperator> {
+        self.postfix.iter().find(|op| op.token == *token)
+    }
+
+    fn is_function(&self, name: &str) -> bool {
+        self.juxtaposition == Juxtaposition::Reject
+            || self.functions.iter().any(|function| {
+                function == name || self.spreadsheet && function.eq_ignore_ascii_case(name)
+            })
+    }
+
+    /// Get the name of a called function, which is in upper case in
+    /// spreadsheet mode unless it was quoted.
+    fn function_name(&self, name: String, quoted: bool) -> String {
+        if self.spreadsheet && !quoted {
+            name.to_uppercase()
+        } else {
+            name
+        }
+    }
+
+    /// Create a configuration without any operators, to build the
+    /// operator table of a new dialect from scratch.
+    pub fn empty() -> ParserConfig {
+        ParserConfig {
+            juxtaposition: Juxtaposition::Reject,
+            abs_bars: false,
+            spreadsheet: false,
+            unicode: true,
+            logic: false,
+            integer: false,
+            prefix: Vec::new(),
+            infix: Vec::new(),
+            postfix: Vec::new(),
+            symbols: Vec::new(),
+            functions: Vec::new(),
+            builtins: Vec::new(),
+            separator: Token::Comma,
+        }
+    }
+
+    /// Register a function that builds an expression tree node when
+    /// called with the right number of arguments. Calls with another
+    /// number of arguments are kept as calls.
+    pub fn with_builtin(mut self, name: &str, build: Builtin) -> ParserConfig {
+        match build {
+            Builtin::Unary(kind) => check_arity(kind, 1),
+            Builtin::Binary(kind) => check_arity(kind, 2),
+        }
+        self.builtins.retain(|(known, _)| known != name);
+        self.builtins.push((name.to_string(), build));
+        self.with_function(name)
+    }
+
+    /// Set the symbol separating function arguments, which is `,` by
+    /// default.
+    pub fn with_separator(mut self, symbol: &str) -> ParserConfig {
+        self.separator = self.operator_token(symbol);
+        self
+    }
+
+    /// Build the tree for a call, which is a node if the function is
+    /// a builtin taking that number of arguments.
+    fn call<B: Builder>(&self, builder: &mut B, name: String, mut args: Vec<B::Node>) -> B::Node {
+        let builtin = self.builtins.iter().find(|(known, _)| *known == name);
+        match (builtin, args.len()) {
+            (Some((_, Builtin::Unary(kind))), 1) => {
+                builder.unary(&Unary::Node(*kind), args.remove(0))
+            }
+            (Some((_, Builtin::Binary(kind))), 2) => {
+                let rhs = args.remove(1);
+                builder.binary(&Binary::Node(*kind), args.remove(0), rhs)
+            }
+            _ => builder.call(name, args),
+        }
+    }
+
+    /// Binding power of juxtaposed operands, if they are accepted.
+    fn juxtaposition_power(&self) -> Option<u32> {
+        let multiply = self
+            .infix(&Token::Star)
+            .map_or(MULTIPLY_POWER, |op| op.power);
+        match self.juxtaposition {
+            Juxtaposition::Reject => None,
+            Juxtaposition::Multiply => Some(multiply),
+            Juxtaposition::MultiplyTight => Some(multiply + 1),
+        }
+    }
+}
+
+/// Grammar used to parse expressions, which is either a
+/// [`ParserConfig`] or a [`Dialect`](crate::dialect::Dialect).
+pub trait Grammar {
+    fn config(&self) -> Cow<'_, ParserConfig>;
+}
+
+impl Grammar for ParserConfig {
+    fn config(&self) -> Cow<'_, ParserConfig> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Default for ParserConfig {
+    fn default() -> ParserConfig {
+        ParserConfig::new()
+    }
+}
+
+/// Parse expression using a parser configuration or a dialect.
+///
+/// # Example
+///
+/// ```
+/// # use expr::dialect::Dialect;
+/// # use expr::parser::{parse_with, Juxtaposition, ParserConfig};
+/// let config = ParserConfig::new().with_juxtaposition(Juxtaposition::Multiply);
+/// let tree = parse_with("2pi r", &config).unwrap();
+/// assert_eq!(tree.to_string(), "2 * pi * r");
+/// let tree = parse_with("MOD(a; 2) = 1", &Dialect::Spreadsheet).unwrap();
+/// assert_eq!(tree.to_string(), "a mod 2 == 1");
+/// ```
+pub fn parse_with<G: Grammar + ?Sized>(text: &str, grammar: &G) -> Result<ExprTree> {
+    parse_into(text, grammar, &mut Trees)
+}
+
+/// Parse expression into a [`FlatTree`].
+///
+/// The nodes are added to the flat tree as they are parsed, so no
+/// [`ExprTree`] is built on the way.
+///
+/// # Example
+///
+/// ```
+/// # use expr::parser::{parse, parse_flat};
+/// let flat = parse_flat("x * (x + 1)").unwrap();
+/// assert_eq!(flat.nodes().len(), 5);
+/// assert_eq!(flat.names(), ["x"]);
+/// assert_eq!(flat.to_tree(), parse("x * (x + 1)").unwrap());
+/// ```
+pub fn parse_flat(text: &str) -> Result<FlatTree> {
+    parse_flat_with(text, default_config())
+}
+
+/// Parse expression into a [`FlatTree`] using a parser configuration
+/// or a dialect.
+pub fn parse_flat_with<G: Grammar + ?Sized>(text: &str, grammar: &G) -> Result<FlatTree> {
+    let mut builder = FlatBuilder::new();
+    parse_into(text, grammar, &mut builder)?;
+    Ok(builder.finish())
+}
+
+/// Builder of the nodes of a parsed expression, so that the parser
+/// can produce either an [`ExprTree`] or a [`FlatTree`].
+///
+/// Operators are given as the constructors of the operator table,
+/// which build [`ExprTree`] nodes.
+pub(crate) trait Builder {
+    type Node;
+
+    /// Build a node without operands, like a number or a variable.
+    fn leaf(&mut self, tree: ExprTree) -> Self::Node;
+
+    /// Build the tree of a prefix or postfix operator.
+    fn unary(&mut self, build: &Unary, operand: Self::Node) -> Self::Node;
+
+    /// Build the tree of an infix operator.
+    fn binary(&mut self, build: &Binary, lhs: Self::Node, rhs: Self::Node) -> Self::Node;
+
+    fn call(&mut self, name: String, args: Vec<Self::Node>) -> Self::Node;
+}
+
+/// Builder of expression trees.
+struct Trees;
+
+impl Builder for Trees {
+    type Node = ExprTree;
+
+    fn leaf(&mut self, tree: ExprTree) -> ExprTree {
+        tree
+    }
+
+    fn unary(&mut self, build: &Unary, operand: ExprTree) -> ExprTree {
+        match build {
+            Unary::Node(kind) => kind.unary(operand),
+            Unary::Operand => operand,
+            Unary::Build(build) => build(Box::new(operand)),
+            Unary::Call(name) => ExprTree::Call(name.clone(), vec![operand]),
+        }
+    }
+
+    fn binary(&mut self, build: &Binary, lhs: ExprTree, rhs: ExprTree) -> ExprTree {
+        match build {
+            Binary::Node(kind) => kind.binary(lhs, rhs),
+            Binary::Build(build) => build(Box::new(lhs), Box::new(rhs)),
+            Binary::Call(name) => ExprTree::Call(name.clone(), vec![lhs, rhs]),
+        }
+    }
+
+    fn call(&mut self, name: String, args: Vec<ExprTree>) -> ExprTree {
+        ExprTree::Call(name, args)
+    }
+}
+
+fn parse_into<G: Grammar + ?Sized, B: Builder>(
+    text: &str,
+    grammar: &G,
+    builder: &mut B,
+) -> Result<B::Node> {
+    let config = grammar.config();
+    let config = config.as_ref();
+    let mut tokens = Tokenizer::new(text)
+        .with_unicode(config.unicode)
+        .with_cells(config.spreadsheet)
+        .with_logic_keywords(config.logic)
+        .with_integer_keywords(config.integer)
+        .with_operators(&config.symbols);
+    if config.spreadsheet && tokens.peek() == Some(&Token::Assign) {
+        tokens.next();
+    }
+    let tree = expr_rule(&mut tokens, config, builder, 0);
     match tokens.next() {
         None => tree,
         Some(tok) => Err(Error::UnexpectedToken {
-            token: tok,
-            rule: "expr",
-            expect: "end of input",
+            token: tok.into_owned(),
+            rule: RULE_EXPR,
+            expect: EXPECT_END,
         }),
     }
 }
 
-fn expr_rule(tokens: &mut Tokenizer<'_>) -> Result<ExprTree> {
-    let mut tree = term_rule(tokens)?;
-    while let Some(Token::Plus) | Some(Token::Minus) = tokens.peek() {
-        let tok = tokens.next();
-        let rhs = term_rule(tokens)?;
-        match tok {
-            Some(Token::Plus) => {
-                tree = ExprTree::Add(Box::new(tree), Box::new(rhs));
+/// Parse an expression consisting of operators binding at least as
+/// tight as `power`.
+fn expr_rule<B: Builder>(
+    tokens: &mut Tokenizer<'_>,
+    config: &ParserConfig,
+    builder: &mut B,
+    power: u32,
+) -> Result<B::Node> {
+    let lhs = prefix_rule(tokens, config, builder, None)?;
+    operator_rule(lhs, tokens, config, builder, power)
+}
+
+/// Parse an operand with an optional prefix operator. Inside the
+/// operand of another prefix operator, only prefix operators binding
+/// tighter than the `outer` one are accepted.
+fn prefix_rule<B: Builder>(
+    tokens: &mut Tokenizer<'_>,
+    config: &ParserConfig,
+    builder: &mut B,
+    outer: Option<u32>,
+) -> Result<B::Node> {
+    let tok = tokens.next();
+    match tok.as_ref().and_then(|tok| config.prefix(tok)) {
+        Some(op) if outer.map_or(true, |outer| op.power > outer) => {
+            let operand = prefix_rule(tokens, config, builder, Some(op.power))?;
+            let operand = operator_rule(operand, tokens, config, builder, op.power)?;
+            Ok(builder.unary(&op.build, operand))
+        }
+        _ => primary_rule(tok, tokens, config, builder),
+    }
+}
+
+/// Parse the postfix and infix operators following the operand `lhs`
+/// as long as they bind at least as tight as `power`.
+fn operator_rule<B: Builder>(
+    mut lhs: B::Node,
+    tokens: &mut Tokenizer<'_>,
+    config: &ParserConfig,
+    builder: &mut B,
+    power: u32,
+) -> Result<B::Node> {
+    // Binding power of the last operator if it was non-associative,
+    // since another operator with the same binding power cannot
+    // follow it.
+    let mut blocked = None;
+    loop {
+        let tok = tokens.peek();
+        if let Some(op) = tok.and_then(|tok| config.postfix(tok)) {
+            if op.power < power {
+                break;
             }
-            Some(Token::Minus) => {
-                tree = ExprTree::Sub(Box::new(tree), Box::new(rhs));
+            tokens.next();
+            lhs = builder.unary(&op.build, lhs);
+        } else if let Some(&Token::Superscript(exponent)) = tok {
+            tokens.next();
+            let exponent = builder.leaf(ExprTree::Float(f64::from(exponent)));
+            lhs = builder.binary(&Binary::Node(Kind::Pow), lhs, exponent);
+        } else if let Some(op) = tok.and_then(|tok| config.infix(tok)) {
+            if op.power < power || blocked == Some(op.power) {
+                break;
+            }
+            tokens.next();
+            let rhs = match op.assoc {
+                Assoc::Right => expr_rule(tokens, config, builder, op.power)?,
+                Assoc::Left | Assoc::NonAssoc => expr_rule(tokens, config, builder, op.power + 1)?,
+            };
+            lhs = builder.binary(&op.build, lhs, rhs);
+            if op.assoc == Assoc::NonAssoc {
+                blocked = Some(op.power);
+            }
+        } else if starts_operand(tok) {
+            let juxtaposition = match config.juxtaposition_power() {
+                Some(juxtaposition) if juxtaposition >= power => juxtaposition,
+                _ => break,
+            };
+            let rhs = expr_rule(tokens, config, builder, juxtaposition + 1)?;
+            lhs = builder.binary(&Binary::Node(Kind::Mul), lhs, rhs);
+        } else {
+            break;
+        }
+    }
+    Ok(lhs)
+}
+
+/// Check if a token can start a juxtaposed operand.
+fn starts_operand(tok: Option<&Token<'_>>) -> bool {
+    matches!(
+        tok,
+        Some(Token::Float(_))
+            | Some(Token::Integer(..))
+            | Some(Token::Symbol(_))
+            | Some(Token::Quoted(_))
+            | Some(Token::Open)
+            | Some(Token::Root)
+    )
+}
+
+/// Parse a primary expression, starting with the already read token
+/// `tok`.
+fn primary_rule<B: Builder>(
+    tok: Option<Token<'_>>,
+    tokens: &mut Tokenizer<'_>,
+    config: &ParserConfig,
+    builder: &mut B,
+) -> Result<B::Node> {
+    let quoted = matches!(tok, Some(Token::Quoted(_)));
+    match tok {
+        Some(Token::Float(number)) => Ok(builder.leaf(ExprTree::Float(number))),
+        Some(Token::Integer(number, radix)) => Ok(builder.leaf(ExprTree::Integer(number, radix))),
+        Some(Token::Symbol(name)) | Some(Token::Quoted(name)) => {
+            match path_rule(name.into_owned(), tokens)? {
+                ExprTree::Var(name)
+                    if tokens.peek() == Some(&Token::Open) && config.is_function(&name) =>
+                {
+                    tokens.next();
+                    call_rule(config.function_name(name, quoted), tokens, config, builder)
+                }
+                path => Ok(builder.leaf(path)),
             }
+        }
+        Some(Token::Cell(cell)) => {
+            reference_rule(None, cell, tokens).map(|tree| builder.leaf(tree))
+        }
+        Some(Token::Sheet(sheet)) => match tokens.next() {
+            Some(Token::Cell(cell)) => reference_rule(Some(sheet.into_owned()), cell, tokens)
+                .map(|tree| builder.leaf(tree)),
+            Some(tok) => Err(Error::UnexpectedToken {
+                token: tok.into_owned(),
+                rule: RULE_REFERENCE,
+                expect: EXPECT_CELL,
+            }),
+            None => Err(Error::UnexpectedEndOfInput {
+                rule: RULE_REFERENCE,
+                expect: EXPECT_CELL,
+            }),
+        },
+        Some(Token::Open) => {
+            let expr = expr_rule(tokens, config, builder, 0)?;
+            expect(tokens, Token::Close, RULE_FACTOR, EXPECT_CLOSE)?;
+            Ok(expr)
+        }
+        Some(Token::Pipe) if config.abs_bars => {
+            // Bars inside the bars cannot be bitwise or, so parse
+            // the operand using only the operators binding tighter.
+            let power = config.infix(&Token::Pipe).map_or(0, |op| op.power + 1);
+            let expr = expr_rule(tokens, config, builder, power)?;
+            expect(tokens, Token::Pipe, RULE_FACTOR, EXPECT_BAR)?;
+            Ok(builder.unary(&Unary::Node(Kind::Abs), expr))
+        }
+        Some(tok) => Err(Error::UnexpectedToken {
+            token: tok.into_owned(),
+            rule: RULE_FACTOR,
+            expect: EXPECT_OPERAND,
+        }),
+        None => Err(Error::UnexpectedEndOfInput {
+            rule: RULE_FACTOR,
+            expect: EXPECT_OPERAND,
+        }),
+    }
+}
+
+/// Parse a cell reference or, if it is followed by a colon, a range
+/// of cells.
+fn reference_rule(
+    sheet: Option<String>,
+    start: CellRef,
+    tokens: &mut Tokenizer<'_>,
+) -> Result<ExprTree> {
+    if tokens.peek() != Some(&Token::Colon) {
+        return Ok(ExprTree::Cell(sheet, start));
+    }
+    tokens.next();
+    match tokens.next() {
+        Some(Token::Cell(end)) => Ok(ExprTree::Range(sheet, start, end)),
+        Some(tok) => Err(Error::UnexpectedToken {
+            token: tok.into_owned(),
+            rule: RULE_REFERENCE,
+            expect: EXPECT_CELL,
+        }),
+        None => Err(Error::UnexpectedEndOfInput {
+            rule: RULE_REFERENCE,
+            expect: EXPECT_CELL,
+        }),
+    }
+}
+
+/// Parse the arguments of a function call after the opening
+/// parenthesis.
+fn call_rule<B: Builder>(
+    name: String,
+    tokens: &mut Tokenizer<'_>,
+    config: &ParserConfig,
+    builder: &mut B,
+) -> Result<B::Node> {
+    let mut args = Vec::new();
+    if let Some(Token::Close) = tokens.peek() {
+        tokens.next();
+        return Ok(config.call(builder, name, args));
+    }
+    loop {
+        args.push(expr_rule(tokens, config, builder, 0)?);
+        match tokens.next() {
+            Some(Token::Close) => return Ok(config.call(builder, name, args)),
+            Some(ref tok) if *tok == config.separator => {}
             Some(tok) => {
                 return Err(Error::UnexpectedToken {
-                    token: tok,
-                    rule: "expr",
-                    expect: "'+' or '-'",
+                    token: tok.into_owned(),
+                    rule: RULE_CALL,
+                    expect: EXPECT_SEPARATOR,
                 });
             }
             None => {
                 return Err(Error::UnexpectedEndOfInput {
-                    rule: "expr",
-                    expect: "'+' or '-'",
+                    rule: RULE_CALL,
+                    expect: EXPECT_SEPARATOR,
                 });
             }
         }
     }
-    Ok(tree)
 }
 
-fn term_rule(tokens: &mut Tokenizer<'_>) -> Result<ExprTree> {
-    let mut tree = factor_rule(tokens)?;
-    while let Some(Token::Star) | Some(Token::Slash) = tokens.peek() {
-        let tok = tokens.next();
-        let rhs = factor_rule(tokens)?;
-        match tok {
-            Some(Token::Star) => {
-                tree = ExprTree::Mul(Box::new(tree), Box::new(rhs));
-            }
-            Some(Token::Slash) => {
-                tree = ExprTree::Div(Box::new(tree), Box::new(rhs));
+/// Read the token closing a rule.
+fn expect(
+    tokens: &mut Tokenizer<'_>,
+    token: Token<'_>,
+    rule: &'static str,
+    expect: &'static str,
+) -> Result<()> {
+    match tokens.next() {
+        Some(tok) if tok == token => Ok(()),
+        Some(tok) => Err(Error::UnexpectedToken {
+            token: tok.into_owned(),
+            rule,
+            expect,
+        }),
+        None => Err(Error::UnexpectedEndOfInput { rule, expect }),
+    }
+}
+
+fn path_rule(name: String, tokens: &mut Tokenizer<'_>) -> Result<ExprTree> {
+    let mut path = vec![name];
+    while let Some(Token::Dot) = tokens.peek() {
+        tokens.next();
+        match tokens.next() {
+            Some(Token::Symbol(member)) | Some(Token::Quoted(member)) => {
+                path.push(member.into_owned())
             }
             Some(tok) => {
                 return Err(Error::UnexpectedToken {
-                    token: tok,
-                    rule: "term",
-                    expect: "'*' or '/'",
+                    token: tok.into_owned(),
+                    rule: RULE_PATH,
+                    expect: EXPECT_MEMBER,
                 });
             }
             None => {
                 return Err(Error::UnexpectedEndOfInput {
-                    rule: "term",
-                    expect: "'*' or '/'",
+                    rule: RULE_PATH,
+                    expect: EXPECT_MEMBER,
                 });
             }
         }
     }
-    Ok(tree)
-}
-
-fn factor_rule(tokens: &mut Tokenizer<'_>) -> Result<ExprTree> {
-    let tok = tokens.next();
-    let mut negate = false;
 
-    // Match optional plus or minus
-    let tok = match tok {
-        Some(Token::Minus) => {
-            negate = true;
-            tokens.next()
-        }
-        Some(Token::Plus) => {
-            negate = false;
-            tokens.next()
-        }
-        _ => tok,
-    };
-
-    let result = match tok {
-        Some(Token::Float(number)) => ExprTree::Float(number),
-        Some(Token::Symbol(name)) => ExprTree::Var(name),
-        Some(Token::Open) => {
-            let expr = expr_rule(tokens)?;
-            match tokens.next() {
-                Some(Token::Close) => expr,
-                Some(tok) => {
-                    return Err(Error::UnexpectedToken {
-                        token: tok,
-                        rule: "factor",
-                        expect: "')'",
-                    });
-                }
-                None => {
-                    return Err(Error::UnexpectedEndOfInput {
-                        rule: "factor",
-                        expect: "')'",
-                    });
-                }
-            }
-        }
-        Some(tok) => {
-            return Err(Error::UnexpectedToken {
-                token: tok,
-                rule: "factor",
-                expect: "number, variable, or '('",
-            });
-        }
-        None => {
-            return Err(Error::UnexpectedEndOfInput {
-                rule: "factor",
-                expect: "number, variable, or '('",
-            });
-        }
-    };
-
-    if negate {
-        Ok(ExprTree::Neg(Box::new(result)))
+    if path.len() == 1 {
+        Ok(ExprTree::Var(path.remove(0)))
     } else {
-        Ok(result)
+        Ok(ExprTree::Path(path))
     }
 }
 
+// Names of the rules and descriptions of the expected input given in
+// errors, which deserialized errors are mapped back to.
+const RULE_EXPR: &str = "expr";
+const RULE_FACTOR: &str = "factor";
+const RULE_REFERENCE: &str = "reference";
+const RULE_CALL: &str = "call";
+const RULE_PATH: &str = "path";
+const EXPECT_END: &str = "end of input";
+const EXPECT_OPERAND: &str = "number, variable, or '('";
+const EXPECT_CELL: &str = "cell reference";
+const EXPECT_SEPARATOR: &str = "separator or ')'";
+const EXPECT_MEMBER: &str = "member name";
+const EXPECT_CLOSE: &str = "')'";
+const EXPECT_BAR: &str = "'|'";
+
+#[cfg(feature = "serde")]
+const KNOWN: &[&str] = &[
+    RULE_EXPR,
+    RULE_FACTOR,
+    RULE_REFERENCE,
+    RULE_CALL,
+    RULE_PATH,
+    EXPECT_END,
+    EXPECT_OPERAND,
+    EXPECT_CELL,
+    EXPECT_SEPARATOR,
+    EXPECT_MEMBER,
+    EXPECT_CLOSE,
+    EXPECT_BAR,
+];
+
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Error {
     UnexpectedEndOfInput {
         rule: &'static str,
         expect: &'static str,
     },
     UnexpectedToken {
-        token: Token,
+        token: Token<'static>,
         rule: &'static str,
         expect: &'static str,
     },
 }
 
+/// Deserialized error, where the rule and the expected input are read
+/// as strings and then replaced by the strings used by the parser.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+enum Stored {
+    UnexpectedEndOfInput {
+        rule: String,
+        expect: String,
+    },
+    UnexpectedToken {
+        token: Token<'static>,
+        rule: String,
+        expect: String,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Error {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Error, D::Error> {
+        use serde::de::Error as _;
+        let known = |text: String| {
+            KNOWN
+                .iter()
+                .find(|known| **known == text)
+                .copied()
+                .ok_or_else(|| D::Error::custom(format!("unknown rule or expectation '{}'", text)))
+        };
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::UnexpectedEndOfInput { rule, expect } => Error::UnexpectedEndOfInput {
+                rule: known(rule)?,
+                expect: known(expect)?,
+            },
+            Stored::UnexpectedToken {
+                token,
+                rule,
+                expect,
+            } => Error::UnexpectedToken {
+                token,
+                rule: known(rule)?,
+                expect: known(expect)?,
+            },
+        })
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -215,7 +1048,10 @@ mod tests {
     use super::Error::*;
     use super::ExprTree::*;
     use super::Token;
-    use super::{parse, ExprTree};
+    use super::{
+        parse, parse_with, Assoc, Binary, ExprTree, Juxtaposition, Kind, ParserConfig, Unary,
+    };
+    use crate::tree::Radix;
 
     fn check(expr: &str, tree: ExprTree) {
         assert_eq!(parse(expr), Ok(tree));
@@ -293,5 +1129,449 @@ mod tests {
             parse("10+("),
             Err(UnexpectedEndOfInput { rule: "factor", .. })
         );
+
+        assert_matches!(
+            parse("order."),
+            Err(UnexpectedEndOfInput { rule: "path", .. })
+        );
+
+        assert_matches!(
+            parse("order.12"),
+            Err(UnexpectedToken {
+                token: Token::Float(_),
+                rule: "path",
+                ..
+            })
+        );
+
+        assert_matches!(
+            parse("3 $ 4"),
+            Err(UnexpectedToken {
+                token: Token::Error,
+                rule: "expr",
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn bitwise_parse() {
+        check(
+            "(flags >> 4) & 0xF | mode << 8",
+            BitOr(
+                Box::new(BitAnd(
+                    Box::new(Shr(
+                        Box::new(Var("flags".to_string())),
+                        Box::new(Float(4.0)),
+                    )),
+                    Box::new(Integer(15, Radix::Hexadecimal)),
+                )),
+                Box::new(Shl(Box::new(Var("mode".to_string())), Box::new(Float(8.0)))),
+            ),
+        );
+        check(
+            "a | b xor c & d",
+            BitOr(
+                Box::new(Var("a".to_string())),
+                Box::new(BitXor(
+                    Box::new(Var("b".to_string())),
+                    Box::new(BitAnd(
+                        Box::new(Var("c".to_string())),
+                        Box::new(Var("d".to_string())),
+                    )),
+                )),
+            ),
+        );
+        check(
+            "1 << 2 + 3",
+            Shl(
+                Box::new(Float(1.0)),
+                Box::new(Add(Box::new(Float(2.0)), Box::new(Float(3.0)))),
+            ),
+        );
+        check(
+            "~a mod 3 div 2",
+            IntDiv(
+                Box::new(Mod(
+                    Box::new(BitNot(Box::new(Var("a".to_string())))),
+                    Box::new(Float(3.0)),
+                )),
+                Box::new(Float(2.0)),
+            ),
+        );
+
+        assert_matches!(
+            parse("a &"),
+            Err(UnexpectedEndOfInput { rule: "factor", .. })
+        );
+        assert_matches!(
+            parse("a mod"),
+            Err(UnexpectedEndOfInput { rule: "factor", .. })
+        );
+    }
+
+    #[test]
+    fn power_parse() {
+        let var = |name: &str| Box::new(Var(name.to_string()));
+        check("x^2", Pow(var("x"), Box::new(Float(2.0))));
+        check("-x^2", Neg(Box::new(Pow(var("x"), Box::new(Float(2.0))))));
+        check("a^b^c", Pow(var("a"), Box::new(Pow(var("b"), var("c")))));
+        check("2^-x", Pow(Box::new(Float(2.0)), Box::new(Neg(var("x")))));
+        check(
+            "n!^2",
+            Pow(Box::new(Factorial(var("n"))), Box::new(Float(2.0))),
+        );
+        check(
+            "2 * x^2",
+            Mul(
+                Box::new(Float(2.0)),
+                Box::new(Pow(var("x"), Box::new(Float(2.0)))),
+            ),
+        );
+    }
+
+    #[test]
+    fn compare_parse() {
+        let var = |name: &str| Box::new(Var(name.to_string()));
+        check("a < b", Lt(var("a"), var("b")));
+        check(
+            "a + 1 >= b | c",
+            Ge(
+                Box::new(Add(var("a"), Box::new(Float(1.0)))),
+                Box::new(BitOr(var("b"), var("c"))),
+            ),
+        );
+        check(
+            "(a == b) != c",
+            Ne(Box::new(Eq(var("a"), var("b"))), var("c")),
+        );
+        assert_matches!(
+            parse("a < b < c"),
+            Err(UnexpectedToken {
+                token: Token::Less,
+                rule: "expr",
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn unicode_parse() {
+        let var = |name: &str| Box::new(Var(name.to_string()));
+        check("a × b", Mul(var("a"), var("b")));
+        check("a ÷ b", Div(var("a"), var("b")));
+        check("a \u{2212} b", Sub(var("a"), var("b")));
+        check("\u{2212}a", Neg(var("a")));
+        check("a ≤ b", Le(var("a"), var("b")));
+        check("a ≥ b", Ge(var("a"), var("b")));
+        check("a ≠ b", Ne(var("a"), var("b")));
+        check("√x", Sqrt(var("x")));
+        check("√x²", Sqrt(Box::new(Pow(var("x"), Box::new(Float(2.0))))));
+        check("x⁻¹", Pow(var("x"), Box::new(Float(-1.0))));
+        check("π", Float(std::f64::consts::PI));
+
+        let juxtaposed = ParserConfig::new().with_juxtaposition(Juxtaposition::Multiply);
+        assert_eq!(
+            parse_with("2π r²", &juxtaposed),
+            Ok(Mul(
+                Box::new(Mul(
+                    Box::new(Float(2.0)),
+                    Box::new(Float(std::f64::consts::PI))
+                )),
+                Box::new(Pow(var("r"), Box::new(Float(2.0)))),
+            ))
+        );
+        assert_eq!(
+            parse_with("3√x", &juxtaposed),
+            Ok(Mul(Box::new(Float(3.0)), Box::new(Sqrt(var("x")))))
+        );
+
+        let strict = ParserConfig::new().with_unicode(false);
+        assert_eq!(parse_with("π", &strict), Ok(Var("π".to_string())));
+        assert_matches!(
+            parse_with("√x", &strict),
+            Err(UnexpectedToken {
+                token: Token::Error,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn abs_parse() {
+        let bars = ParserConfig::new().with_abs_bars(true);
+        let check_with = |text: &str, expected: ExprTree| {
+            assert_eq!(parse_with(text, &bars), Ok(expected));
+        };
+        let var = |name: &str| Box::new(Var(name.to_string()));
+
+        check_with(
+            "|a - b| / |b|",
+            Div(
+                Box::new(Abs(Box::new(Sub(var("a"), var("b"))))),
+                Box::new(Abs(var("b"))),
+            ),
+        );
+        check_with(
+            "||a| - |b||",
+            Abs(Box::new(Sub(
+                Box::new(Abs(var("a"))),
+                Box::new(Abs(var("b"))),
+            ))),
+        );
+        check_with(
+            "|a| | |b|",
+            BitOr(Box::new(Abs(var("a"))), Box::new(Abs(var("b")))),
+        );
+        check_with(
+            "|(a | b)|!",
+            Factorial(Box::new(Abs(Box::new(BitOr(var("a"), var("b")))))),
+        );
+        check_with("-|a|", Neg(Box::new(Abs(var("a")))));
+
+        assert_matches!(
+            parse_with("|a", &bars),
+            Err(UnexpectedEndOfInput { rule: "factor", .. })
+        );
+        assert_matches!(
+            parse_with("|a)", &bars),
+            Err(UnexpectedToken {
+                token: Token::Close,
+                rule: "factor",
+                ..
+            })
+        );
+        assert_matches!(
+            parse("|"),
+            Err(UnexpectedToken {
+                token: Token::Pipe,
+                rule: "factor",
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn postfix_parse() {
+        check("5!", Factorial(Box::new(Float(5.0))));
+        check("15%", Percent(Box::new(Float(15.0))));
+        check(
+            "price * 15%",
+            Mul(
+                Box::new(Var("price".to_string())),
+                Box::new(Percent(Box::new(Float(15.0)))),
+            ),
+        );
+        check(
+            "-n!",
+            Neg(Box::new(Factorial(Box::new(Var("n".to_string()))))),
+        );
+        check(
+            "(n - 1)!%",
+            Percent(Box::new(Factorial(Box::new(Sub(
+                Box::new(Var("n".to_string())),
+                Box::new(Float(1.0)),
+            ))))),
+        );
+        check(
+            "n!!",
+            Factorial(Box::new(Factorial(Box::new(Var("n".to_string()))))),
+        );
+
+        check(
+            "n != 3",
+            Ne(Box::new(Var("n".to_string())), Box::new(Float(3.0))),
+        );
+        assert_matches!(
+            parse("!"),
+            Err(UnexpectedToken {
+                token: Token::Bang,
+                rule: "factor",
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn juxtaposition_parse() {
+        let multiply = ParserConfig::new().with_juxtaposition(Juxtaposition::Multiply);
+        let tight = ParserConfig::new().with_juxtaposition(Juxtaposition::MultiplyTight);
+        let check_with = |config: &ParserConfig, text: &str, expected: &str| {
+            assert_eq!(parse_with(text, config).unwrap().to_string(), expected);
+        };
+
+        check_with(&multiply, "2x", "2 * x");
+        check_with(&multiply, "2pi r", "2 * pi * r");
+        check_with(&multiply, "3(x+1)", "3 * (x + 1)");
+        check_with(&multiply, "(a)(b)", "a * b");
+        check_with(&multiply, "1/2x", "1 / 2 * x");
+        check_with(&multiply, "2 -x", "2 - x");
+        check_with(&multiply, "-2x", "-2 * x");
+
+        check_with(&tight, "2x", "2 * x");
+        check_with(&tight, "1/2x", "1 / (2 * x)");
+        check_with(&tight, "1/2x y * z", "1 / (2 * x * y) * z");
+        check_with(&tight, "3(x+1)/2", "3 * (x + 1) / 2");
+
+        assert_matches!(
+            parse("2x"),
+            Err(UnexpectedToken {
+                token: Token::Symbol(_),
+                rule: "expr",
+                ..
+            })
+        );
+        assert_matches!(
+            parse_with("2x(", &multiply),
+            Err(UnexpectedEndOfInput { rule: "factor", .. })
+        );
+    }
+
+    #[test]
+    fn logical_parse() {
+        let var = |name: &str| Box::new(Var(name.to_string()));
+        check(
+            "a or not b and c = 1",
+            Or(
+                var("a"),
+                Box::new(And(
+                    Box::new(Not(var("b"))),
+                    Box::new(Eq(var("c"), Box::new(Float(1.0)))),
+                )),
+            ),
+        );
+        check("not -x", Not(Box::new(Neg(var("x")))));
+        assert_matches!(
+            parse("-not"),
+            Err(UnexpectedToken {
+                token: Token::Not,
+                rule: "factor",
+                ..
+            })
+        );
+        assert_matches!(
+            parse("not not"),
+            Err(UnexpectedToken {
+                token: Token::Not,
+                rule: "factor",
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn call_parse() {
+        check("f()", Call("f".to_string(), vec![]));
+        check(
+            "max(a, b + 1)",
+            Call(
+                "max".to_string(),
+                vec![
+                    Var("a".to_string()),
+                    Add(Box::new(Var("b".to_string())), Box::new(Float(1.0))),
+                ],
+            ),
+        );
+        check(
+            "-f(x)^2",
+            Neg(Box::new(Pow(
+                Box::new(Call("f".to_string(), vec![Var("x".to_string())])),
+                Box::new(Float(2.0)),
+            ))),
+        );
+
+        let multiply = ParserConfig::new()
+            .with_juxtaposition(Juxtaposition::Multiply)
+            .with_function("sin");
+        assert_eq!(
+            parse_with("x(a) sin(a)", &multiply).unwrap().to_string(),
+            "x * a * sin(a)"
+        );
+
+        assert_matches!(
+            parse("f(a b"),
+            Err(UnexpectedToken {
+                token: Token::Symbol(_),
+                rule: "call",
+                ..
+            })
+        );
+        assert_matches!(
+            parse("f(a,"),
+            Err(UnexpectedEndOfInput { rule: "factor", .. })
+        );
+        assert_matches!(parse("f(a"), Err(UnexpectedEndOfInput { rule: "call", .. }));
+    }
+
+    #[test]
+    fn operator_parse() {
+        let config = ParserConfig::new()
+            .with_infix("+/-", 60, Assoc::Left, Binary::Call("pm".to_string()))
+            .with_infix("..", 15, Assoc::NonAssoc, Binary::Call("range".to_string()))
+            .with_prefix("sgn", 5, Unary::Call("sgn".to_string()))
+            .with_postfix("'", 100, Unary::Call("prime".to_string()));
+        let check_with = |text: &str, expected: &str| {
+            assert_eq!(parse_with(text, &config).unwrap().to_string(), expected);
+        };
+
+        check_with("x +/- 2 * e", "pm(x, 2 * e)");
+        check_with("a + b +/- c", "pm(a + b, c)");
+        check_with("1..n+1", "range(1, n + 1)");
+        check_with("1..5 == r", "range(1, 5) == r");
+        check_with("sgn a < b", "sgn(a < b)");
+        check_with("f'^2", "prime(f) ^ 2");
+        check_with("sgnx + 1", "sgnx + 1");
+        check_with("nothing + 1", "nothing + 1");
+        assert_matches!(
+            parse_with("1..2..3", &config),
+            Err(UnexpectedToken {
+                token: Token::Operator(_),
+                rule: "expr",
+                ..
+            })
+        );
+
+        // Replacing operators changes the grammar
+        let config = ParserConfig::new()
+            .with_infix("^", 30, Assoc::Left, Binary::Node(Kind::BitXor))
+            .with_infix("**", 90, Assoc::Right, Binary::Node(Kind::Pow))
+            .with_prefix("-", 95, Unary::Node(Kind::Neg));
+        check_with_config(&config, "a ^ b & c", "a xor b & c");
+        check_with_config(&config, "-x ** 2", "(-x) ^ 2");
+        check_with_config(&config, "2 ** 3 ** 2", "2 ^ 3 ^ 2");
+    }
+
+    #[test]
+    #[should_panic(expected = "Add nodes take 2 operands, not 1")]
+    fn operator_arity() {
+        let _ = ParserConfig::new().with_prefix("+", 80, Unary::Node(Kind::Add));
+    }
+
+    fn check_with_config(config: &ParserConfig, text: &str, expected: &str) {
+        assert_eq!(parse_with(text, config).unwrap().to_string(), expected);
+    }
+
+    #[test]
+    fn path_parse() {
+        check("order", Var("order".to_string()));
+        check(
+            "order.total",
+            Path(vec!["order".to_string(), "total".to_string()]),
+        );
+        check(
+            "order.total * customer.discount",
+            Mul(
+                Box::new(Path(vec!["order".to_string(), "total".to_string()])),
+                Box::new(Path(vec!["customer".to_string(), "discount".to_string()])),
+            ),
+        );
+        check(
+            "-a.b.c",
+            Neg(Box::new(Path(vec![
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string(),
+            ]))),
+        );
     }
 }
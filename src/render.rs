@@ -0,0 +1,136 @@
+//! Helpers shared by the LaTeX and MathML renderers, which write
+//! expressions in math notation with the same names, binding powers,
+//! and parentheses.
+
+use crate::tree::ExprTree;
+
+/// Greek letters, by the name of their LaTeX command.
+pub(crate) const GREEK: [(&str, char); 40] = [
+    ("alpha", 'α'),
+    ("beta", 'β'),
+    ("gamma", 'γ'),
+    ("delta", 'δ'),
+    ("epsilon", 'ϵ'),
+    ("zeta", 'ζ'),
+    ("eta", 'η'),
+    ("theta", 'θ'),
+    ("iota", 'ι'),
+    ("kappa", 'κ'),
+    ("lambda", 'λ'),
+    ("mu", 'μ'),
+    ("nu", 'ν'),
+    ("xi", 'ξ'),
+    ("pi", 'π'),
+    ("rho", 'ρ'),
+    ("sigma", 'σ'),
+    ("tau", 'τ'),
+    ("upsilon", 'υ'),
+    ("phi", 'ϕ'),
+    ("chi", 'χ'),
+    ("psi", 'ψ'),
+    ("omega", 'ω'),
+    ("vartheta", 'ϑ'),
+    ("varpi", 'ϖ'),
+    ("varrho", 'ϱ'),
+    ("varsigma", 'ς'),
+    ("varphi", 'φ'),
+    ("Gamma", 'Γ'),
+    ("Delta", 'Δ'),
+    ("Theta", 'Θ'),
+    ("Lambda", 'Λ'),
+    ("Xi", 'Ξ'),
+    ("Pi", 'Π'),
+    ("Sigma", 'Σ'),
+    ("Upsilon", 'Υ'),
+    ("Phi", 'Φ'),
+    ("Psi", 'Ψ'),
+    ("Omega", 'Ω'),
+    ("digamma", 'ϝ'),
+];
+
+/// Functions with both a LaTeX command and a Content MathML element
+/// of the same name.
+pub(crate) const FUNCTIONS: [&str; 19] = [
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "exp", "log", "ln", "max", "min", "gcd",
+];
+
+/// Get the Greek letter with a name, if there is one.
+pub(crate) fn greek(name: &str) -> Option<char> {
+    GREEK
+        .iter()
+        .find(|(greek, _)| *greek == name)
+        .map(|&(_, letter)| letter)
+}
+
+/// Split a name at the first underscore into a base and a subscript,
+/// if neither is empty.
+pub(crate) fn subscript(name: &str) -> Option<(&str, &str)> {
+    name.split_once('_')
+        .filter(|(base, sub)| !base.is_empty() && !sub.is_empty())
+}
+
+/// Binding power of a node as written in math notation, where
+/// operands binding less tight than their operator need parentheses.
+/// Fractions and powers are written two-dimensionally, so only the
+/// base of a power can need parentheses.
+pub(crate) fn power(tree: &ExprTree) -> u32 {
+    match tree {
+        ExprTree::Or(..) => 1,
+        ExprTree::And(..) => 2,
+        ExprTree::Not(..) => 3,
+        ExprTree::Eq(..)
+        | ExprTree::Ne(..)
+        | ExprTree::Lt(..)
+        | ExprTree::Le(..)
+        | ExprTree::Gt(..)
+        | ExprTree::Ge(..) => COMPARISON,
+        ExprTree::BitOr(..) => 5,
+        ExprTree::BitXor(..) => 6,
+        ExprTree::BitAnd(..) => 7,
+        ExprTree::Shl(..) | ExprTree::Shr(..) => 8,
+        ExprTree::Add(..) | ExprTree::Sub(..) => 9,
+        ExprTree::Mul(..) | ExprTree::Mod(..) | ExprTree::IntDiv(..) => 10,
+        ExprTree::Neg(..) | ExprTree::BitNot(..) => 11,
+        ExprTree::Float(..) if negative(tree) => 11,
+        ExprTree::Div(..) => 12,
+        ExprTree::Pow(..) => 13,
+        ExprTree::Factorial(..) | ExprTree::Percent(..) => 14,
+        _ => 15,
+    }
+}
+
+/// Binding power of the comparisons, which do not associate.
+const COMPARISON: u32 = 4;
+
+/// Check if a node is written starting with a minus sign.
+pub(crate) fn negative(tree: &ExprTree) -> bool {
+    match tree {
+        ExprTree::Neg(_) => true,
+        ExprTree::Float(num) => num.is_sign_negative() && *num != 0.0 && !num.is_nan(),
+        _ => false,
+    }
+}
+
+/// Check which operands of an infix operator need parentheses.
+///
+/// The operators are left-associative, so the left operand can bind
+/// as tight as the operator, except for comparisons, which do not
+/// associate. An operand starting with a minus sign is put within
+/// parentheses on the right, as in `a - (-b)`.
+pub(crate) fn infix_parens(tree: &ExprTree, lhs: &ExprTree, rhs: &ExprTree) -> (bool, bool) {
+    let right = power(tree);
+    let left = if right == COMPARISON {
+        right + 1
+    } else {
+        right
+    };
+    (power(lhs) < left, power(rhs) <= right || negative(rhs))
+}
+
+/// Check if the base of a power needs parentheses. A power as the
+/// base would give a double superscript, and a minus sign would
+/// apply to the whole power.
+pub(crate) fn base_parens(tree: &ExprTree, base: &ExprTree) -> bool {
+    power(base) <= power(tree) || negative(base)
+}
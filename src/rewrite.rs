@@ -0,0 +1,683 @@
+//! Rewriting of expression trees using rules.
+//!
+//! A rule is written in the expression syntax as `pattern =>
+//! replacement`, optionally followed by `when guard`. Names starting
+//! with `?`, like `?a`, are metavariables that match any subtree,
+//! and a metavariable used more than once has to match equal
+//! subtrees. For example, `?a * ?x + ?b * ?x => (?a + ?b) * ?x`
+//! factors out a common operand.
+//!
+//! Patterns are matched modulo commutativity and associativity, so
+//! the pattern `?x * 1` also matches `1 * y` and `(y * 1) * z`. When
+//! the pattern of a rule is a sum, product, or other associative
+//! operator, it can match some of the operands of a longer chain, and
+//! the operands that are not matched are kept, so `?x * 1 => ?x`
+//! rewrites `y * 1 * z` to `y * z`.
+//!
+//! A guard is an expression using the metavariables, and the rule is
+//! only applied if the guard evaluates to a non-zero value after the
+//! metavariables are replaced with the subtrees they matched. Since
+//! the guard is evaluated without any variables, `?x / ?x => 1 when
+//! ?x != 0` is only applied when `?x` matched a constant. Guards
+//! that cannot be written as expressions can be added as predicates
+//! using [`Rule::with_predicate`].
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::parse;
+//! # use expr::rewrite::Rewriter;
+//! let rewriter = Rewriter::from_rules(
+//!     "?x * 1 => ?x
+//!      ?a * ?x + ?b * ?x => (?a + ?b) * ?x",
+//! )
+//! .unwrap();
+//! let tree = rewriter.rewrite(&parse("2 * y + z * 1 + y * 3").unwrap()).unwrap();
+//! assert_eq!(tree.to_string(), "(2 + 3) * y + z");
+//! ```
+
+use crate::parser::{self, parse};
+use crate::tree::ExprTree;
+use crate::visit::{fold_operands, Fold, Folded};
+use std::collections::HashMap;
+use std::mem::discriminant;
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Error {
+    /// A pattern, replacement, or guard could not be parsed.
+    Parse(parser::Error),
+    /// A rule has no `=>` between the pattern and the replacement.
+    NoArrow(String),
+    /// A metavariable is used in the replacement or the guard but not
+    /// in the pattern.
+    Unbound(String),
+    /// Rewriting did not reach a tree where no rule applies within
+    /// the step limit. The tree is the result of the last step.
+    StepLimit { limit: usize, tree: ExprTree },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::convert::From<parser::Error> for Error {
+    fn from(error: parser::Error) -> Error {
+        Error::Parse(error)
+    }
+}
+
+/// Subtrees bound to the metavariables of a pattern.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Bindings {
+    values: Vec<(String, ExprTree)>,
+}
+
+impl Bindings {
+    /// Get the subtree bound to a metavariable, which can be named
+    /// with or without the leading `?`.
+    pub fn get(&self, name: &str) -> Option<&ExprTree> {
+        let name = name.strip_prefix('?').unwrap_or(name);
+        self.values
+            .iter()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, tree)| tree)
+    }
+}
+
+/// Predicate deciding if a rule applies to the subtrees bound by its
+/// pattern.
+type Predicate = Box<dyn Fn(&Bindings) -> bool>;
+
+/// Rule replacing subtrees matching a pattern.
+pub struct Rule {
+    pattern: ExprTree,
+    replacement: ExprTree,
+    guard: Option<ExprTree>,
+    predicates: Vec<Predicate>,
+}
+
+impl Rule {
+    /// Create a rule from a pattern and a replacement, where
+    /// metavariables are variables with names starting with `?`.
+    pub fn new(pattern: ExprTree, replacement: ExprTree) -> Result<Rule> {
+        check_bound(&pattern, &replacement)?;
+        Ok(Rule {
+            pattern,
+            replacement,
+            guard: None,
+            predicates: Vec::new(),
+        })
+    }
+
+    /// Parse a rule written as `pattern => replacement`, optionally
+    /// followed by `when guard`.
+    pub fn parse(text: &str) -> Result<Rule> {
+        let (pattern, rest) =
+            split_at_word(text, "=>").ok_or_else(|| Error::NoArrow(text.to_string()))?;
+        let (replacement, guard) = match split_at_word(rest, "when") {
+            Some((replacement, guard)) => (replacement, Some(guard)),
+            None => (rest, None),
+        };
+        let mut rule = Rule::new(
+            parse(&metavariables(pattern))?,
+            parse(&metavariables(replacement))?,
+        )?;
+        if let Some(guard) = guard {
+            let guard = parse(&metavariables(guard))?;
+            check_bound(&rule.pattern, &guard)?;
+            rule.guard = Some(guard);
+        }
+        Ok(rule)
+    }
+
+    /// Add a predicate that has to hold for the rule to apply.
+    pub fn with_predicate<F>(mut self, predicate: F) -> Rule
+    where
+        F: Fn(&Bindings) -> bool + 'static,
+    {
+        self.predicates.push(Box::new(predicate));
+        self
+    }
+
+    /// Apply the rule at the root of a tree, giving the rewritten tree
+    /// if the pattern matches and the guards hold.
+    pub fn apply(&self, tree: &ExprTree) -> Option<ExprTree> {
+        let mut result = None;
+        let mut bindings = Bindings::default();
+        if is_associative(&self.pattern) && same_kind(&self.pattern, tree) {
+            // The pattern can match a part of the chain, and the
+            // operands that are not matched are kept.
+            let (patterns, terms) = (flatten(&self.pattern), flatten(tree));
+            let mut used = vec![false; terms.len()];
+            unify_terms(
+                &patterns,
+                &terms,
+                &mut used,
+                &mut bindings,
+                &mut |bindings, used| {
+                    if !self.accepts(bindings) {
+                        return false;
+                    }
+                    let replacement = instantiate(&self.replacement, bindings);
+                    let rest = terms.iter().zip(used).filter(|(_, used)| !**used);
+                    result = Some(rest.fold(replacement, |lhs, (rhs, _)| {
+                        tree.with_operands(vec![lhs, (*rhs).clone()])
+                    }));
+                    true
+                },
+            );
+        } else {
+            unify(&self.pattern, tree, &mut bindings, &mut |bindings| {
+                if !self.accepts(bindings) {
+                    return false;
+                }
+                result = Some(instantiate(&self.replacement, bindings));
+                true
+            });
+        }
+        result
+    }
+
+    /// Check if the guard and the predicates hold for the bindings.
+    fn accepts(&self, bindings: &Bindings) -> bool {
+        let guard = self.guard.as_ref().map_or(true, |guard| {
+            instantiate(guard, bindings)
+                .eval(&HashMap::<String, f64>::new())
+                .is_ok_and(|value| value != 0.0)
+        });
+        guard && self.predicates.iter().all(|predicate| predicate(bindings))
+    }
+}
+
+/// Rules applied repeatedly until none of them applies.
+pub struct Rewriter {
+    rules: Vec<Rule>,
+    limit: usize,
+}
+
+impl Default for Rewriter {
+    fn default() -> Rewriter {
+        Rewriter {
+            rules: Vec::new(),
+            limit: 1000,
+        }
+    }
+}
+
+impl Rewriter {
+    pub fn new() -> Rewriter {
+        Rewriter::default()
+    }
+
+    /// Create a rewriter from rules written one per line. Empty lines
+    /// and lines starting with `#` are skipped.
+    pub fn from_rules(text: &str) -> Result<Rewriter> {
+        let mut rewriter = Rewriter::new();
+        for line in text.lines().map(str::trim) {
+            if !line.is_empty() && !line.starts_with('#') {
+                rewriter.rules.push(Rule::parse(line)?);
+            }
+        }
+        Ok(rewriter)
+    }
+
+    /// Add a rule, which is tried after the rules already added.
+    pub fn with_rule(mut self, rule: Rule) -> Rewriter {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Set the maximum number of rewrite steps, which is 1000 by
+    /// default.
+    pub fn with_step_limit(mut self, limit: usize) -> Rewriter {
+        self.limit = limit;
+        self
+    }
+
+    /// Rewrite a tree until no rule applies.
+    ///
+    /// Each step applies the first rule that matches to the first
+    /// subtree it matches, where the operands of a node are tried
+    /// before the node itself, so the tree is simplified from the
+    /// leaves up.
+    pub fn rewrite(&self, tree: &ExprTree) -> Result<ExprTree> {
+        let mut tree = tree.clone();
+        for _ in 0..self.limit {
+            match self.step(&tree) {
+                Some(next) => tree = next,
+                None => return Ok(tree),
+            }
+        }
+        match self.step(&tree) {
+            Some(_) => Err(Error::StepLimit {
+                limit: self.limit,
+                tree,
+            }),
+            None => Ok(tree),
+        }
+    }
+
+    /// Apply one rule to the first subtree it matches.
+    fn step(&self, tree: &ExprTree) -> Option<ExprTree> {
+        let operands: Vec<&ExprTree> = tree.operands().collect();
+        for (index, operand) in operands.iter().enumerate() {
+            if let Some(rewritten) = self.step(operand) {
+                let mut operands: Vec<ExprTree> =
+                    operands.iter().map(|&operand| operand.clone()).collect();
+                operands[index] = rewritten;
+                return Some(tree.with_operands(operands));
+            }
+        }
+        self.rules.iter().find_map(|rule| rule.apply(tree))
+    }
+}
+
+/// Get the name of a metavariable, without the leading `?`.
+fn metavariable(tree: &ExprTree) -> Option<&str> {
+    match tree {
+        ExprTree::Var(name) if name.len() > 1 => name.strip_prefix('?'),
+        _ => None,
+    }
+}
+
+/// Check that every metavariable in a tree is bound by the pattern.
+fn check_bound(pattern: &ExprTree, tree: &ExprTree) -> Result<()> {
+    let bound = pattern.free_variables();
+    match tree
+        .free_variables()
+        .into_iter()
+        .find(|name| name.starts_with('?') && !bound.contains(name))
+    {
+        Some(name) => Err(Error::Unbound(name)),
+        None => Ok(()),
+    }
+}
+
+/// Operators where the operands can be swapped.
+fn is_commutative(tree: &ExprTree) -> bool {
+    is_associative(tree) || matches!(tree, ExprTree::Eq(..) | ExprTree::Ne(..))
+}
+
+/// Operators where the operands can be swapped and regrouped.
+fn is_associative(tree: &ExprTree) -> bool {
+    matches!(
+        tree,
+        ExprTree::Add(..)
+            | ExprTree::Mul(..)
+            | ExprTree::BitAnd(..)
+            | ExprTree::BitOr(..)
+            | ExprTree::BitXor(..)
+            | ExprTree::And(..)
+            | ExprTree::Or(..)
+    )
+}
+
+/// Get the operands of a chain of the same associative operator, so
+/// `(a + b) + c` gives `a`, `b`, and `c`.
+fn flatten(tree: &ExprTree) -> Vec<&ExprTree> {
+    let mut terms = Vec::new();
+    for operand in tree.operands() {
+        if discriminant(operand) == discriminant(tree) {
+            terms.extend(flatten(operand));
+        } else {
+            terms.push(operand);
+        }
+    }
+    terms
+}
+
+/// Check if a pattern node, which is not a metavariable, can match a
+/// tree node, disregarding the operands.
+fn same_kind(pattern: &ExprTree, tree: &ExprTree) -> bool {
+    match (pattern, tree) {
+        (ExprTree::Call(lhs, _), ExprTree::Call(rhs, _)) => lhs == rhs,
+        (pattern, tree) if pattern.operands().len() == 0 => {
+            pattern == tree || number(pattern).is_some() && number(pattern) == number(tree)
+        }
+        (pattern, tree) => discriminant(pattern) == discriminant(tree),
+    }
+}
+
+/// Get the value of a number literal.
+fn number(tree: &ExprTree) -> Option<f64> {
+    match tree {
+        ExprTree::Float(num) => Some(*num),
+        ExprTree::Integer(num, _) => Some(*num as f64),
+        _ => None,
+    }
+}
+
+/// Match a pattern against a tree, calling `next` for each way the
+/// pattern matches until it returns `true`.
+///
+/// Matching is done by backtracking: when `next` returns `false`,
+/// the bindings are restored and the next way of matching is tried.
+/// The bindings are only kept if the match succeeds.
+fn unify(
+    pattern: &ExprTree,
+    tree: &ExprTree,
+    bindings: &mut Bindings,
+    next: &mut dyn FnMut(&mut Bindings) -> bool,
+) -> bool {
+    if let Some(name) = metavariable(pattern) {
+        return match bindings.get(name) {
+            Some(bound) => bound == tree && next(bindings),
+            None => {
+                bindings.values.push((name.to_string(), tree.clone()));
+                let matched = next(bindings);
+                if !matched {
+                    bindings.values.pop();
+                }
+                matched
+            }
+        };
+    }
+    if !same_kind(pattern, tree) {
+        return false;
+    }
+    if is_associative(pattern) {
+        let (patterns, terms) = (flatten(pattern), flatten(tree));
+        let mut used = vec![false; terms.len()];
+        return patterns.len() == terms.len()
+            && unify_terms(
+                &patterns,
+                &terms,
+                &mut used,
+                bindings,
+                &mut |bindings, _| next(bindings),
+            );
+    }
+    let patterns: Vec<&ExprTree> = pattern.operands().collect();
+    let trees: Vec<&ExprTree> = tree.operands().collect();
+    if patterns.len() != trees.len() {
+        return false;
+    }
+    unify_all(&patterns, &trees, bindings, next)
+        || is_commutative(pattern) && unify_all(&[patterns[1], patterns[0]], &trees, bindings, next)
+}
+
+/// Match patterns against trees, pairwise.
+fn unify_all(
+    patterns: &[&ExprTree],
+    trees: &[&ExprTree],
+    bindings: &mut Bindings,
+    next: &mut dyn FnMut(&mut Bindings) -> bool,
+) -> bool {
+    match (patterns.split_first(), trees.split_first()) {
+        (Some((pattern, patterns)), Some((tree, trees))) => {
+            unify(pattern, tree, bindings, &mut |bindings| {
+                unify_all(patterns, trees, bindings, next)
+            })
+        }
+        _ => next(bindings),
+    }
+}
+
+/// Match each pattern against a different term, in any order. The
+/// terms matched are marked as used, and `next` is given the marks.
+fn unify_terms(
+    patterns: &[&ExprTree],
+    terms: &[&ExprTree],
+    used: &mut Vec<bool>,
+    bindings: &mut Bindings,
+    next: &mut dyn FnMut(&mut Bindings, &[bool]) -> bool,
+) -> bool {
+    let (pattern, patterns) = match patterns.split_first() {
+        Some(split) => split,
+        None => return next(bindings, used),
+    };
+    for index in 0..terms.len() {
+        if used[index] {
+            continue;
+        }
+        used[index] = true;
+        if unify(pattern, terms[index], bindings, &mut |bindings| {
+            unify_terms(patterns, terms, used, bindings, next)
+        }) {
+            return true;
+        }
+        used[index] = false;
+    }
+    false
+}
+
+/// Replace the metavariables of a tree with the subtrees bound to
+/// them.
+fn instantiate(tree: &ExprTree, bindings: &Bindings) -> ExprTree {
+    Instantiate { bindings }.fold(tree)
+}
+
+/// Fold replacing metavariables with the subtrees bound to them.
+struct Instantiate<'a> {
+    bindings: &'a Bindings,
+}
+
+impl Fold for Instantiate<'_> {
+    type Output = ExprTree;
+
+    fn fold(&mut self, tree: &ExprTree) -> ExprTree {
+        match metavariable(tree).and_then(|name| self.bindings.get(name)) {
+            Some(bound) => bound.clone(),
+            None => fold_operands(self, tree),
+        }
+    }
+
+    fn fold_node(&mut self, tree: &ExprTree, operands: Folded<ExprTree>) -> ExprTree {
+        tree.with_operands(operands)
+    }
+}
+
+/// Quote the metavariables of a rule, so that `?a` is parsed as the
+/// variable `` `?a` ``.
+fn metavariables(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '`' => {
+                // Copy quoted names as they are.
+                result.push(ch);
+                while let Some(ch) = chars.next() {
+                    result.push(ch);
+                    match ch {
+                        '\\' => result.extend(chars.next()),
+                        '`' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '?' if chars
+                .peek()
+                .is_some_and(|ch| ch.is_alphabetic() || *ch == '_') =>
+            {
+                result.push_str("`?");
+                while let Some(&ch) = chars.peek() {
+                    if !(ch.is_alphanumeric() || ch == '_') {
+                        break;
+                    }
+                    result.push(ch);
+                    chars.next();
+                }
+                result.push('`');
+            }
+            ch => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Split a rule at the first occurrence of a separator that is not
+/// within a quoted name. A separator that is a word, like `when`, has
+/// to be a separate word.
+fn split_at_word<'a>(text: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+    let is_word = |ch: char| ch.is_alphanumeric() || ch == '_';
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, ch) in text.char_indices() {
+        if quoted {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '`' => quoted = false,
+                _ => {}
+            }
+            continue;
+        }
+        if ch == '`' {
+            quoted = true;
+            continue;
+        }
+        let (before, after) = (&text[..index], &text[index..]);
+        let rest = match after.strip_prefix(separator) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let embedded = separator.starts_with(is_word)
+            && (before.ends_with(is_word) || rest.starts_with(is_word));
+        if !embedded {
+            return Some((before, rest));
+        }
+    }
+    None
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(error) => write!(f, "{}", error),
+            Error::NoArrow(rule) => write!(f, "rule '{}' has no '=>'", rule),
+            Error::Unbound(name) => write!(f, "metavariable '{}' is not in the pattern", name),
+            Error::StepLimit { limit, .. } => {
+                write!(f, "rewriting did not finish within {} steps", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            Error::Parse(_) => "parser error",
+            Error::NoArrow(_) => "rule without arrow",
+            Error::Unbound(_) => "unbound metavariable",
+            Error::StepLimit { .. } => "step limit reached",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(rules: &str, text: &str) -> String {
+        let rewriter = Rewriter::from_rules(rules).unwrap();
+        rewriter.rewrite(&parse(text).unwrap()).unwrap().to_string()
+    }
+
+    #[test]
+    fn rules() {
+        let rule = Rule::parse("?x * 1 => ?x").unwrap();
+        assert_eq!(rule.pattern, parse("`?x` * 1").unwrap());
+        assert_eq!(
+            rule.apply(&parse("1 * (a + b)").unwrap()),
+            parse("a + b").ok()
+        );
+        assert_eq!(rule.apply(&parse("a * 2").unwrap()), None);
+
+        let rule = Rule::parse("`a when b` => 1 when 1 > 0").unwrap();
+        assert_eq!(rule.pattern, ExprTree::Var("a when b".to_string()));
+        assert!(Rule::parse("?x + 1 => ?x when ?x > 0").is_ok());
+        assert!(Rule::parse("?x + 1 => ?x whenever").is_err());
+        assert_eq!(
+            Rule::parse("?x + 1").err(),
+            Some(Error::NoArrow("?x + 1".to_string()))
+        );
+        assert_eq!(
+            Rule::parse("?x => ?y").err(),
+            Some(Error::Unbound("?y".to_string()))
+        );
+        assert!(matches!(Rule::parse("?x => (?x"), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn matching() {
+        // Repeated metavariables match equal subtrees.
+        assert_eq!(rewrite("?x - ?x => 0", "(a + b) - (a + b)"), "0");
+        assert_eq!(
+            rewrite("?x - ?x => 0", "(a + b) - (b + a)"),
+            "a + b - (b + a)"
+        );
+
+        // Commutative and associative operators.
+        assert_eq!(rewrite("?x * 1 => ?x", "1 * y"), "y");
+        assert_eq!(rewrite("?x * 1 => ?x", "y * 1 * z"), "y * z");
+        assert_eq!(rewrite("?x * 1 => ?x", "y * (1 * z)"), "y * z");
+        assert_eq!(rewrite("?x == ?x => 1", "f(a) == f(a)"), "1");
+        assert_eq!(rewrite("?a - 0 => ?a", "0 - a"), "0 - a");
+        assert_eq!(
+            rewrite(
+                "?a * ?x + ?b * ?x => (?a + ?b) * ?x",
+                "2 * y + 3 * z + y * 4"
+            ),
+            "(2 + 4) * y + 3 * z"
+        );
+        assert_eq!(
+            rewrite("(?a + ?b) * 2 => ?a * 2 + ?b * 2", "(x + y) * 2"),
+            "x * 2 + y * 2"
+        );
+        assert_eq!(
+            rewrite("(?a + ?b) * 2 => ?a * 2 + ?b * 2", "(x + y + z) * 2"),
+            "(x + y + z) * 2"
+        );
+
+        // Functions, with and without metavariables as arguments.
+        assert_eq!(
+            rewrite(
+                "sin(?x) ^ 2 + cos(?x) ^ 2 => 1",
+                "cos(t) ^ 2 + sin(t) ^ 2 + 1"
+            ),
+            "1 + 1"
+        );
+        assert_eq!(rewrite("f(?x) => ?x", "f(1, 2)"), "f(1, 2)");
+    }
+
+    #[test]
+    fn fixpoint() {
+        let rules = "
+            # Identities
+            ?x * 1 => ?x
+            ?x + 0 => ?x
+            ?x * 0 => 0
+        ";
+        assert_eq!(rewrite(rules, "(a * 0 + b) * (1 + 0)"), "b");
+
+        let rewriter = Rewriter::from_rules("?a + ?b => ?b + ?a")
+            .unwrap()
+            .with_step_limit(5);
+        let error = rewriter.rewrite(&parse("x + y").unwrap()).unwrap_err();
+        assert_eq!(
+            error,
+            Error::StepLimit {
+                limit: 5,
+                tree: parse("y + x").unwrap()
+            }
+        );
+        assert_eq!(error.to_string(), "rewriting did not finish within 5 steps");
+    }
+
+    #[test]
+    fn guards() {
+        let rules = "?x / ?x => 1 when ?x != 0";
+        assert_eq!(rewrite(rules, "(2 + 3) / (2 + 3)"), "1");
+        assert_eq!(rewrite(rules, "(2 - 2) / (2 - 2)"), "(2 - 2) / (2 - 2)");
+        assert_eq!(rewrite(rules, "y / y"), "y / y");
+
+        let rule = Rule::parse("?x / ?x => 1")
+            .unwrap()
+            .with_predicate(|bindings| matches!(bindings.get("x"), Some(ExprTree::Var(_))));
+        let rewriter = Rewriter::new().with_rule(rule);
+        let rewritten = rewriter
+            .rewrite(&parse("y / y + f(y) / f(y)").unwrap())
+            .unwrap();
+        assert_eq!(rewritten.to_string(), "1 + f(y) / f(y)");
+    }
+}
@@ -0,0 +1,514 @@
+//! Reverse Polish notation of expression trees.
+//!
+//! In Reverse Polish notation, the operands of an operator come
+//! before it, so `10 + x * y` is written `10 x y * +` and no
+//! parentheses are needed. [`to_rpn`] converts a tree into the tokens
+//! of the notation, [`Rpn`] writes them as text, and [`parse`] reads
+//! the text back into a tree using the same [`Tokenizer`] as the
+//! infix parser.
+//!
+//! Operators are written using their usual tokens, except that
+//! negation, whose token is that of subtraction, is written `+/-`,
+//! like the change sign key of a calculator, and the absolute value,
+//! which has no token of its own, is written `|x|`. A call is
+//! written as its name followed by the number of arguments in
+//! parentheses, like `a b max(2)`, and paths and cell references are
+//! written the same way as in infix notation.
+//!
+//! Negative numbers are written as their magnitude followed by `+/-`,
+//! since a minus sign would be read as subtraction, and infinity and
+//! NaN are written `#inf` and `#nan`, as in the
+//! [`sexpr`](crate::sexpr) module, so minus infinity is written
+//! `#inf +/-`.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::parse;
+//! # use expr::rpn::{self, Rpn};
+//! let tree = parse("10 + x * -y").unwrap();
+//! assert_eq!(Rpn(&tree).to_string(), "10 x y +/- * +");
+//! assert_eq!(rpn::parse("10 x y +/- * +").unwrap(), tree);
+//! ```
+
+use crate::spreadsheet::CellRef;
+use crate::tokens::{Token, Tokenizer};
+use crate::tree::ExprTree;
+use crate::visit::Visitor;
+use std::borrow::Cow;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// An operator or call with fewer operands on the stack than it
+    /// takes.
+    StackUnderflow(Token<'static>),
+    /// Operands left on the stack besides the result, like in `1 2`,
+    /// with the number of operands left over.
+    LeftoverOperands(usize),
+    EmptyInput,
+    UnexpectedToken(Token<'static>),
+    UnexpectedEndOfInput,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::StackUnderflow(token) => write!(f, "missing operand for '{}'", token),
+            Error::LeftoverOperands(count) => {
+                write!(f, "{} operands left over without an operator", count)
+            }
+            Error::EmptyInput => write!(f, "empty input"),
+            Error::UnexpectedToken(token) => write!(f, "unexpected token '{}'", token),
+            Error::UnexpectedEndOfInput => write!(f, "unexpected end of input"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            Error::StackUnderflow(_) => "missing operand",
+            Error::LeftoverOperands(_) => "operands left over",
+            Error::EmptyInput => "empty input",
+            Error::UnexpectedToken(_) => "unexpected token",
+            Error::UnexpectedEndOfInput => "unexpected end of input",
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Token of the negation operator.
+const NEG: &str = "+/-";
+
+/// Token of the absolute value operator.
+const ABS: &str = "|x|";
+
+/// Token of infinity.
+const INF: &str = "#inf";
+
+/// Token of NaN.
+const NAN: &str = "#nan";
+
+/// Convert an expression tree into tokens in Reverse Polish notation.
+///
+/// The tokens borrow the names from the tree.
+pub fn to_rpn(tree: &ExprTree) -> Vec<Token<'_>> {
+    let mut writer = Writer { tokens: Vec::new() };
+    writer.visit(tree);
+    writer.tokens
+}
+
+/// Visitor adding the tokens of each node after those of its operands.
+struct Writer<'a> {
+    tokens: Vec<Token<'a>>,
+}
+
+impl<'a> Visitor<'a> for Writer<'a> {
+    fn leave(&mut self, tree: &'a ExprTree) {
+        let name = |name: &'a str| Token::Symbol(Cow::Borrowed(name));
+        let token = match tree {
+            ExprTree::Var(var) => name(var),
+            ExprTree::Path(path) => {
+                for (index, member) in path.iter().enumerate() {
+                    if index > 0 {
+                        self.tokens.push(Token::Dot);
+                    }
+                    self.tokens.push(name(member));
+                }
+                return;
+            }
+            ExprTree::Float(num) => {
+                let magnitude = if num.is_nan() {
+                    Token::Operator(Cow::Borrowed(NAN))
+                } else if num.is_infinite() {
+                    Token::Operator(Cow::Borrowed(INF))
+                } else {
+                    Token::Float(num.abs())
+                };
+                if !num.is_sign_negative() || num.is_nan() {
+                    magnitude
+                } else {
+                    self.tokens.push(magnitude);
+                    Token::Operator(Cow::Borrowed(NEG))
+                }
+            }
+            ExprTree::Integer(num, radix) => Token::Integer(*num, *radix),
+            ExprTree::Call(function, args) => {
+                self.tokens.push(name(function));
+                self.tokens.push(Token::Open);
+                self.tokens.push(Token::Float(args.len() as f64));
+                Token::Close
+            }
+            ExprTree::Cell(sheet, cell) => {
+                if let Some(sheet) = sheet {
+                    self.tokens.push(Token::Sheet(Cow::Borrowed(sheet)));
+                }
+                Token::Cell(*cell)
+            }
+            ExprTree::Range(sheet, start, end) => {
+                if let Some(sheet) = sheet {
+                    self.tokens.push(Token::Sheet(Cow::Borrowed(sheet)));
+                }
+                self.tokens.push(Token::Cell(*start));
+                self.tokens.push(Token::Colon);
+                Token::Cell(*end)
+            }
+            ExprTree::Neg(_) => Token::Operator(Cow::Borrowed(NEG)),
+            ExprTree::Abs(_) => Token::Operator(Cow::Borrowed(ABS)),
+            ExprTree::BitNot(_) => Token::Tilde,
+            ExprTree::Not(_) => Token::Not,
+            ExprTree::Sqrt(_) => Token::Root,
+            ExprTree::Factorial(_) => Token::Bang,
+            ExprTree::Percent(_) => Token::Percent,
+            ExprTree::Add(..) => Token::Plus,
+            ExprTree::Sub(..) => Token::Minus,
+            ExprTree::Mul(..) => Token::Star,
+            ExprTree::Div(..) => Token::Slash,
+            ExprTree::Mod(..) => Token::Mod,
+            ExprTree::IntDiv(..) => Token::Div,
+            ExprTree::Pow(..) => Token::Power,
+            ExprTree::BitAnd(..) => Token::Ampersand,
+            ExprTree::BitOr(..) => Token::Pipe,
+            ExprTree::BitXor(..) => Token::Xor,
+            ExprTree::Shl(..) => Token::ShiftLeft,
+            ExprTree::Shr(..) => Token::ShiftRight,
+            ExprTree::Eq(..) => Token::Equal,
+            ExprTree::Ne(..) => Token::NotEqual,
+            ExprTree::Lt(..) => Token::Less,
+            ExprTree::Le(..) => Token::LessEqual,
+            ExprTree::Gt(..) => Token::Greater,
+            ExprTree::Ge(..) => Token::GreaterEqual,
+            ExprTree::And(..) => Token::And,
+            ExprTree::Or(..) => Token::Or,
+        };
+        self.tokens.push(token);
+    }
+}
+
+/// Display wrapper writing an expression tree in Reverse Polish
+/// notation, with the tokens separated by spaces.
+pub struct Rpn<'a>(pub &'a ExprTree);
+
+impl std::fmt::Display for Rpn<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut previous = None;
+        for token in to_rpn(self.0) {
+            // Paths, cell references, and the arity of a call are
+            // written without spaces, like `a.b`, `A1:B2`, and `f(2)`.
+            let joined = matches!(
+                (&previous, &token),
+                (_, Token::Dot | Token::Colon | Token::Open | Token::Close)
+                    | (
+                        Some(Token::Dot | Token::Colon | Token::Open | Token::Sheet(_)),
+                        _
+                    )
+            );
+            if previous.is_some() && !joined {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", token)?;
+            previous = Some(token);
+        }
+        Ok(())
+    }
+}
+
+/// Get the node for a unary operator token.
+fn unary(token: &Token<'_>) -> Option<fn(Box<ExprTree>) -> ExprTree> {
+    match token {
+        Token::Operator(symbol) if symbol == NEG => Some(ExprTree::Neg),
+        Token::Operator(symbol) if symbol == ABS => Some(ExprTree::Abs),
+        Token::Tilde => Some(ExprTree::BitNot),
+        Token::Not => Some(ExprTree::Not),
+        Token::Root => Some(ExprTree::Sqrt),
+        Token::Bang => Some(ExprTree::Factorial),
+        Token::Percent => Some(ExprTree::Percent),
+        _ => None,
+    }
+}
+
+/// Get the node for a binary operator token.
+#[allow(clippy::type_complexity)]
+fn binary(token: &Token<'_>) -> Option<fn(Box<ExprTree>, Box<ExprTree>) -> ExprTree> {
+    match token {
+        Token::Plus => Some(ExprTree::Add),
+        Token::Minus => Some(ExprTree::Sub),
+        Token::Star => Some(ExprTree::Mul),
+        Token::Slash => Some(ExprTree::Div),
+        Token::Mod => Some(ExprTree::Mod),
+        Token::Div => Some(ExprTree::IntDiv),
+        Token::Power => Some(ExprTree::Pow),
+        Token::Ampersand => Some(ExprTree::BitAnd),
+        Token::Pipe => Some(ExprTree::BitOr),
+        Token::Xor => Some(ExprTree::BitXor),
+        Token::ShiftLeft => Some(ExprTree::Shl),
+        Token::ShiftRight => Some(ExprTree::Shr),
+        Token::Equal => Some(ExprTree::Eq),
+        Token::NotEqual => Some(ExprTree::Ne),
+        Token::Less => Some(ExprTree::Lt),
+        Token::LessEqual => Some(ExprTree::Le),
+        Token::Greater => Some(ExprTree::Gt),
+        Token::GreaterEqual => Some(ExprTree::Ge),
+        Token::And => Some(ExprTree::And),
+        Token::Or => Some(ExprTree::Or),
+        _ => None,
+    }
+}
+
+/// Reader of expressions in Reverse Polish notation.
+#[derive(Debug, Clone, Default)]
+pub struct Reader {
+    cells: bool,
+}
+
+impl Reader {
+    pub fn new() -> Reader {
+        Reader::default()
+    }
+
+    /// Read spreadsheet cell references, like `A1`, `Sheet2!B2`, and
+    /// `A1:B3`, instead of variables named like cells.
+    pub fn with_cells(mut self, enable: bool) -> Reader {
+        self.cells = enable;
+        self
+    }
+
+    /// Parse an expression in Reverse Polish notation into a tree.
+    pub fn parse(&self, text: &str) -> Result<ExprTree> {
+        let operators = [
+            NEG.to_string(),
+            ABS.to_string(),
+            INF.to_string(),
+            NAN.to_string(),
+        ];
+        let mut tokens = Tokenizer::new(text)
+            .with_cells(self.cells)
+            .with_operators(&operators);
+        let mut stack = Vec::new();
+        while let Some(token) = tokens.next() {
+            let node = match token {
+                Token::Float(num) => ExprTree::Float(num),
+                Token::Integer(num, radix) => ExprTree::Integer(num, radix),
+                Token::Operator(symbol) if symbol == INF => ExprTree::Float(f64::INFINITY),
+                Token::Operator(symbol) if symbol == NAN => ExprTree::Float(f64::NAN),
+                Token::Symbol(name) | Token::Quoted(name)
+                    if tokens.peek() == Some(&Token::Open) =>
+                {
+                    tokens.next();
+                    let count = arity(&mut tokens)?;
+                    if count > stack.len() {
+                        return Err(Error::StackUnderflow(Token::Symbol(name).into_owned()));
+                    }
+                    let args = stack.split_off(stack.len() - count);
+                    ExprTree::Call(name.into_owned(), args)
+                }
+                Token::Symbol(name) | Token::Quoted(name) if tokens.peek() == Some(&Token::Dot) => {
+                    let mut path = vec![name.into_owned()];
+                    while tokens.peek() == Some(&Token::Dot) {
+                        tokens.next();
+                        match tokens.next() {
+                            Some(Token::Symbol(member)) | Some(Token::Quoted(member)) => {
+                                path.push(member.into_owned())
+                            }
+                            Some(token) => return Err(Error::UnexpectedToken(token.into_owned())),
+                            None => return Err(Error::UnexpectedEndOfInput),
+                        }
+                    }
+                    ExprTree::Path(path)
+                }
+                Token::Symbol(name) | Token::Quoted(name) => ExprTree::Var(name.into_owned()),
+                Token::Sheet(sheet) => match tokens.next() {
+                    Some(Token::Cell(cell)) => {
+                        reference(&mut tokens, Some(sheet.into_owned()), cell)?
+                    }
+                    Some(token) => return Err(Error::UnexpectedToken(token.into_owned())),
+                    None => return Err(Error::UnexpectedEndOfInput),
+                },
+                Token::Cell(cell) => reference(&mut tokens, None, cell)?,
+                token => {
+                    if let Some(build) = unary(&token) {
+                        let operand = stack
+                            .pop()
+                            .ok_or_else(|| Error::StackUnderflow(token.into_owned()))?;
+                        build(Box::new(operand))
+                    } else if let Some(build) = binary(&token) {
+                        if stack.len() < 2 {
+                            return Err(Error::StackUnderflow(token.into_owned()));
+                        }
+                        let rhs = stack.pop().unwrap();
+                        let lhs = stack.pop().unwrap();
+                        build(Box::new(lhs), Box::new(rhs))
+                    } else {
+                        return Err(Error::UnexpectedToken(token.into_owned()));
+                    }
+                }
+            };
+            stack.push(node);
+        }
+        match stack.len() {
+            0 => Err(Error::EmptyInput),
+            1 => Ok(stack.remove(0)),
+            count => Err(Error::LeftoverOperands(count - 1)),
+        }
+    }
+}
+
+/// Read the number of arguments of a call after the opening
+/// parenthesis.
+fn arity(tokens: &mut Tokenizer<'_>) -> Result<usize> {
+    let count = match tokens.next() {
+        Some(Token::Float(num)) if num.fract() == 0.0 && num <= u32::MAX as f64 => num as usize,
+        Some(token) => return Err(Error::UnexpectedToken(token.into_owned())),
+        None => return Err(Error::UnexpectedEndOfInput),
+    };
+    match tokens.next() {
+        Some(Token::Close) => Ok(count),
+        Some(token) => Err(Error::UnexpectedToken(token.into_owned())),
+        None => Err(Error::UnexpectedEndOfInput),
+    }
+}
+
+/// Read a cell, or a range if the cell is followed by a colon.
+fn reference(tokens: &mut Tokenizer<'_>, sheet: Option<String>, cell: CellRef) -> Result<ExprTree> {
+    if tokens.peek() != Some(&Token::Colon) {
+        return Ok(ExprTree::Cell(sheet, cell));
+    }
+    tokens.next();
+    match tokens.next() {
+        Some(Token::Cell(end)) => Ok(ExprTree::Range(sheet, cell, end)),
+        Some(token) => Err(Error::UnexpectedToken(token.into_owned())),
+        None => Err(Error::UnexpectedEndOfInput),
+    }
+}
+
+/// Parse an expression in Reverse Polish notation into a tree.
+pub fn parse(text: &str) -> Result<ExprTree> {
+    Reader::new().parse(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::{self, parse_with};
+    use std::collections::HashMap;
+
+    fn rpn(text: &str) -> String {
+        Rpn(&parser::parse(text).unwrap()).to_string()
+    }
+
+    #[test]
+    fn writing() {
+        assert_eq!(rpn("10 + x * y"), "10 x y * +");
+        assert_eq!(rpn("(a - b) / c ^ 2"), "a b - c 2 ^ /");
+        assert_eq!(rpn("-x + √y! - 5%"), "x +/- y ! √ + 5 % -");
+        assert_eq!(rpn("max(a, f(), 0xff)"), "a f(0) 0xff max(3)");
+        assert_eq!(
+            rpn("order.total * `unit price`"),
+            "order.total `unit price` *"
+        );
+        assert_eq!(
+            rpn("not a < b and c mod 2 != 0"),
+            "a b < not c 2 mod 0 != and"
+        );
+        let tree = parse_with("=SUM('My Sheet'!A1:$B$2; C3)", &Dialect::Spreadsheet).unwrap();
+        assert_eq!(Rpn(&tree).to_string(), "'My Sheet'!A1:$B$2 C3 SUM(2)");
+
+        let tree = parser::parse("f(x.y)").unwrap();
+        assert_eq!(
+            to_rpn(&tree),
+            vec![
+                Token::Symbol("x".into()),
+                Token::Dot,
+                Token::Symbol("y".into()),
+                Token::Symbol("f".into()),
+                Token::Open,
+                Token::Float(1.0),
+                Token::Close,
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let texts = [
+            "x",
+            "10 + x * y",
+            "-(a - b) / -c ^ 2",
+            "a mod b div c ^ d ^ e",
+            "(x >> 4) & 0xF | x << 8 xor ~y",
+            "a == b or a != c and not (a <= 1 or a >= 2 or a < 0 or a > 9)",
+            "f(g(x), h(), k(1, 2.5, 3))",
+            "√x! + 5% + abs(y)",
+            "order.customer.discount * `unit price`",
+        ];
+        for text in &texts {
+            let tree = parser::parse(text).unwrap();
+            assert_eq!(parse(&Rpn(&tree).to_string()), Ok(tree), "{}", text);
+        }
+        let tree = ExprTree::Abs(Box::new(parser::parse("x - 1").unwrap()));
+        assert_eq!(Rpn(&tree).to_string(), "x 1 - |x|");
+        assert_eq!(parse("x 1 - |x|"), Ok(tree));
+
+        let reader = Reader::new().with_cells(true);
+        for text in &["=Sheet2!B2 * $A$1 + COUNT(A1:B3)", "=SUM('It''s'!A1:A9)"] {
+            let tree = parse_with(text, &Dialect::Spreadsheet).unwrap();
+            assert_eq!(reader.parse(&Rpn(&tree).to_string()), Ok(tree), "{}", text);
+        }
+        assert_eq!(parse("A1"), Ok(ExprTree::Var("A1".to_string())));
+    }
+
+    #[test]
+    fn special_numbers() {
+        let write = |num: f64| Rpn(&ExprTree::Float(num)).to_string();
+        assert_eq!(write(-2.5), "2.5 +/-");
+        assert_eq!(write(-0.0), "0 +/-");
+        assert_eq!(write(f64::INFINITY), "#inf");
+        assert_eq!(write(f64::NEG_INFINITY), "#inf +/-");
+        assert_eq!(write(f64::NAN), "#nan");
+
+        let map: HashMap<String, f64> = HashMap::new();
+        for &num in &[-2.5, -0.0, 1e300, f64::INFINITY, f64::NEG_INFINITY] {
+            let value = parse(&write(num)).unwrap().eval(&map).unwrap();
+            assert_eq!(value.to_bits(), num.to_bits(), "{}", num);
+        }
+        let value = parse(&write(f64::NAN)).unwrap().eval(&map).unwrap();
+        assert!(value.is_nan());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("1 +"), Err(Error::StackUnderflow(Token::Plus)));
+        assert_eq!(
+            parse("+/-"),
+            Err(Error::StackUnderflow(Token::Operator(NEG.into())))
+        );
+        assert_eq!(
+            parse("a max(2)"),
+            Err(Error::StackUnderflow(Token::Symbol("max".into())))
+        );
+        assert_eq!(parse("1 2"), Err(Error::LeftoverOperands(1)));
+        assert_eq!(parse("1 2 3 +"), Err(Error::LeftoverOperands(1)));
+        assert_eq!(parse(""), Err(Error::EmptyInput));
+        assert_eq!(parse("a b ,"), Err(Error::UnexpectedToken(Token::Comma)));
+        assert_eq!(
+            parse("a f(1.5)"),
+            Err(Error::UnexpectedToken(Token::Float(1.5)))
+        );
+        assert_eq!(parse("a f(1"), Err(Error::UnexpectedEndOfInput));
+        assert_eq!(parse("a."), Err(Error::UnexpectedEndOfInput));
+        assert_eq!(
+            parse("1 2 + $ 3"),
+            Err(Error::UnexpectedToken(Token::Error))
+        );
+        assert_eq!(
+            parse("1 2 3").unwrap_err().to_string(),
+            "2 operands left over without an operator"
+        );
+        assert_eq!(
+            parse("*").unwrap_err().to_string(),
+            "missing operand for '*'"
+        );
+    }
+}
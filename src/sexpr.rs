@@ -0,0 +1,577 @@
+//! S-expression notation of expression trees.
+//!
+//! Every operator is written as a list starting with the operator,
+//! like `(+ 10 (* x y))`, and a call is written as a list starting
+//! with the name of the function, like `(max a b)`. Names that are not
+//! plain identifiers, or that are the names of operators, are quoted
+//! using double quotes, so a call of a function named `mod` is written
+//! `("mod" a b)` to tell it from the operator.
+//!
+//! | Tree | S-expression |
+//! |------|--------------|
+//! | variable and path | `x`, `order.total`, `"unit price"`, `(path order "unit price")` |
+//! | number | `2.5`, `-1`, `#nan`, `#inf`, `#-inf` |
+//! | integer | `#xff`, `#o17`, `#b1010` |
+//! | unary operator | `(- x)`, `(~ x)`, `(not x)`, `(abs x)`, `(sqrt x)`, `(fact x)`, `(percent x)` |
+//! | binary operator | `(+ a b)`, `(- a b)`, `(* a b)`, `(/ a b)`, `(mod a b)`, `(div a b)`, `(^ a b)` |
+//! | bitwise operator | `(& a b)`, `(\| a b)`, `(xor a b)`, `(<< a b)`, `(>> a b)` |
+//! | comparison | `(== a b)`, `(!= a b)`, `(< a b)`, `(<= a b)`, `(> a b)`, `(>= a b)` |
+//! | logical operator | `(and a b)`, `(or a b)` |
+//! | cell and range | `(cell B2)`, `(cell Sheet2 $B$2)`, `(range A1 B2)`, `(range "My Sheet" A1 B2)` |
+//!
+//! Line comments start with `;`.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::parse;
+//! # use expr::sexpr::{self, SExpr};
+//! let tree = parse("10 + x * y").unwrap();
+//! assert_eq!(SExpr(&tree).to_string(), "(+ 10 (* x y))");
+//! assert_eq!(sexpr::parse("(+ 10 (* x y))").unwrap(), tree);
+//! ```
+
+use crate::spreadsheet::CellRef;
+use crate::tokens::is_identifier;
+use crate::tree::{ExprTree, Radix};
+use crate::visit::{Fold, Folded};
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnexpectedEndOfInput,
+    /// A token that cannot be used at this point, like `)` at the
+    /// start of an expression or anything after the expression.
+    UnexpectedToken(String),
+    /// A list without a head, like `()`.
+    EmptyList,
+    /// An atom that looks like a number or a cell but is not one,
+    /// like `1x` or `#q`.
+    InvalidAtom(String),
+    /// An operator with the wrong number of operands.
+    Arity {
+        operator: String,
+        count: usize,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedEndOfInput => write!(f, "unexpected end of input"),
+            Error::UnexpectedToken(token) => write!(f, "unexpected token '{}'", token),
+            Error::EmptyList => write!(f, "list without an operator or function"),
+            Error::InvalidAtom(atom) => write!(f, "invalid atom '{}'", atom),
+            Error::Arity { operator, count } => {
+                write!(f, "operator '{}' cannot take {} operands", operator, count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            Error::UnexpectedEndOfInput => "unexpected end of input",
+            Error::UnexpectedToken(_) => "unexpected token",
+            Error::EmptyList => "empty list",
+            Error::InvalidAtom(_) => "invalid atom",
+            Error::Arity { .. } => "wrong number of operands",
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+type Unary = fn(Box<ExprTree>) -> ExprTree;
+type Binary = fn(Box<ExprTree>, Box<ExprTree>) -> ExprTree;
+
+/// Operators taking one operand.
+const UNARY: [(&str, Unary); 7] = [
+    ("-", ExprTree::Neg),
+    ("~", ExprTree::BitNot),
+    ("not", ExprTree::Not),
+    ("abs", ExprTree::Abs),
+    ("sqrt", ExprTree::Sqrt),
+    ("fact", ExprTree::Factorial),
+    ("percent", ExprTree::Percent),
+];
+
+/// Operators taking two operands.
+const BINARY: [(&str, Binary); 20] = [
+    ("+", ExprTree::Add),
+    ("-", ExprTree::Sub),
+    ("*", ExprTree::Mul),
+    ("/", ExprTree::Div),
+    ("mod", ExprTree::Mod),
+    ("div", ExprTree::IntDiv),
+    ("^", ExprTree::Pow),
+    ("&", ExprTree::BitAnd),
+    ("|", ExprTree::BitOr),
+    ("xor", ExprTree::BitXor),
+    ("<<", ExprTree::Shl),
+    (">>", ExprTree::Shr),
+    ("==", ExprTree::Eq),
+    ("!=", ExprTree::Ne),
+    ("<", ExprTree::Lt),
+    ("<=", ExprTree::Le),
+    (">", ExprTree::Gt),
+    (">=", ExprTree::Ge),
+    ("and", ExprTree::And),
+    ("or", ExprTree::Or),
+];
+
+/// Words with a meaning at the head of a list besides the operators.
+const FORMS: [&str; 3] = ["path", "cell", "range"];
+
+/// Check if a word at the head of a list is an operator or a form.
+fn is_reserved(word: &str) -> bool {
+    UNARY.iter().any(|(op, _)| *op == word)
+        || BINARY.iter().any(|(op, _)| *op == word)
+        || FORMS.contains(&word)
+}
+
+/// Display wrapper writing a name, quoting it unless it is a plain
+/// identifier that is not reserved.
+struct Name<'a>(&'a str);
+
+impl std::fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if is_identifier(self.0) && !is_reserved(self.0) {
+            return write!(f, "{}", self.0);
+        }
+        write!(f, "\"")?;
+        for ch in self.0.chars() {
+            if ch == '"' || ch == '\\' {
+                write!(f, "\\")?;
+            }
+            write!(f, "{}", ch)?;
+        }
+        write!(f, "\"")
+    }
+}
+
+/// Display wrapper writing an expression tree as an S-expression.
+pub struct SExpr<'a>(pub &'a ExprTree);
+
+impl std::fmt::Display for SExpr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Printer.fold(self.0))
+    }
+}
+
+struct Printer;
+
+impl Fold for Printer {
+    type Output = String;
+
+    fn fold_node(&mut self, tree: &ExprTree, texts: Folded<String>) -> String {
+        let list = |head: &dyn std::fmt::Display, texts: &[String]| {
+            let mut text = format!("({}", head);
+            for operand in texts {
+                text.push(' ');
+                text.push_str(operand);
+            }
+            text.push(')');
+            text
+        };
+        let sheet = |sheet: &Option<String>| match sheet {
+            Some(sheet) => format!(" {}", Name(sheet)),
+            None => String::new(),
+        };
+        match tree {
+            ExprTree::Var(name) => Name(name).to_string(),
+            ExprTree::Path(path)
+                if path
+                    .iter()
+                    .all(|member| Name(member).to_string() == *member) =>
+            {
+                path.join(".")
+            }
+            ExprTree::Path(path) => {
+                let members: Vec<String> =
+                    path.iter().map(|member| Name(member).to_string()).collect();
+                list(&"path", &members)
+            }
+            ExprTree::Float(num) if num.is_nan() => "#nan".to_string(),
+            ExprTree::Float(num) if num.is_infinite() && *num > 0.0 => "#inf".to_string(),
+            ExprTree::Float(num) if num.is_infinite() => "#-inf".to_string(),
+            ExprTree::Float(num) => num.to_string(),
+            ExprTree::Integer(num, radix) => format!("#{}{}", radix.letter(), radix.digits(*num)),
+            ExprTree::Call(name, _) => list(&Name(name), &texts),
+            ExprTree::Cell(name, cell) => format!("(cell{} {})", sheet(name), cell),
+            ExprTree::Range(name, start, end) => {
+                format!("(range{} {} {})", sheet(name), start, end)
+            }
+            tree => list(&operator(tree), &texts),
+        }
+    }
+}
+
+/// Get the word written for an operator node.
+fn operator(tree: &ExprTree) -> &'static str {
+    match tree {
+        ExprTree::Neg(_) => "-",
+        ExprTree::BitNot(_) => "~",
+        ExprTree::Not(_) => "not",
+        ExprTree::Abs(_) => "abs",
+        ExprTree::Sqrt(_) => "sqrt",
+        ExprTree::Factorial(_) => "fact",
+        ExprTree::Percent(_) => "percent",
+        ExprTree::Add(..) => "+",
+        ExprTree::Sub(..) => "-",
+        ExprTree::Mul(..) => "*",
+        ExprTree::Div(..) => "/",
+        ExprTree::Mod(..) => "mod",
+        ExprTree::IntDiv(..) => "div",
+        ExprTree::Pow(..) => "^",
+        ExprTree::BitAnd(..) => "&",
+        ExprTree::BitOr(..) => "|",
+        ExprTree::BitXor(..) => "xor",
+        ExprTree::Shl(..) => "<<",
+        ExprTree::Shr(..) => ">>",
+        ExprTree::Eq(..) => "==",
+        ExprTree::Ne(..) => "!=",
+        ExprTree::Lt(..) => "<",
+        ExprTree::Le(..) => "<=",
+        ExprTree::Gt(..) => ">",
+        ExprTree::Ge(..) => ">=",
+        ExprTree::And(..) => "and",
+        ExprTree::Or(..) => "or",
+        _ => unreachable!("not an operator: {:?}", tree),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    /// Atom written without quotes.
+    Word(&'a str),
+    /// Name written within double quotes.
+    Quoted(String),
+}
+
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(name) => write!(f, "{}", Name(name)),
+        }
+    }
+}
+
+struct Reader<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Reader<'a> {
+    /// Read the next token, skipping whitespace and comments.
+    fn next(&mut self) -> Result<Option<Token<'a>>> {
+        loop {
+            self.rest = self.rest.trim_start();
+            if !self.rest.starts_with(';') {
+                break;
+            }
+            self.rest = self.rest.find('\n').map_or("", |end| &self.rest[end..]);
+        }
+        let mut chars = self.rest.chars();
+        let token = match chars.next() {
+            None => return Ok(None),
+            Some('(') => Token::Open,
+            Some(')') => Token::Close,
+            Some('"') => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(ch) => name.push(ch),
+                            None => return Err(Error::UnexpectedEndOfInput),
+                        },
+                        Some(ch) => name.push(ch),
+                        None => return Err(Error::UnexpectedEndOfInput),
+                    }
+                }
+                self.rest = chars.as_str();
+                return Ok(Some(Token::Quoted(name)));
+            }
+            Some(_) => {
+                let len = self
+                    .rest
+                    .find(|ch: char| ch.is_whitespace() || "()\";".contains(ch))
+                    .unwrap_or(self.rest.len());
+                let word = &self.rest[..len];
+                self.rest = &self.rest[len..];
+                return Ok(Some(Token::Word(word)));
+            }
+        };
+        self.rest = chars.as_str();
+        Ok(Some(token))
+    }
+
+    /// Read the next token, which has to be there.
+    fn expect(&mut self) -> Result<Token<'a>> {
+        self.next()?.ok_or(Error::UnexpectedEndOfInput)
+    }
+
+    fn expr(&mut self) -> Result<ExprTree> {
+        match self.expect()? {
+            Token::Open => self.list(),
+            Token::Word(word) => atom(word),
+            Token::Quoted(name) => Ok(ExprTree::Var(name)),
+            Token::Close => Err(Error::UnexpectedToken(")".to_string())),
+        }
+    }
+
+    /// Read the expressions up to the end of a list.
+    fn operands(&mut self) -> Result<Vec<ExprTree>> {
+        let mut operands = Vec::new();
+        loop {
+            match self.expect()? {
+                Token::Close => return Ok(operands),
+                Token::Open => operands.push(self.list()?),
+                Token::Word(word) => operands.push(atom(word)?),
+                Token::Quoted(name) => operands.push(ExprTree::Var(name)),
+            }
+        }
+    }
+
+    /// Read the words and quoted names up to the end of a list.
+    fn names(&mut self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        loop {
+            match self.expect()? {
+                Token::Close => return Ok(names),
+                Token::Word(word) => names.push(word.to_string()),
+                Token::Quoted(name) => names.push(name),
+                Token::Open => return Err(Error::UnexpectedToken("(".to_string())),
+            }
+        }
+    }
+
+    /// Read a list after the opening parenthesis.
+    fn list(&mut self) -> Result<ExprTree> {
+        let head = match self.expect()? {
+            Token::Word(word) => word,
+            Token::Quoted(name) => return Ok(ExprTree::Call(name, self.operands()?)),
+            Token::Close => return Err(Error::EmptyList),
+            Token::Open => return Err(Error::UnexpectedToken("(".to_string())),
+        };
+        match head {
+            "path" => Ok(ExprTree::Path(self.names()?)),
+            "cell" | "range" => self.reference(head),
+            head if is_reserved(head) => {
+                let mut operands = self.operands()?;
+                let unary = UNARY.iter().find(|(op, _)| *op == head);
+                let binary = BINARY.iter().find(|(op, _)| *op == head);
+                match (unary, binary, operands.len()) {
+                    (Some((_, build)), _, 1) => Ok(build(Box::new(operands.remove(0)))),
+                    (_, Some((_, build)), 2) => {
+                        let rhs = operands.remove(1);
+                        Ok(build(Box::new(operands.remove(0)), Box::new(rhs)))
+                    }
+                    (_, _, count) => Err(Error::Arity {
+                        operator: head.to_string(),
+                        count,
+                    }),
+                }
+            }
+            name => Ok(ExprTree::Call(name.to_string(), self.operands()?)),
+        }
+    }
+
+    /// Read the rest of a `cell` or `range` list.
+    fn reference(&mut self, head: &str) -> Result<ExprTree> {
+        let mut names = self.names()?;
+        let cells = if head == "cell" { 1 } else { 2 };
+        if names.len() != cells && names.len() != cells + 1 {
+            return Err(Error::Arity {
+                operator: head.to_string(),
+                count: names.len(),
+            });
+        }
+        let sheet = if names.len() > cells {
+            Some(names.remove(0))
+        } else {
+            None
+        };
+        let cells = names
+            .iter()
+            .map(|name| match CellRef::read(name) {
+                Some((cell, len)) if len == name.len() => Ok(cell),
+                _ => Err(Error::InvalidAtom(name.clone())),
+            })
+            .collect::<Result<Vec<CellRef>>>()?;
+        Ok(match cells[..] {
+            [cell] => ExprTree::Cell(sheet, cell),
+            [start, end] => ExprTree::Range(sheet, start, end),
+            _ => unreachable!(),
+        })
+    }
+}
+
+/// Get the tree for an atom written without quotes.
+fn atom(word: &str) -> Result<ExprTree> {
+    let invalid = || Error::InvalidAtom(word.to_string());
+    let number = word
+        .trim_start_matches(['-', '+'])
+        .starts_with(|ch: char| ch.is_ascii_digit() || ch == '.');
+    if number {
+        return word
+            .parse::<f64>()
+            .map(ExprTree::Float)
+            .map_err(|_| invalid());
+    }
+    match word {
+        "#nan" => Ok(ExprTree::Float(f64::NAN)),
+        "#inf" => Ok(ExprTree::Float(f64::INFINITY)),
+        "#-inf" => Ok(ExprTree::Float(f64::NEG_INFINITY)),
+        _ if word.starts_with('#') => {
+            let mut digits = word[1..].chars();
+            let radix = match digits.next() {
+                Some('b') => Radix::Binary,
+                Some('o') => Radix::Octal,
+                Some('x') => Radix::Hexadecimal,
+                _ => return Err(invalid()),
+            };
+            u64::from_str_radix(digits.as_str(), radix.base())
+                .map(|num| ExprTree::Integer(num, radix))
+                .map_err(|_| invalid())
+        }
+        _ if word.contains('.') => Ok(ExprTree::Path(
+            word.split('.').map(str::to_string).collect(),
+        )),
+        _ => Ok(ExprTree::Var(word.to_string())),
+    }
+}
+
+/// Parse an S-expression into an expression tree.
+pub fn parse(text: &str) -> Result<ExprTree> {
+    let mut reader = Reader { rest: text };
+    let tree = reader.expr()?;
+    match reader.next()? {
+        None => Ok(tree),
+        Some(token) => Err(Error::UnexpectedToken(token.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::{self, parse_with};
+
+    fn write(tree: &ExprTree) -> String {
+        SExpr(tree).to_string()
+    }
+
+    #[test]
+    fn printing() {
+        let sexpr = |text: &str| write(&parser::parse(text).unwrap());
+        assert_eq!(sexpr("10 + x * y"), "(+ 10 (* x y))");
+        assert_eq!(sexpr("-x ^ 2 - 1"), "(- (- (^ x 2)) 1)");
+        assert_eq!(sexpr("f() + g(a, 0xff)"), "(+ (f) (g a #xff))");
+        assert_eq!(sexpr("not a < b and c"), "(and (not (< a b)) c)");
+        assert_eq!(sexpr("√x! + 5%"), "(+ (sqrt (fact x)) (percent 5))");
+        assert_eq!(sexpr("order.total * 0.5"), "(* order.total 0.5)");
+        assert_eq!(
+            sexpr("`unit price` + `mod`(a, b) + obj.`the total`"),
+            r#"(+ (+ "unit price" ("mod" a b)) (path obj "the total"))"#
+        );
+        assert_eq!(
+            write(&parse_with("=SUM('My Sheet'!A1:$B$2; C3)", &Dialect::Spreadsheet).unwrap()),
+            r#"(SUM (range "My Sheet" A1 $B$2) (cell C3))"#
+        );
+        assert_eq!(write(&ExprTree::Float(f64::NEG_INFINITY)), "#-inf");
+        assert_eq!(
+            write(&ExprTree::Var(r#"a"b\c"#.to_string())),
+            r#""a\"b\\c""#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let texts = [
+            "x",
+            "10 + x * y",
+            "-0.0 - 1.25 / 3",
+            "a mod b div c ^ d ^ e",
+            "(x >> 4) & 0xF | x << 8 xor ~y",
+            "a == b or a != c and (a <= 1 or a >= 2 or a < 0 or a > 9)",
+            "f(g(x), h(), k(1, 2, 3))",
+            "abs(x) + sqrt(y) + fact(3) + percent(z)",
+            "order.customer.discount * `unit price`",
+            "`and`(1) + `path`(2) - `-`",
+        ];
+        for text in &texts {
+            let tree = parser::parse(text).unwrap();
+            assert_eq!(parse(&write(&tree)).unwrap(), tree, "{}", text);
+        }
+        for text in &["=Sheet2!B2 * $A$1 + COUNT(A1:B3)", "=SUM('It''s'!A1:A9)"] {
+            let tree = parse_with(text, &Dialect::Spreadsheet).unwrap();
+            assert_eq!(parse(&write(&tree)).unwrap(), tree, "{}", text);
+        }
+        for tree in &[
+            ExprTree::Float(f64::INFINITY),
+            ExprTree::Float(-2.5),
+            ExprTree::Integer(u64::MAX, Radix::Hexadecimal),
+            ExprTree::Integer(5, Radix::Binary),
+            ExprTree::Integer(8, Radix::Octal),
+        ] {
+            assert_eq!(parse(&write(tree)).as_ref(), Ok(tree));
+        }
+        assert!(matches!(parse("#nan"), Ok(ExprTree::Float(num)) if num.is_nan()));
+    }
+
+    #[test]
+    fn reading() {
+        assert_eq!(
+            parse("; total\n(+ a ; first\n   b)").unwrap(),
+            parser::parse("a + b").unwrap()
+        );
+        assert_eq!(
+            parse("(max)").unwrap(),
+            ExprTree::Call("max".to_string(), vec![])
+        );
+        assert_eq!(parse(r#""a b""#).unwrap(), ExprTree::Var("a b".to_string()));
+        assert_eq!(
+            parse("(path a)").unwrap(),
+            ExprTree::Path(vec!["a".to_string()])
+        );
+        assert_eq!(parse("(+ a"), Err(Error::UnexpectedEndOfInput));
+        assert_eq!(parse(r#""a"#), Err(Error::UnexpectedEndOfInput));
+        assert_eq!(parse("a b"), Err(Error::UnexpectedToken("b".to_string())));
+        assert_eq!(parse(")"), Err(Error::UnexpectedToken(")".to_string())));
+        assert_eq!(parse("()"), Err(Error::EmptyList));
+        assert_eq!(
+            parse("((f) x)"),
+            Err(Error::UnexpectedToken("(".to_string()))
+        );
+        assert_eq!(parse("1x"), Err(Error::InvalidAtom("1x".to_string())));
+        assert_eq!(parse("#xg"), Err(Error::InvalidAtom("#xg".to_string())));
+        assert_eq!(
+            parse("(cell A1 B2 C3 D4)").unwrap_err().to_string(),
+            "operator 'cell' cannot take 4 operands"
+        );
+        assert_eq!(
+            parse("(range A1 x)"),
+            Err(Error::InvalidAtom("x".to_string()))
+        );
+        assert_eq!(
+            parse("(+ a b c)"),
+            Err(Error::Arity {
+                operator: "+".to_string(),
+                count: 3
+            })
+        );
+        assert_eq!(
+            parse("(not)"),
+            Err(Error::Arity {
+                operator: "not".to_string(),
+                count: 0
+            })
+        );
+    }
+}
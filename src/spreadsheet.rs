@@ -0,0 +1,370 @@
+//! Spreadsheet cell references and their evaluation.
+//!
+//! In spreadsheet mode, enabled using
+//! [`ParserConfig::with_spreadsheet`](crate::parser::ParserConfig::with_spreadsheet)
+//! or the spreadsheet dialect, formulas can refer to cells using A1
+//! notation, like `B$2` or `Sheet2!A1`, and to rectangular ranges of
+//! cells, like `A1:A10`. The values of the cells are supplied by a
+//! [`Resolver`], which the context gives to the evaluation.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::dialect::Dialect;
+//! # use expr::parser::parse_with;
+//! # use expr::spreadsheet::Cells;
+//! let mut cells = Cells::new();
+//! cells.set("A1", 1.0).unwrap();
+//! cells.set("A2", 2.0).unwrap();
+//! cells.set("B2", 10.0).unwrap();
+//! let tree = parse_with("=SUM(A1:A10) * B$2", &Dialect::Spreadsheet).unwrap();
+//! assert_eq!(tree.eval(&cells), Ok(30.0));
+//! ```
+
+use crate::tokens::{Token, Tokenizer};
+use crate::tree::{Context, Entry, Error, Result};
+use std::collections::HashMap;
+
+/// Reference to a single cell using A1 notation.
+///
+/// Columns and rows are numbered from 1, so `B3` is column 2 and
+/// row 3. A `$` before the column or the row marks it as absolute,
+/// which only matters when a formula is copied.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CellRef {
+    pub column: u32,
+    pub row: u32,
+    pub absolute_column: bool,
+    pub absolute_row: bool,
+}
+
+/// Largest column number, which is `XFD`.
+const MAX_COLUMN: u32 = 16384;
+
+/// Largest row number.
+const MAX_ROW: u32 = 1_048_576;
+
+impl CellRef {
+    pub fn new(column: u32, row: u32) -> CellRef {
+        CellRef {
+            column,
+            row,
+            absolute_column: false,
+            absolute_row: false,
+        }
+    }
+
+    /// Read a cell reference at the start of the text, giving the
+    /// reference and the length of the text it was read from.
+    ///
+    /// The reference is an optional `$`, one to three letters, an
+    /// optional `$`, and the row number. It cannot be directly
+    /// followed by a letter, a digit, or an underscore, so `ABCD1`
+    /// and `A1x` are not cell references.
+    pub(crate) fn read(text: &str) -> Option<(CellRef, usize)> {
+        let bytes = text.as_bytes();
+        let mut pos = 0;
+        let absolute_column = bytes.first() == Some(&b'$');
+        if absolute_column {
+            pos += 1;
+        }
+        let letters = bytes[pos..]
+            .iter()
+            .take_while(|b| b.is_ascii_alphabetic())
+            .count();
+        if letters == 0 || letters > 3 {
+            return None;
+        }
+        let column = bytes[pos..pos + letters].iter().fold(0, |column, letter| {
+            column * 26 + u32::from(letter.to_ascii_uppercase() - b'A') + 1
+        });
+        pos += letters;
+        let absolute_row = bytes.get(pos) == Some(&b'$');
+        if absolute_row {
+            pos += 1;
+        }
+        let digits = bytes[pos..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        let row = text[pos..pos + digits].parse::<u32>().ok()?;
+        pos += digits;
+        let ends = text[pos..]
+            .chars()
+            .next()
+            .map_or(true, |ch| !(ch.is_alphanumeric() || ch == '_'));
+        if row == 0 || row > MAX_ROW || column > MAX_COLUMN || !ends {
+            return None;
+        }
+        let cell = CellRef {
+            column,
+            row,
+            absolute_column,
+            absolute_row,
+        };
+        Some((cell, pos))
+    }
+}
+
+impl std::fmt::Display for CellRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.absolute_column {
+            write!(f, "$")?;
+        }
+        let mut letters = Vec::new();
+        let mut column = self.column;
+        while column > 0 {
+            letters.push(char::from(b'A' + ((column - 1) % 26) as u8));
+            column = (column - 1) / 26;
+        }
+        for letter in letters.iter().rev() {
+            write!(f, "{}", letter)?;
+        }
+        if self.absolute_row {
+            write!(f, "$")?;
+        }
+        write!(f, "{}", self.row)
+    }
+}
+
+/// Display wrapper writing a sheet name followed by `!`, quoting the
+/// name with single quotes when it is not a plain name.
+pub struct SheetName<'a>(pub &'a str);
+
+impl std::fmt::Display for SheetName<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut chars = self.0.chars();
+        let plain = chars.next().is_some_and(|ch| ch.is_alphabetic())
+            && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+            && CellRef::read(self.0).is_none();
+        if plain {
+            write!(f, "{}!", self.0)
+        } else {
+            write!(f, "'{}'!", self.0.replace('\'', "''"))
+        }
+    }
+}
+
+/// Supplier of cell values during evaluation.
+pub trait Resolver {
+    /// Get the value of a cell, giving `None` if the cell is empty.
+    fn cell(&self, sheet: Option<&str>, cell: &CellRef) -> Option<f64>;
+
+    /// Get the values of the cells in a range, row by row. Empty
+    /// cells are left out, so that functions like `SUM` skip them.
+    ///
+    /// This looks up every cell of the range, so resolvers holding
+    /// few cells should override it.
+    fn range(&self, sheet: Option<&str>, start: &CellRef, end: &CellRef) -> Vec<f64> {
+        let (top, bottom) = (start.row.min(end.row), start.row.max(end.row));
+        let (left, right) = (start.column.min(end.column), start.column.max(end.column));
+        let mut values = Vec::new();
+        for row in top..=bottom {
+            for column in left..=right {
+                if let Some(value) = self.cell(sheet, &CellRef::new(column, row)) {
+                    values.push(value);
+                }
+            }
+        }
+        values
+    }
+}
+
+/// Cell values of one or more sheets.
+///
+/// This is a context supplying the cell values and the functions
+/// `SUM`, `AVERAGE`, `MIN`, `MAX`, and `COUNT`, where ranges are
+/// expanded into their values.
+#[derive(Debug, Default, Clone)]
+pub struct Cells {
+    values: HashMap<(Option<String>, u32, u32), f64>,
+}
+
+impl Cells {
+    pub fn new() -> Cells {
+        Cells::default()
+    }
+
+    /// Set the value of a cell given by a reference like `B2` or
+    /// `Sheet2!B2`, giving `None` if the reference is not valid.
+    pub fn set(&mut self, reference: &str, value: f64) -> Option<()> {
+        let mut tokens = Tokenizer::new(reference).with_cells(true);
+        let (sheet, cell) = match (tokens.next(), tokens.next(), tokens.next()) {
+            (Some(Token::Cell(cell)), None, _) => (None, cell),
+            (Some(Token::Sheet(sheet)), Some(Token::Cell(cell)), None) => {
+                (Some(sheet.into_owned()), cell)
+            }
+            _ => return None,
+        };
+        self.values.insert((sheet, cell.column, cell.row), value);
+        Some(())
+    }
+}
+
+impl Resolver for Cells {
+    fn cell(&self, sheet: Option<&str>, cell: &CellRef) -> Option<f64> {
+        let key = (sheet.map(str::to_string), cell.column, cell.row);
+        self.values.get(&key).cloned()
+    }
+
+    /// Get the values of the cells in a range from the cells that are
+    /// set, rather than looking up every cell of the range.
+    fn range(&self, sheet: Option<&str>, start: &CellRef, end: &CellRef) -> Vec<f64> {
+        let rows = start.row.min(end.row)..=start.row.max(end.row);
+        let columns = start.column.min(end.column)..=start.column.max(end.column);
+        let mut cells: Vec<_> = self
+            .values
+            .iter()
+            .filter(|((name, column, row), _)| {
+                name.as_deref() == sheet && rows.contains(row) && columns.contains(column)
+            })
+            .map(|(&(_, column, row), &value)| (row, column, value))
+            .collect();
+        cells.sort_by_key(|&(row, column, _)| (row, column));
+        cells.into_iter().map(|(_, _, value)| value).collect()
+    }
+}
+
+impl Context for Cells {
+    fn lookup(&self, _name: &str) -> Option<Entry<'_>> {
+        None
+    }
+
+    fn call(&self, name: &str, args: &[f64]) -> Option<Result<f64>> {
+        let sum: f64 = args.iter().sum();
+        let values = |value: f64| match args {
+            [] => Err(Error::NoArguments(name.to_string())),
+            _ => Ok(value),
+        };
+        match name {
+            "SUM" => Some(Ok(sum)),
+            "AVERAGE" => Some(values(sum / args.len() as f64)),
+            "MIN" => Some(values(args.iter().cloned().fold(f64::INFINITY, f64::min))),
+            "MAX" => Some(values(
+                args.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            )),
+            "COUNT" => Some(Ok(args.len() as f64)),
+            _ => None,
+        }
+    }
+
+    fn resolver(&self) -> Option<&dyn Resolver> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::parse_with;
+    use crate::tree::ExprTree;
+
+    #[test]
+    fn references() {
+        assert_eq!(CellRef::read("A1"), Some((CellRef::new(1, 1), 2)));
+        assert_eq!(
+            CellRef::read("xfd1048576 "),
+            Some((CellRef::new(16384, 1048576), 10))
+        );
+        let cell = CellRef {
+            column: 28,
+            row: 3,
+            absolute_column: true,
+            absolute_row: false,
+        };
+        assert_eq!(CellRef::read("$AB3:C4"), Some((cell, 4)));
+        assert_eq!(cell.to_string(), "$AB3");
+        assert_eq!(CellRef::new(26, 1).to_string(), "Z1");
+        assert_eq!(CellRef::new(27, 1).to_string(), "AA1");
+        assert_eq!(CellRef::read("A0"), None);
+        assert_eq!(CellRef::read("ABCD1"), None);
+        assert_eq!(CellRef::read("XFE1"), None);
+        assert_eq!(CellRef::read("A1048577"), None);
+        assert_eq!(CellRef::read("A4294967295"), None);
+        assert_eq!(CellRef::read("A1x"), None);
+        assert_eq!(CellRef::read("A$"), None);
+    }
+
+    #[test]
+    fn formulas() {
+        let parse = |text: &str| parse_with(text, &Dialect::Spreadsheet).unwrap();
+        let absolute = CellRef {
+            absolute_row: true,
+            ..CellRef::new(2, 2)
+        };
+        assert_eq!(
+            parse("=SUM(A1:A10) * B$2"),
+            ExprTree::Mul(
+                Box::new(ExprTree::Call(
+                    "SUM".to_string(),
+                    vec![ExprTree::Range(
+                        None,
+                        CellRef::new(1, 1),
+                        CellRef::new(1, 10)
+                    )],
+                )),
+                Box::new(ExprTree::Cell(None, absolute)),
+            )
+        );
+        assert_eq!(parse("sum(a1; 2)"), parse("SUM(A1; 2)"));
+        assert_eq!(parse("abs(-A1)"), ExprTree::Abs(Box::new(parse("-A1"))));
+
+        for &(text, printed) in &[
+            ("=sum(Sheet2!A1:B3)", "SUM(Sheet2!A1:B3)"),
+            (
+                "'My Sheet'!$A$1 + 'It''s'!c2",
+                "'My Sheet'!$A$1 + 'It''s'!C2",
+            ),
+            ("'A1'!A1 * rate", "'A1'!A1 * rate"),
+        ] {
+            let tree = parse(text);
+            assert_eq!(tree.display(Dialect::Spreadsheet).to_string(), printed);
+            assert_eq!(parse(printed), tree);
+        }
+
+        assert!(parse_with("A1:", &Dialect::Spreadsheet).is_err());
+        assert!(parse_with("Sheet2!x", &Dialect::Spreadsheet).is_err());
+        assert!(parse_with("'Sheet2", &Dialect::Spreadsheet).is_err());
+    }
+
+    #[test]
+    fn evaluation() {
+        let mut cells = Cells::new();
+        for (row, value) in (1..=4).zip(&[1.0, 2.0, 3.0, 4.0]) {
+            cells.set(&format!("A{}", row), *value).unwrap();
+        }
+        cells.set("Data!B1", 10.0).unwrap();
+        cells.set("'My Data'!B1", 20.0).unwrap();
+        assert_eq!(cells.set("B", 1.0), None);
+
+        let eval = |text: &str| {
+            parse_with(text, &Dialect::Spreadsheet)
+                .unwrap()
+                .eval(&cells)
+        };
+        assert_eq!(eval("=SUM(A1:A10)"), Ok(10.0));
+        assert_eq!(eval("=AVERAGE(A1:A4; Data!B1)"), Ok(4.0));
+        assert_eq!(eval("=MAX(A4:A1) + COUNT(A1:B2)"), Ok(6.0));
+        assert_eq!(eval("=SUM(Data!A1:XFD1048576)"), Ok(10.0));
+        assert_eq!(eval("=SUM(C1:C9)"), Ok(0.0));
+        for function in &["AVERAGE", "MIN", "MAX"] {
+            assert_eq!(
+                eval(&format!("={}(C1:C9)", function)),
+                Err(Error::NoArguments(function.to_string()))
+            );
+        }
+        assert_eq!(eval("=Data!B1 + 'My Data'!B1"), Ok(30.0));
+        assert_eq!(eval("=A$1 * $A2"), Ok(2.0));
+        assert_eq!(eval("=B7"), Err(Error::NoCell("B7".to_string())));
+        assert_eq!(
+            eval("=A1:A2 + 1"),
+            Err(Error::UnexpectedRange("A1:A2".to_string()))
+        );
+        assert_eq!(
+            eval("=MEDIAN(A1:A2)"),
+            Err(Error::NoFunction("MEDIAN".to_string()))
+        );
+    }
+}
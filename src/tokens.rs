@@ -1,13 +1,34 @@
 //! Tokenizer to read character from an iterator over some text and
 //! produce tokens for the shunting-yard algorithm.
+//!
+//! Tokens borrow names and other text from the input, so reading
+//! tokens does not allocate. Only names that have to be unescaped,
+//! like the quoted name `` `a\`b` ``, are copied.
 
+use crate::spreadsheet::{CellRef, SheetName};
+use crate::tree::Radix;
+use std::borrow::Cow;
 use std::str::Chars;
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Token {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "token", content = "value", rename_all = "snake_case")
+)]
+pub enum Token<'a> {
     Error,
-    Float(f64),
-    Symbol(String),
+    Float(#[cfg_attr(feature = "serde", serde(with = "crate::document::float"))] f64),
+    Integer(u64, Radix),
+    /// Name of a variable or function. This is a `Cow` rather than a
+    /// `&str` because a quoted name containing escapes, like
+    /// `` `a\`b` ``, differs from its text in the input and has to be
+    /// copied.
+    Symbol(Cow<'a, str>),
+    /// Name written in backticks, like `` `Gross Margin %` ``. It is
+    /// read like a symbol, except that spreadsheet mode keeps the case
+    /// of a quoted function name.
+    Quoted(Cow<'a, str>),
     Plus,
     Minus,
     Star,
@@ -15,9 +36,91 @@ pub enum Token {
     Power,
     Open,
     Close,
+    Dot,
+    Bang,
+    Percent,
+    NotEqual,
+    Ampersand,
+    Pipe,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
+    Mod,
+    Div,
+    Xor,
+    And,
+    Or,
+    Not,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    Assign,
+    Root,
+    Superscript(i32),
+    Comma,
+    Semicolon,
+    Colon,
+    Cell(CellRef),
+    Sheet(Cow<'a, str>),
+    Operator(Cow<'a, str>),
+    Comment(Cow<'a, str>),
 }
 
-impl std::fmt::Display for Token {
+impl Token<'_> {
+    /// Copy the text that the token borrows, so that the token can
+    /// outlive the input, like in errors.
+    pub fn into_owned(self) -> Token<'static> {
+        let owned = |text: Cow<'_, str>| Cow::Owned(text.into_owned());
+        match self {
+            Token::Error => Token::Error,
+            Token::Float(n) => Token::Float(n),
+            Token::Integer(n, radix) => Token::Integer(n, radix),
+            Token::Symbol(name) => Token::Symbol(owned(name)),
+            Token::Quoted(name) => Token::Quoted(owned(name)),
+            Token::Plus => Token::Plus,
+            Token::Minus => Token::Minus,
+            Token::Star => Token::Star,
+            Token::Slash => Token::Slash,
+            Token::Power => Token::Power,
+            Token::Open => Token::Open,
+            Token::Close => Token::Close,
+            Token::Dot => Token::Dot,
+            Token::Bang => Token::Bang,
+            Token::Percent => Token::Percent,
+            Token::NotEqual => Token::NotEqual,
+            Token::Ampersand => Token::Ampersand,
+            Token::Pipe => Token::Pipe,
+            Token::Tilde => Token::Tilde,
+            Token::ShiftLeft => Token::ShiftLeft,
+            Token::ShiftRight => Token::ShiftRight,
+            Token::Mod => Token::Mod,
+            Token::Div => Token::Div,
+            Token::Xor => Token::Xor,
+            Token::And => Token::And,
+            Token::Or => Token::Or,
+            Token::Not => Token::Not,
+            Token::Less => Token::Less,
+            Token::LessEqual => Token::LessEqual,
+            Token::Greater => Token::Greater,
+            Token::GreaterEqual => Token::GreaterEqual,
+            Token::Equal => Token::Equal,
+            Token::Assign => Token::Assign,
+            Token::Root => Token::Root,
+            Token::Superscript(n) => Token::Superscript(n),
+            Token::Comma => Token::Comma,
+            Token::Semicolon => Token::Semicolon,
+            Token::Colon => Token::Colon,
+            Token::Cell(cell) => Token::Cell(cell),
+            Token::Sheet(name) => Token::Sheet(owned(name)),
+            Token::Operator(symbol) => Token::Operator(owned(symbol)),
+            Token::Comment(text) => Token::Comment(owned(text)),
+        }
+    }
+}
+
+impl std::fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
             Token::Plus => write!(f, "+"),
@@ -27,34 +130,227 @@ impl std::fmt::Display for Token {
             Token::Power => write!(f, "^"),
             Token::Open => write!(f, "("),
             Token::Close => write!(f, ")"),
-            Token::Symbol(ref n) => write!(f, "{}", n),
+            Token::Dot => write!(f, "."),
+            Token::Bang => write!(f, "!"),
+            Token::Percent => write!(f, "%"),
+            Token::NotEqual => write!(f, "!="),
+            Token::Ampersand => write!(f, "&"),
+            Token::Pipe => write!(f, "|"),
+            Token::Tilde => write!(f, "~"),
+            Token::ShiftLeft => write!(f, "<<"),
+            Token::ShiftRight => write!(f, ">>"),
+            Token::Mod => write!(f, "mod"),
+            Token::Div => write!(f, "div"),
+            Token::Xor => write!(f, "xor"),
+            Token::And => write!(f, "and"),
+            Token::Or => write!(f, "or"),
+            Token::Not => write!(f, "not"),
+            Token::Less => write!(f, "<"),
+            Token::LessEqual => write!(f, "<="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterEqual => write!(f, ">="),
+            Token::Equal => write!(f, "=="),
+            Token::Assign => write!(f, "="),
+            Token::Root => write!(f, "√"),
+            Token::Superscript(n) => {
+                if n < 0 {
+                    write!(f, "⁻")?;
+                }
+                for digit in n.unsigned_abs().to_string().chars() {
+                    let index = digit.to_digit(10).unwrap_or(0) as usize;
+                    write!(f, "{}", SUPERSCRIPTS[index])?;
+                }
+                Ok(())
+            }
+            Token::Comma => write!(f, ","),
+            Token::Semicolon => write!(f, ";"),
+            Token::Colon => write!(f, ":"),
+            Token::Cell(ref cell) => write!(f, "{}", cell),
+            Token::Sheet(ref name) => write!(f, "{}", SheetName(name)),
+            Token::Operator(ref symbol) => write!(f, "{}", symbol),
+            Token::Symbol(ref n) => write!(f, "{}", Identifier(n)),
+            Token::Quoted(ref n) => quote(f, n),
             Token::Float(n) => write!(f, "{}", n),
+            Token::Integer(n, radix) => write!(f, "0{}{}", radix.letter(), radix.digits(n)),
+            Token::Comment(ref text) => write!(f, "{}", text),
             Token::Error => write!(f, "ERROR"),
         }
     }
 }
 
+/// Superscript digits, indexed by their value.
+const SUPERSCRIPTS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+
+fn is_superscript(ch: char) -> bool {
+    ch == '⁻' || SUPERSCRIPTS.contains(&ch)
+}
+
+/// Get the token for a Unicode alias of an operator or constant.
+fn unicode_alias(ch: char) -> Option<Token<'static>> {
+    match ch {
+        '×' => Some(Token::Star),
+        '÷' => Some(Token::Slash),
+        '\u{2212}' => Some(Token::Minus),
+        '√' => Some(Token::Root),
+        'π' => Some(Token::Float(std::f64::consts::PI)),
+        '≤' => Some(Token::LessEqual),
+        '≥' => Some(Token::GreaterEqual),
+        '≠' => Some(Token::NotEqual),
+        _ => None,
+    }
+}
+
+/// Get the token for a keyword, if the name is one.
+fn keyword(name: &str) -> Option<Token<'static>> {
+    match name {
+        "mod" => Some(Token::Mod),
+        "div" => Some(Token::Div),
+        "xor" => Some(Token::Xor),
+        "and" => Some(Token::And),
+        "or" => Some(Token::Or),
+        "not" => Some(Token::Not),
+        _ => None,
+    }
+}
+
+/// Check if a name can be written as a plain identifier.
+///
+/// Plain identifiers start with a letter and continue with letters,
+/// digits, or underscores. Any other name, including keywords like
+/// `mod`, has to be quoted using backticks.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|ch| ch.is_alphabetic())
+        && chars.all(|ch| ch.is_alphabetic() || ch == '_' || ch.is_ascii_digit())
+        && keyword(name).is_none()
+}
+
+/// Display wrapper writing a name as an identifier, quoting it with
+/// backticks only when it is not a plain identifier.
+pub struct Identifier<'a>(pub &'a str);
+
+impl std::fmt::Display for Identifier<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if is_identifier(self.0) {
+            write!(f, "{}", self.0)
+        } else {
+            quote(f, self.0)
+        }
+    }
+}
+
+/// Write a name in backticks, escaping backticks and backslashes.
+fn quote(f: &mut std::fmt::Formatter<'_>, name: &str) -> std::fmt::Result {
+    write!(f, "`")?;
+    for ch in name.chars() {
+        if ch == '`' || ch == '\\' {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", ch)?;
+    }
+    write!(f, "`")
+}
+
 #[derive(Clone)]
 pub struct Tokenizer<'a> {
     chars: Chars<'a>,
+    /// Token read by [`peek`](Tokenizer::peek) and not yet returned
+    /// by `next`.
+    peeked: Option<Option<Token<'a>>>,
+    comments: bool,
+    unicode: bool,
+    cells: bool,
+    logic: bool,
+    integer: bool,
+    operators: &'a [String],
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(text: &'a str) -> Tokenizer<'a> {
         Tokenizer {
             chars: text.chars(),
+            peeked: None,
+            comments: false,
+            unicode: true,
+            cells: false,
+            logic: true,
+            integer: true,
+            operators: &[],
         }
     }
 
-    pub fn peek(&self) -> Option<Token> {
-        self.clone().next()
+    /// Accept Unicode aliases for operators and constants.
+    ///
+    /// This is enabled by default and maps `×`, `÷`, `−` (U+2212),
+    /// `≤`, `≥`, and `≠` to the corresponding operators, `√` to the
+    /// square root prefix operator, `π` to the number pi, and
+    /// superscript digits, like in `x²`, to a power. Disable it for
+    /// strict inputs, in which case `π` is an ordinary variable name.
+    pub fn with_unicode(mut self, enable: bool) -> Tokenizer<'a> {
+        self.unicode = enable;
+        self
     }
 
-    #[allow(dead_code)]
-    fn take(&mut self, count: usize) -> &'a str {
-        let start = self.chars.as_str();
-        self.skip(count);
-        &start[..start.len() - self.chars.as_str().len()]
+    /// Retain comments as `Token::Comment` tokens instead of skipping
+    /// them like whitespace.
+    ///
+    /// The comment tokens contain the full text of the comment,
+    /// including the delimiters, so that a formatter can reproduce
+    /// them. The parser does not accept comment tokens.
+    pub fn with_comments(mut self, retain: bool) -> Tokenizer<'a> {
+        self.comments = retain;
+        self
+    }
+
+    /// Recognize additional operator symbols, like `+/-` or `..`,
+    /// which are returned as `Token::Operator` tokens.
+    ///
+    /// The symbols are tried in order before any other token or
+    /// comment, so a symbol that is a prefix of another symbol has to
+    /// come after it, and a symbol can start with `#`.
+    pub fn with_operators(mut self, operators: &'a [String]) -> Tokenizer<'a> {
+        self.operators = operators;
+        self
+    }
+
+    /// Read `and`, `or`, and `not` as keywords.
+    ///
+    /// This is enabled by default. Disable it for grammars that write
+    /// the logical operators differently, like `&&` or `AND(a; b)`, so
+    /// that the words can be used as names.
+    pub fn with_logic_keywords(mut self, enable: bool) -> Tokenizer<'a> {
+        self.logic = enable;
+        self
+    }
+
+    /// Read `mod`, `div`, and `xor` as keywords.
+    ///
+    /// This is enabled by default. Disable it for grammars without the
+    /// integer operators, or that write them differently, like `%` and
+    /// `^`, so that the words can be used as names.
+    pub fn with_integer_keywords(mut self, enable: bool) -> Tokenizer<'a> {
+        self.integer = enable;
+        self
+    }
+
+    /// Recognize spreadsheet cell references.
+    ///
+    /// Cell references, like `A1` or `$B$2`, are returned as
+    /// `Token::Cell` tokens instead of names. A sheet name followed
+    /// by `!`, like `Sheet2!` or `'My Sheet'!`, is returned as a
+    /// `Token::Sheet` token and `:` is returned as `Token::Colon`.
+    pub fn with_cells(mut self, enable: bool) -> Tokenizer<'a> {
+        self.cells = enable;
+        self
+    }
+
+    /// Get the next token without consuming it. The token is kept
+    /// until it is returned by `next`, so it is only read once.
+    pub fn peek(&mut self) -> Option<&Token<'a>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.read());
+        }
+        self.peeked.as_ref().and_then(Option::as_ref)
     }
 
     fn skip(&mut self, count: usize) {
@@ -72,30 +368,265 @@ impl<'a> Tokenizer<'a> {
         &start[..start.len() - self.chars.as_str().len()]
     }
 
+    /// Read the rest of a quoted identifier after the opening
+    /// backtick, returning `None` if it is not terminated or contains
+    /// an invalid escape.
+    fn quoted_identifier(&mut self) -> Option<Cow<'a, str>> {
+        let rest = self.chars.as_str();
+        let end = rest.find(['`', '\\'])?;
+        if rest[end..].starts_with('`') {
+            self.chars = rest[end + 1..].chars();
+            return Some(Cow::Borrowed(&rest[..end]));
+        }
+        let mut name = rest[..end].to_string();
+        self.chars = rest[end..].chars();
+        loop {
+            match self.chars.next()? {
+                '`' => return Some(Cow::Owned(name)),
+                '\\' => match self.chars.next()? {
+                    ch @ '`' | ch @ '\\' => name.push(ch),
+                    _ => return None,
+                },
+                ch => name.push(ch),
+            }
+        }
+    }
+
+    /// Read a comment, if there is one at the current position.
+    ///
+    /// Line comments start with `#` or `//` and run to the end of the
+    /// line. Block comments start with `/*` and end with `*/`. An
+    /// unterminated block comment gives `Some(None)`.
+    fn comment(&mut self) -> Option<Option<&'a str>> {
+        let rest = self.chars.as_str();
+        let len = if rest.starts_with('#') || rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            match body.find("*/") {
+                Some(end) => end + 4,
+                None => return Some(None),
+            }
+        } else {
+            return None;
+        };
+        self.chars = rest[len..].chars();
+        Some(Some(&rest[..len]))
+    }
+
+    /// Get the radix of an integer literal prefix (`0x`, `0o`, or
+    /// `0b`), if there is one at the current position.
+    fn radix(&self) -> Option<Radix> {
+        let rest = self.chars.as_str().as_bytes();
+        match (rest.first(), rest.get(1)) {
+            (Some(b'0'), Some(b'x')) | (Some(b'0'), Some(b'X')) => Some(Radix::Hexadecimal),
+            (Some(b'0'), Some(b'o')) | (Some(b'0'), Some(b'O')) => Some(Radix::Octal),
+            (Some(b'0'), Some(b'b')) | (Some(b'0'), Some(b'B')) => Some(Radix::Binary),
+            _ => None,
+        }
+    }
+
+    /// Read an integer literal with a radix prefix. Underscores can
+    /// be used to separate groups of digits.
+    fn integer(&mut self) -> Option<Token<'a>> {
+        let radix = self.radix()?;
+        let base = radix.base();
+        self.skip(2);
+        let digits = self.take_while(move |ch| ch.is_digit(base) || ch == '_');
+        let mut value: u64 = 0;
+        let mut empty = true;
+        for digit in digits.chars().filter_map(|ch| ch.to_digit(base)) {
+            empty = false;
+            match value
+                .checked_mul(u64::from(base))
+                .and_then(|value| value.checked_add(u64::from(digit)))
+            {
+                Some(next) => value = next,
+                None => return self.fail(),
+            }
+        }
+        if empty {
+            return self.fail();
+        }
+        Some(Token::Integer(value, radix))
+    }
+
+    /// Read a superscript exponent, which is an optional superscript
+    /// minus followed by superscript digits.
+    fn superscript(&mut self) -> Option<Token<'a>> {
+        let negative = self.chars.as_str().starts_with('⁻');
+        if negative {
+            self.skip(1);
+        }
+        let digits = self.take_while(|ch| SUPERSCRIPTS.contains(&ch));
+        let exponent = digits
+            .chars()
+            .filter_map(|ch| SUPERSCRIPTS.iter().position(|&digit| digit == ch))
+            .try_fold(0i32, |acc, digit| {
+                acc.checked_mul(10)?.checked_add(digit as i32)
+            });
+        match exponent {
+            _ if digits.is_empty() => self.fail(),
+            Some(exponent) if negative => Some(Token::Superscript(-exponent)),
+            Some(exponent) => Some(Token::Superscript(exponent)),
+            _ => self.fail(),
+        }
+    }
+
+    /// Read a cell reference, a sheet name, or a colon, if there is
+    /// one at the current position.
+    fn reference(&mut self) -> Option<Token<'a>> {
+        let rest = self.chars.as_str();
+        if let Some(quoted) = rest.strip_prefix('\'') {
+            // Quoted sheet names use two quotes for a quote
+            let mut name = Cow::Borrowed("");
+            let mut chars = quoted.chars();
+            loop {
+                let start = chars.as_str();
+                let end = match start.find('\'') {
+                    Some(end) => end,
+                    None => return self.fail(),
+                };
+                chars = start[end + 1..].chars();
+                if !chars.as_str().starts_with('\'') {
+                    name = if name.is_empty() {
+                        Cow::Borrowed(&start[..end])
+                    } else {
+                        Cow::Owned(name.into_owned() + &start[..end])
+                    };
+                    break;
+                }
+                chars.next();
+                name = Cow::Owned(name.into_owned() + &start[..=end]);
+            }
+            self.chars = chars;
+            return match self.chars.next() {
+                Some('!') => Some(Token::Sheet(name)),
+                _ => self.fail(),
+            };
+        }
+        if rest.starts_with(':') {
+            self.skip(1);
+            return Some(Token::Colon);
+        }
+
+        let len = rest
+            .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+            .unwrap_or(rest.len());
+        let after = &rest[len..];
+        let named = rest.starts_with(char::is_alphabetic);
+        if named && after.starts_with('!') && !after.starts_with("!=") {
+            self.chars = after[1..].chars();
+            return Some(Token::Sheet(Cow::Borrowed(&rest[..len])));
+        }
+        let (cell, len) = CellRef::read(rest)?;
+        self.chars = rest[len..].chars();
+        Some(Token::Cell(cell))
+    }
+
+    /// Read a custom operator symbol, if there is one at the current
+    /// position.
+    fn operator(&mut self) -> Option<Token<'a>> {
+        let rest = self.chars.as_str();
+        let symbol = self
+            .operators
+            .iter()
+            .find(|symbol| rest.starts_with(symbol.as_str()))?;
+        self.chars = rest[symbol.len()..].chars();
+        Some(Token::Operator(Cow::Borrowed(symbol)))
+    }
+
+    /// Read a decimal number. A dot directly followed by another dot
+    /// is not part of the number, so that `1..5` can be read as an
+    /// operator between two numbers.
+    fn number(&mut self) -> Option<Token<'a>> {
+        let rest = self.chars.as_str();
+        let mut len = 0;
+        for (index, ch) in rest.char_indices() {
+            if !(ch.is_ascii_digit() || ch == '.' && !rest[index + 1..].starts_with('.')) {
+                break;
+            }
+            len = index + 1;
+        }
+        self.chars = rest[len..].chars();
+        rest[..len].parse::<f64>().map(Token::Float).ok()
+    }
+
+    /// Give an error token and stop tokenizing since there is no
+    /// sensible place to resume.
+    fn fail(&mut self) -> Option<Token<'a>> {
+        self.chars = "".chars();
+        Some(Token::Error)
+    }
+
     fn skip_while<P>(&mut self, pred: P)
     where
         P: Copy + FnMut(char) -> bool,
     {
-        while self.chars.clone().next().map_or(false, pred) {
+        while self.chars.clone().next().is_some_and(pred) {
             self.chars.next();
         }
     }
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
-    type Item = Token;
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        match self.peeked.take() {
+            Some(token) => token,
+            None => self.read(),
+        }
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Read the next token from the input.
+    #[allow(clippy::is_digit_ascii_radix)]
+    fn read(&mut self) -> Option<Token<'a>> {
+        loop {
+            self.skip_while(|ch| ch.is_whitespace());
+            if let Some(token) = self.operator() {
+                return Some(token);
+            }
+            match self.comment() {
+                Some(Some(text)) if self.comments => {
+                    return Some(Token::Comment(Cow::Borrowed(text)))
+                }
+                Some(Some(_)) => continue,
+                Some(None) => return self.fail(),
+                None => break,
+            }
+        }
+
+        if self.cells {
+            if let Some(token) = self.reference() {
+                return Some(token);
+            }
+        }
 
-    fn next(&mut self) -> Option<Token> {
-        self.skip_while(|ch| ch.is_whitespace());
         match self.chars.clone().next() {
-            Some(ch) if ch.is_digit(10) => self
-                .take_while(|ch| ch.is_digit(10) || ch == '.')
-                .parse::<f64>()
-                .map(Token::Float)
-                .ok(),
+            Some(ch) if self.unicode && is_superscript(ch) => self.superscript(),
+            Some(ch) if self.unicode && unicode_alias(ch).is_some() => {
+                self.skip(1);
+                unicode_alias(ch)
+            }
+            Some('0') if self.radix().is_some() => self.integer(),
+            Some(ch) if ch.is_digit(10) => self.number(),
             Some(ch) if ch.is_alphabetic() => {
                 let name = self.take_while(|c| c.is_alphabetic() || c == '_' || c.is_digit(10));
-                Some(Token::Symbol(name.to_string()))
+                let keyword = keyword(name).filter(|token| match token {
+                    Token::And | Token::Or | Token::Not => self.logic,
+                    Token::Mod | Token::Div | Token::Xor => self.integer,
+                    _ => true,
+                });
+                Some(keyword.unwrap_or(Token::Symbol(Cow::Borrowed(name))))
+            }
+            Some('`') => {
+                self.skip(1);
+                match self.quoted_identifier() {
+                    Some(name) => Some(Token::Quoted(name)),
+                    None => self.fail(),
+                }
             }
             Some('+') => {
                 self.skip(1);
@@ -125,7 +656,76 @@ impl<'a> Iterator for Tokenizer<'a> {
                 self.skip(1);
                 Some(Token::Close)
             }
-            None | Some(_) => None,
+            Some('.') => {
+                self.skip(1);
+                Some(Token::Dot)
+            }
+            Some(',') => {
+                self.skip(1);
+                Some(Token::Comma)
+            }
+            Some(';') => {
+                self.skip(1);
+                Some(Token::Semicolon)
+            }
+            Some('=') if self.chars.as_str().starts_with("==") => {
+                self.skip(2);
+                Some(Token::Equal)
+            }
+            Some('=') => {
+                self.skip(1);
+                Some(Token::Assign)
+            }
+            Some('!') if self.chars.as_str().starts_with("!=") => {
+                self.skip(2);
+                Some(Token::NotEqual)
+            }
+            Some('!') => {
+                self.skip(1);
+                Some(Token::Bang)
+            }
+            Some('%') => {
+                self.skip(1);
+                Some(Token::Percent)
+            }
+            Some('&') => {
+                self.skip(1);
+                Some(Token::Ampersand)
+            }
+            Some('|') => {
+                self.skip(1);
+                Some(Token::Pipe)
+            }
+            Some('~') => {
+                self.skip(1);
+                Some(Token::Tilde)
+            }
+            Some('<') if self.chars.as_str().starts_with("<<") => {
+                self.skip(2);
+                Some(Token::ShiftLeft)
+            }
+            Some('>') if self.chars.as_str().starts_with(">>") => {
+                self.skip(2);
+                Some(Token::ShiftRight)
+            }
+            Some('<') if self.chars.as_str().starts_with("<=") => {
+                self.skip(2);
+                Some(Token::LessEqual)
+            }
+            Some('>') if self.chars.as_str().starts_with(">=") => {
+                self.skip(2);
+                Some(Token::GreaterEqual)
+            }
+            Some('<') => {
+                self.skip(1);
+                Some(Token::Less)
+            }
+            Some('>') => {
+                self.skip(1);
+                Some(Token::Greater)
+            }
+            Some(_) => self.fail(),
+            None => None,
         }
     }
 }
@@ -136,9 +736,9 @@ mod tests {
     use super::*;
     //    use test::{black_box, Bencher};
 
-    fn assert_tokens(text: &str, expected: Vec<Token>) {
+    fn assert_tokens(text: &str, expected: Vec<Token<'_>>) {
         let tokenizer = Tokenizer::new(text);
-        let tokens: Vec<Token> = tokenizer.collect();
+        let tokens: Vec<Token<'_>> = tokenizer.collect();
         assert_eq!(tokens, expected);
     }
 
@@ -152,8 +752,8 @@ mod tests {
 
     #[test]
     fn operators() {
-        let x = "x".to_string();
-        let y = "y".to_string();
+        let x = Cow::from("x");
+        let y = Cow::from("y");
         assert_tokens("10+", vec![Float(10.0), Plus]);
         assert_tokens("-12", vec![Minus, Float(12.0)]);
         assert_tokens("+*-/", vec![Plus, Star, Minus, Slash]);
@@ -164,12 +764,297 @@ mod tests {
 
     #[test]
     fn variables() {
-        let x = "x".to_string();
-        assert_tokens("x12", vec![Symbol("x12".to_string())]);
+        let x = Cow::from("x");
+        assert_tokens("x12", vec![Symbol("x12".into())]);
         assert_tokens("12x", vec![Float(12.0), Symbol(x)]);
-        assert_tokens("12.0zzz", vec![Float(12.0), Symbol("zzz".to_string())]);
-        assert_tokens("12.a_3", vec![Float(12.0), Symbol("a_3".to_string())]);
-        assert_tokens("a_12", vec![Symbol("a_12".to_string())]);
+        assert_tokens("12.0zzz", vec![Float(12.0), Symbol("zzz".into())]);
+        assert_tokens("12.a_3", vec![Float(12.0), Symbol("a_3".into())]);
+        assert_tokens("a_12", vec![Symbol("a_12".into())]);
+    }
+
+    #[test]
+    fn postfix() {
+        let n = Cow::from("n");
+        assert_tokens("n!", vec![Symbol(n.clone()), Token::Bang]);
+        assert_tokens("n!!", vec![Symbol(n.clone()), Token::Bang, Token::Bang]);
+        assert_tokens("n!=3", vec![Symbol(n.clone()), Token::NotEqual, Float(3.0)]);
+        assert_tokens("15%", vec![Float(15.0), Token::Percent]);
+        assert_tokens("15 % n", vec![Float(15.0), Token::Percent, Symbol(n)]);
+    }
+
+    #[test]
+    fn integers() {
+        use Radix::Hexadecimal as Hex;
+        assert_tokens("0xF", vec![Token::Integer(15, Hex)]);
+        assert_tokens("0XfF", vec![Token::Integer(255, Hex)]);
+        assert_tokens("0b1010", vec![Token::Integer(10, Radix::Binary)]);
+        assert_tokens("0o17", vec![Token::Integer(15, Radix::Octal)]);
+        assert_tokens("0xFFFF_FFFF", vec![Token::Integer(0xFFFF_FFFF, Hex)]);
+        assert_tokens("0xffffffffffffffff", vec![Token::Integer(u64::MAX, Hex)]);
+        assert_tokens("0x1_0000_0000_0000_0000", vec![Token::Error]);
+        assert_tokens("0x", vec![Token::Error]);
+        assert_tokens("0b12", vec![Token::Integer(1, Radix::Binary), Float(2.0)]);
+        assert_tokens("0.5", vec![Float(0.5)]);
+    }
+
+    #[test]
+    fn bitwise() {
+        let x = Cow::from("x");
+        assert_tokens(
+            "(x >> 4) & 0xF | x << 8",
+            vec![
+                Token::Open,
+                Symbol(x.clone()),
+                Token::ShiftRight,
+                Float(4.0),
+                Token::Close,
+                Token::Ampersand,
+                Token::Integer(15, Radix::Hexadecimal),
+                Token::Pipe,
+                Symbol(x.clone()),
+                Token::ShiftLeft,
+                Float(8.0),
+            ],
+        );
+        assert_tokens(
+            "~x mod 3 div 2 xor x",
+            vec![
+                Token::Tilde,
+                Symbol(x.clone()),
+                Token::Mod,
+                Float(3.0),
+                Token::Div,
+                Float(2.0),
+                Token::Xor,
+                Symbol(x),
+            ],
+        );
+        assert_tokens("modulo", vec![Symbol("modulo".into())]);
+        let tokens: Vec<Token<'_>> = Tokenizer::new("div xor")
+            .with_integer_keywords(false)
+            .collect();
+        assert_eq!(tokens, vec![Symbol("div".into()), Symbol("xor".into())]);
+        assert_tokens("`mod`", vec![Token::Quoted("mod".into())]);
+    }
+
+    #[test]
+    fn comparisons() {
+        let x = Cow::from("x");
+        assert_tokens(
+            "x < 1 <= 2 > 3 >= 4 == 5 != 6",
+            vec![
+                Symbol(x),
+                Token::Less,
+                Float(1.0),
+                Token::LessEqual,
+                Float(2.0),
+                Token::Greater,
+                Float(3.0),
+                Token::GreaterEqual,
+                Float(4.0),
+                Token::Equal,
+                Float(5.0),
+                Token::NotEqual,
+                Float(6.0),
+            ],
+        );
+        assert_tokens("1<<2", vec![Float(1.0), Token::ShiftLeft, Float(2.0)]);
+        assert_tokens(
+            "a = 1; not b and c or d",
+            vec![
+                Symbol("a".into()),
+                Token::Assign,
+                Float(1.0),
+                Token::Semicolon,
+                Token::Not,
+                Symbol("b".into()),
+                Token::And,
+                Symbol("c".into()),
+                Token::Or,
+                Symbol("d".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn unicode() {
+        let x = Cow::from("x");
+        assert_tokens(
+            "2×x÷3\u{2212}1",
+            vec![
+                Float(2.0),
+                Star,
+                Symbol(x.clone()),
+                Slash,
+                Float(3.0),
+                Minus,
+                Float(1.0),
+            ],
+        );
+        assert_tokens("√x", vec![Token::Root, Symbol(x.clone())]);
+        assert_tokens("2π", vec![Float(2.0), Float(std::f64::consts::PI)]);
+        assert_tokens(
+            "x ≤ 1 ≥ 2 ≠ 3",
+            vec![
+                Symbol(x.clone()),
+                Token::LessEqual,
+                Float(1.0),
+                Token::GreaterEqual,
+                Float(2.0),
+                Token::NotEqual,
+                Float(3.0),
+            ],
+        );
+        assert_tokens("x²", vec![Symbol(x.clone()), Token::Superscript(2)]);
+        assert_tokens("x¹⁰", vec![Symbol(x.clone()), Token::Superscript(10)]);
+        assert_tokens("x⁻¹", vec![Symbol(x.clone()), Token::Superscript(-1)]);
+        assert_tokens("x⁻", vec![Symbol(x.clone()), Token::Error]);
+        assert_eq!(Token::Superscript(-12).to_string(), "⁻¹²");
+
+        let tokens: Vec<Token<'_>> = Tokenizer::new("π×x").with_unicode(false).collect();
+        assert_eq!(tokens, vec![Symbol("π".into()), Token::Error]);
+    }
+
+    #[test]
+    fn custom_operators() {
+        let operators = ["+/-".to_string(), "..".to_string()];
+        let tokens: Vec<Token<'_>> = Tokenizer::new("x +/- 1..5, -y")
+            .with_operators(&operators)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Symbol("x".into()),
+                Token::Operator("+/-".into()),
+                Float(1.0),
+                Token::Operator("..".into()),
+                Float(5.0),
+                Token::Comma,
+                Minus,
+                Symbol("y".into()),
+            ]
+        );
+        assert_tokens("1..5", vec![Float(1.0), Token::Dot, Token::Dot, Float(5.0)]);
+
+        let operators = ["#inf".to_string()];
+        let tokens: Vec<Token<'_>> = Tokenizer::new("#inf # inf")
+            .with_operators(&operators)
+            .collect();
+        assert_eq!(tokens, vec![Token::Operator("#inf".into())]);
+    }
+
+    #[test]
+    fn paths() {
+        assert_tokens(
+            "order.total",
+            vec![Symbol("order".into()), Token::Dot, Symbol("total".into())],
+        );
+        assert_tokens(
+            "a . b",
+            vec![Symbol("a".into()), Token::Dot, Symbol("b".into())],
+        );
+        assert_tokens("12.x", vec![Float(12.0), Symbol("x".into())]);
+    }
+
+    #[test]
+    fn quoted() {
+        assert_tokens(
+            "`Gross Margin %`",
+            vec![Token::Quoted("Gross Margin %".into())],
+        );
+        assert_tokens(
+            "`unit-price`*2",
+            vec![Token::Quoted("unit-price".into()), Star, Float(2.0)],
+        );
+        assert_tokens(r"`a\`b\\c`", vec![Token::Quoted(r"a`b\c".into())]);
+        assert_tokens("``", vec![Token::Quoted("".into())]);
+        assert_tokens("`abc", vec![Token::Error]);
+        assert_tokens(r"`a\nb`", vec![Token::Error]);
+    }
+
+    #[test]
+    fn comments() {
+        let x = Cow::from("x");
+        assert_tokens("x # comment", vec![Symbol(x.clone())]);
+        assert_tokens(
+            "x // comment\n+ 1",
+            vec![Symbol(x.clone()), Plus, Float(1.0)],
+        );
+        assert_tokens(
+            "x /* a\nb */ / 2",
+            vec![Symbol(x.clone()), Slash, Float(2.0)],
+        );
+        assert_tokens("/**/x#", vec![Symbol(x.clone())]);
+        assert_tokens("x /* open", vec![Symbol(x.clone()), Token::Error]);
+
+        let tokens: Vec<Token<'_>> = Tokenizer::new("x /* a */ + 1 // one\n")
+            .with_comments(true)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Symbol(x),
+                Token::Comment("/* a */".into()),
+                Plus,
+                Float(1.0),
+                Token::Comment("// one".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn identifiers() {
+        assert!(is_identifier("x"));
+        assert!(is_identifier("a_12"));
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("_a"));
+        assert!(!is_identifier("12x"));
+        assert!(!is_identifier("unit-price"));
+        assert!(!is_identifier("xor"));
+        assert_eq!(Identifier("mod").to_string(), "`mod`");
+        assert_eq!(Identifier("x_1").to_string(), "x_1");
+        assert_eq!(Identifier("Gross Margin %").to_string(), "`Gross Margin %`");
+        assert_eq!(Identifier(r"a`b\c").to_string(), r"`a\`b\\c`");
+    }
+
+    #[test]
+    fn borrowed() {
+        let borrowed = |token: &Token<'_>| match token {
+            Symbol(name) | Token::Quoted(name) | Token::Sheet(name) => {
+                matches!(name, Cow::Borrowed(_))
+            }
+            _ => true,
+        };
+        let text = "price * `unit price` + Sheet1!A1 + 'My Sheet'!B2";
+        let tokens: Vec<Token<'_>> = Tokenizer::new(text).with_cells(true).collect();
+        assert!(tokens.iter().all(borrowed));
+        let tokens: Vec<Token<'_>> = Tokenizer::new(r"`a\`b` + 'It''s'!A1")
+            .with_cells(true)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Quoted("a`b".into()),
+                Plus,
+                Token::Sheet("It's".into()),
+                Token::Cell(CellRef::new(1, 1)),
+            ]
+        );
+        assert!(!borrowed(&tokens[0]) && !borrowed(&tokens[2]));
+        assert_eq!(Symbol("x".into()).into_owned(), Symbol("x".into()));
+    }
+
+    #[test]
+    fn peek() {
+        let mut tokens = Tokenizer::new("x + 1");
+        assert_eq!(tokens.peek(), Some(&Symbol("x".into())));
+        assert_eq!(tokens.peek(), Some(&Symbol("x".into())));
+        assert_eq!(tokens.next(), Some(Symbol("x".into())));
+        assert_eq!(tokens.next(), Some(Plus));
+        assert_eq!(tokens.peek(), Some(&Float(1.0)));
+        assert_eq!(tokens.next(), Some(Float(1.0)));
+        assert_eq!(tokens.peek(), None);
+        assert_eq!(tokens.next(), None);
     }
 
     #[test]
@@ -1,44 +1,900 @@
+use crate::dialect::Dialect;
+use crate::spreadsheet::{CellRef, Resolver};
+use crate::tokens::Identifier;
+use crate::visit::{fold_operands, Fold, Folded, Pair, Visitor};
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Error {
-    NoValue(String),
+    /// A variable or path without a value, with the segment of the
+    /// path that was missing, or that is a record rather than a
+    /// number if it is the last one.
+    NoValue {
+        path: String,
+        missing: String,
+    },
+    NoFunction(String),
+    NoCell(String),
+    UnexpectedRange(String),
+    /// A function like `AVERAGE` or `MIN` called without any values,
+    /// like with a range of empty cells.
+    NoArguments(String),
+    NotAnInteger(#[cfg_attr(feature = "serde", serde(with = "crate::document::float"))] f64),
+    /// An integer passed to a function that has no exact floating-point
+    /// value, because its magnitude is above 2^53.
+    NotAFloat(i128),
+    /// An operand for which a function is not defined, like the
+    /// factorial of a negative integer.
+    OutOfDomain {
+        function: String,
+        #[cfg_attr(feature = "serde", serde(with = "crate::document::float"))]
+        value: f64,
+    },
+    Overflow,
+    DivisionByZero,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "node", content = "args", rename_all = "snake_case")
+)]
 pub enum ExprTree {
     Var(String),
-    Float(f64),
+    Path(Vec<String>),
+    Float(#[cfg_attr(feature = "serde", serde(with = "crate::document::float"))] f64),
+    /// Integer literal, with the radix it was written in.
+    Integer(u64, Radix),
     Neg(Box<ExprTree>),
+    BitNot(Box<ExprTree>),
+    Abs(Box<ExprTree>),
+    Sqrt(Box<ExprTree>),
+    Factorial(Box<ExprTree>),
+    Percent(Box<ExprTree>),
     Add(Box<ExprTree>, Box<ExprTree>),
     Sub(Box<ExprTree>, Box<ExprTree>),
     Mul(Box<ExprTree>, Box<ExprTree>),
     Div(Box<ExprTree>, Box<ExprTree>),
+    Mod(Box<ExprTree>, Box<ExprTree>),
+    IntDiv(Box<ExprTree>, Box<ExprTree>),
+    BitAnd(Box<ExprTree>, Box<ExprTree>),
+    BitOr(Box<ExprTree>, Box<ExprTree>),
+    BitXor(Box<ExprTree>, Box<ExprTree>),
+    Shl(Box<ExprTree>, Box<ExprTree>),
+    Shr(Box<ExprTree>, Box<ExprTree>),
+    Pow(Box<ExprTree>, Box<ExprTree>),
+    Eq(Box<ExprTree>, Box<ExprTree>),
+    Ne(Box<ExprTree>, Box<ExprTree>),
+    Lt(Box<ExprTree>, Box<ExprTree>),
+    Le(Box<ExprTree>, Box<ExprTree>),
+    Gt(Box<ExprTree>, Box<ExprTree>),
+    Ge(Box<ExprTree>, Box<ExprTree>),
+    Not(Box<ExprTree>),
+    And(Box<ExprTree>, Box<ExprTree>),
+    Or(Box<ExprTree>, Box<ExprTree>),
+    Call(String, Vec<ExprTree>),
+    Cell(Option<String>, CellRef),
+    Range(Option<String>, CellRef, CellRef),
+}
+
+/// Radix of an integer literal, kept so that printing the literal
+/// gives back the digits it was written with.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Radix {
+    Binary,
+    Octal,
+    Hexadecimal,
+}
+
+impl Radix {
+    /// Get the radix with the given base, if it is 2, 8, or 16.
+    pub fn from_base(base: u32) -> Option<Radix> {
+        match base {
+            2 => Some(Radix::Binary),
+            8 => Some(Radix::Octal),
+            16 => Some(Radix::Hexadecimal),
+            _ => None,
+        }
+    }
+
+    pub fn base(self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Hexadecimal => 16,
+        }
+    }
+
+    /// Letter of the prefix of a literal, like `x` in `0xFF`.
+    pub fn letter(self) -> char {
+        match self {
+            Radix::Binary => 'b',
+            Radix::Octal => 'o',
+            Radix::Hexadecimal => 'x',
+        }
+    }
+
+    /// Write the digits of a number in this radix, without a prefix.
+    pub fn digits(self, num: u64) -> String {
+        match self {
+            Radix::Binary => format!("{:b}", num),
+            Radix::Octal => format!("{:o}", num),
+            Radix::Hexadecimal => format!("{:x}", num),
+        }
+    }
+}
+
+/// Entry found when looking up a name in a context.
+///
+/// A name either refers to a number directly or to a record, which
+/// is itself a context that the remaining members of a path are
+/// looked up in.
+pub enum Entry<'a> {
+    Number(f64),
+    Record(&'a dyn Context),
+}
+
+/// Context used to resolve variables and paths during evaluation.
+///
+/// Implement this for your own structures to evaluate expressions
+/// like `order.total * customer.discount` directly against them.
+pub trait Context {
+    fn lookup(&self, name: &str) -> Option<Entry<'_>>;
+
+    /// Call a function with already evaluated arguments, giving
+    /// `None` if there is no function with that name. By default, no
+    /// functions are defined.
+    fn call(&self, _name: &str, _args: &[f64]) -> Option<Result<f64>> {
+        None
+    }
+
+    /// Get the resolver supplying the values of spreadsheet cells,
+    /// if there is one. By default, there are no cells.
+    fn resolver(&self) -> Option<&dyn Resolver> {
+        None
+    }
+}
+
+impl Context for HashMap<String, f64> {
+    fn lookup(&self, name: &str) -> Option<Entry<'_>> {
+        self.get(name).map(|value| Entry::Number(*value))
+    }
+}
+
+/// Value stored in a nested context.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Number(f64),
+    Record(HashMap<String, Value>),
+}
+
+impl Context for HashMap<String, Value> {
+    fn lookup(&self, name: &str) -> Option<Entry<'_>> {
+        self.get(name).map(|value| match value {
+            Value::Number(num) => Entry::Number(*num),
+            Value::Record(record) => Entry::Record(record),
+        })
+    }
 }
 
 impl ExprTree {
-    pub fn eval(self, map: &HashMap<String, f64>) -> Result<f64> {
+    /// Evaluate the expression using floating-point arithmetic.
+    ///
+    /// The bitwise operators require operands that are integers in
+    /// the range of `i64` and compute the result in that type.
+    /// Comparisons give 1 if true and 0 if false. The logical
+    /// operators treat any non-zero value as true and only evaluate
+    /// the right operand when needed.
+    pub fn eval<C: Context + ?Sized>(&self, ctx: &C) -> Result<f64> {
+        Evaluator { ctx }.fold(self)
+    }
+
+    /// Replace every use of a variable with a tree.
+    pub fn substitute(&self, name: &str, value: &ExprTree) -> ExprTree {
+        Substitute { name, value }.fold(self)
+    }
+
+    /// Evaluate the parts of the tree that can be evaluated using the
+    /// context, giving the residual tree.
+    ///
+    /// Variables, paths, and cells with a value in the context are
+    /// replaced by the value, operators with constant operands are
+    /// folded into a constant, and functions are called if the context
+    /// defines them and all arguments are constant. Everything else
+    /// is left as it is, so `(a + b) * c` with `a` and `b` known
+    /// becomes `3 * c`. Operators whose evaluation fails, or gives a
+    /// value that is not finite, are not folded, so the error is
+    /// reported when the residual tree is evaluated.
+    pub fn partial_eval<C: Context + ?Sized>(&self, known: &C) -> ExprTree {
+        PartialEval { known }.fold(self)
+    }
+
+    /// Get the names of the variables and paths used in the tree, in
+    /// the order they first occur. Paths are written as in an
+    /// expression, like `order.total`, with the members that are not
+    /// plain identifiers quoted, like ``order.`unit.price` ``.
+    pub fn free_variables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        Walk(|tree: &ExprTree| {
+            let name = match tree {
+                ExprTree::Var(name) => name.clone(),
+                ExprTree::Path(path) => path_name(path),
+                _ => return,
+            };
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        })
+        .visit(self);
+        names
+    }
+
+    /// Get the names of the functions called in the tree, in the
+    /// order they first occur.
+    pub fn functions<'a>(&'a self) -> Vec<&'a str> {
+        let mut names = Vec::new();
+        Walk(|tree: &'a ExprTree| {
+            if let ExprTree::Call(name, _) = tree {
+                if !names.contains(&name.as_str()) {
+                    names.push(name.as_str());
+                }
+            }
+        })
+        .visit(self);
+        names
+    }
+
+    /// Get the number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        Walk(|_: &ExprTree| count += 1).visit(self);
+        count
+    }
+
+    /// Get the depth of the tree, where a tree with a single node has
+    /// depth 1.
+    pub fn depth(&self) -> usize {
+        Depth.fold(self)
+    }
+
+    /// Check if the tree is constant, that is, if it gives the same
+    /// value in every context. A tree using variables, paths, cells,
+    /// or calling functions is not constant, since functions are
+    /// defined by the context.
+    pub fn is_constant(&self) -> bool {
+        Constant.fold(self)
+    }
+
+    /// Get the operands of the node, in order.
+    #[inline]
+    pub fn operands(&self) -> Operands<'_> {
+        let pair = |first, second| Operands(Pair::Two(first, second));
+        match self {
+            ExprTree::Var(..)
+            | ExprTree::Path(..)
+            | ExprTree::Float(..)
+            | ExprTree::Integer(..)
+            | ExprTree::Cell(..)
+            | ExprTree::Range(..) => pair(None, None),
+            ExprTree::Neg(expr)
+            | ExprTree::BitNot(expr)
+            | ExprTree::Abs(expr)
+            | ExprTree::Sqrt(expr)
+            | ExprTree::Factorial(expr)
+            | ExprTree::Percent(expr)
+            | ExprTree::Not(expr) => pair(Some(expr), None),
+            ExprTree::Add(lhs, rhs)
+            | ExprTree::Sub(lhs, rhs)
+            | ExprTree::Mul(lhs, rhs)
+            | ExprTree::Div(lhs, rhs)
+            | ExprTree::Mod(lhs, rhs)
+            | ExprTree::IntDiv(lhs, rhs)
+            | ExprTree::BitAnd(lhs, rhs)
+            | ExprTree::BitOr(lhs, rhs)
+            | ExprTree::BitXor(lhs, rhs)
+            | ExprTree::Shl(lhs, rhs)
+            | ExprTree::Shr(lhs, rhs)
+            | ExprTree::Pow(lhs, rhs)
+            | ExprTree::Eq(lhs, rhs)
+            | ExprTree::Ne(lhs, rhs)
+            | ExprTree::Lt(lhs, rhs)
+            | ExprTree::Le(lhs, rhs)
+            | ExprTree::Gt(lhs, rhs)
+            | ExprTree::Ge(lhs, rhs)
+            | ExprTree::And(lhs, rhs)
+            | ExprTree::Or(lhs, rhs) => pair(Some(lhs), Some(rhs)),
+            ExprTree::Call(_, args) => Operands(Pair::Many(args.iter())),
+        }
+    }
+
+    /// Get mutable references to the operands of the node, in order.
+    pub fn operands_mut(&mut self) -> OperandsMut<'_> {
+        let pair = |first, second| OperandsMut(Pair::Two(first, second));
+        match self {
+            ExprTree::Var(..)
+            | ExprTree::Path(..)
+            | ExprTree::Float(..)
+            | ExprTree::Integer(..)
+            | ExprTree::Cell(..)
+            | ExprTree::Range(..) => pair(None, None),
+            ExprTree::Neg(expr)
+            | ExprTree::BitNot(expr)
+            | ExprTree::Abs(expr)
+            | ExprTree::Sqrt(expr)
+            | ExprTree::Factorial(expr)
+            | ExprTree::Percent(expr)
+            | ExprTree::Not(expr) => pair(Some(expr), None),
+            ExprTree::Add(lhs, rhs)
+            | ExprTree::Sub(lhs, rhs)
+            | ExprTree::Mul(lhs, rhs)
+            | ExprTree::Div(lhs, rhs)
+            | ExprTree::Mod(lhs, rhs)
+            | ExprTree::IntDiv(lhs, rhs)
+            | ExprTree::BitAnd(lhs, rhs)
+            | ExprTree::BitOr(lhs, rhs)
+            | ExprTree::BitXor(lhs, rhs)
+            | ExprTree::Shl(lhs, rhs)
+            | ExprTree::Shr(lhs, rhs)
+            | ExprTree::Pow(lhs, rhs)
+            | ExprTree::Eq(lhs, rhs)
+            | ExprTree::Ne(lhs, rhs)
+            | ExprTree::Lt(lhs, rhs)
+            | ExprTree::Le(lhs, rhs)
+            | ExprTree::Gt(lhs, rhs)
+            | ExprTree::Ge(lhs, rhs)
+            | ExprTree::And(lhs, rhs)
+            | ExprTree::Or(lhs, rhs) => pair(Some(lhs), Some(rhs)),
+            ExprTree::Call(_, args) => OperandsMut(Pair::Many(args.iter_mut())),
+        }
+    }
+
+    /// Build a node of the same kind as this one with new operands,
+    /// given in the order of [`operands`](ExprTree::operands).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are fewer operands than the node has.
+    pub fn with_operands<I>(&self, operands: I) -> ExprTree
+    where
+        I: IntoIterator<Item = ExprTree>,
+    {
+        if let ExprTree::Call(name, _) = self {
+            return ExprTree::Call(name.clone(), operands.into_iter().collect());
+        }
+        let mut operands = operands.into_iter();
+        let mut next = || Box::new(operands.next().expect("too few operands"));
         match self {
-            ExprTree::Float(num) => Ok(num),
-            ExprTree::Var(name) => map
-                .get(&name)
-                .ok_or_else(|| Error::NoValue(name.clone()))
-                .map(Clone::clone),
-            ExprTree::Neg(expr) => Ok(-expr.eval(map)?),
-            ExprTree::Add(lhs, rhs) => Ok(lhs.eval(map)? + rhs.eval(map)?),
-            ExprTree::Sub(lhs, rhs) => Ok(lhs.eval(map)? - rhs.eval(map)?),
-            ExprTree::Mul(lhs, rhs) => Ok(lhs.eval(map)? * rhs.eval(map)?),
-            ExprTree::Div(lhs, rhs) => Ok(lhs.eval(map)? / rhs.eval(map)?),
+            ExprTree::Var(..)
+            | ExprTree::Path(..)
+            | ExprTree::Float(..)
+            | ExprTree::Integer(..)
+            | ExprTree::Cell(..)
+            | ExprTree::Range(..)
+            | ExprTree::Call(..) => self.clone(),
+            ExprTree::Neg(_) => ExprTree::Neg(next()),
+            ExprTree::BitNot(_) => ExprTree::BitNot(next()),
+            ExprTree::Abs(_) => ExprTree::Abs(next()),
+            ExprTree::Sqrt(_) => ExprTree::Sqrt(next()),
+            ExprTree::Factorial(_) => ExprTree::Factorial(next()),
+            ExprTree::Percent(_) => ExprTree::Percent(next()),
+            ExprTree::Not(_) => ExprTree::Not(next()),
+            ExprTree::Add(..) => ExprTree::Add(next(), next()),
+            ExprTree::Sub(..) => ExprTree::Sub(next(), next()),
+            ExprTree::Mul(..) => ExprTree::Mul(next(), next()),
+            ExprTree::Div(..) => ExprTree::Div(next(), next()),
+            ExprTree::Mod(..) => ExprTree::Mod(next(), next()),
+            ExprTree::IntDiv(..) => ExprTree::IntDiv(next(), next()),
+            ExprTree::BitAnd(..) => ExprTree::BitAnd(next(), next()),
+            ExprTree::BitOr(..) => ExprTree::BitOr(next(), next()),
+            ExprTree::BitXor(..) => ExprTree::BitXor(next(), next()),
+            ExprTree::Shl(..) => ExprTree::Shl(next(), next()),
+            ExprTree::Shr(..) => ExprTree::Shr(next(), next()),
+            ExprTree::Pow(..) => ExprTree::Pow(next(), next()),
+            ExprTree::Eq(..) => ExprTree::Eq(next(), next()),
+            ExprTree::Ne(..) => ExprTree::Ne(next(), next()),
+            ExprTree::Lt(..) => ExprTree::Lt(next(), next()),
+            ExprTree::Le(..) => ExprTree::Le(next(), next()),
+            ExprTree::Gt(..) => ExprTree::Gt(next(), next()),
+            ExprTree::Ge(..) => ExprTree::Ge(next(), next()),
+            ExprTree::And(..) => ExprTree::And(next(), next()),
+            ExprTree::Or(..) => ExprTree::Or(next(), next()),
+        }
+    }
+}
+
+/// Iterator over the operands of a node, in order, given by
+/// [`ExprTree::operands`].
+#[derive(Debug, Clone)]
+pub struct Operands<'a>(pub(crate) Pair<&'a ExprTree, std::slice::Iter<'a, ExprTree>>);
+
+/// Iterator over mutable references to the operands of a node, in
+/// order, given by [`ExprTree::operands_mut`].
+#[derive(Debug)]
+pub struct OperandsMut<'a>(Pair<&'a mut ExprTree, std::slice::IterMut<'a, ExprTree>>);
+
+impl<'a> Iterator for Operands<'a> {
+    type Item = &'a ExprTree;
+
+    fn next(&mut self) -> Option<&'a ExprTree> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+impl ExactSizeIterator for Operands<'_> {}
+
+impl<'a> Iterator for OperandsMut<'a> {
+    type Item = &'a mut ExprTree;
+
+    fn next(&mut self) -> Option<&'a mut ExprTree> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+impl ExactSizeIterator for OperandsMut<'_> {}
+
+/// Fold evaluating a tree using floating-point arithmetic.
+///
+/// The logical operators only fold their right operand when needed,
+/// and calls expand the ranges given as arguments, so those nodes are
+/// handled before their operands are folded. The other nodes are
+/// computed by [`apply`] from the values of their operands.
+struct Evaluator<'a, C: ?Sized> {
+    ctx: &'a C,
+}
+
+impl<C: Context + ?Sized> Fold for Evaluator<'_, C> {
+    type Output = Result<f64>;
+
+    fn fold(&mut self, tree: &ExprTree) -> Result<f64> {
+        match tree {
+            ExprTree::And(lhs, rhs) => Ok(truth(self.fold(lhs)? != 0.0 && self.fold(rhs)? != 0.0)),
+            ExprTree::Or(lhs, rhs) => Ok(truth(self.fold(lhs)? != 0.0 || self.fold(rhs)? != 0.0)),
+            ExprTree::Call(name, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg {
+                        ExprTree::Range(sheet, start, end) => {
+                            values.extend(range_values(self.ctx, sheet, start, end)?)
+                        }
+                        arg => values.push(self.fold(arg)?),
+                    }
+                }
+                call(self.ctx, name, &values)
+            }
+            tree => fold_operands(self, tree),
+        }
+    }
+
+    #[inline]
+    fn fold_node(&mut self, tree: &ExprTree, operands: Folded<Result<f64>>) -> Result<f64> {
+        match operands {
+            Folded::Zero => apply(self.ctx, tree, &[]),
+            Folded::One(value) => apply(self.ctx, tree, &[value?]),
+            Folded::Two([lhs, rhs]) => apply(self.ctx, tree, &[lhs?, rhs?]),
+            Folded::Many(values) => {
+                let values = values.into_iter().collect::<Result<Vec<f64>>>()?;
+                apply(self.ctx, tree, &values)
+            }
         }
     }
 }
 
+/// Compute the value of a node from the values of its operands. The
+/// logical operators evaluate both operands, and ranges are not
+/// expanded, so this is only used when that is already handled.
+#[inline]
+pub(crate) fn apply<C: Context + ?Sized>(ctx: &C, tree: &ExprTree, values: &[f64]) -> Result<f64> {
+    let arg = |index: usize| values[index];
+    match tree {
+        ExprTree::Float(num) => Ok(*num),
+        ExprTree::Integer(num, _) => Ok(*num as f64),
+        ExprTree::Var(name) => variable(ctx, name),
+        ExprTree::Path(path) => resolve(ctx, path),
+        ExprTree::Neg(_) => Ok(-arg(0)),
+        ExprTree::BitNot(_) => Ok(!to_i64(arg(0))? as f64),
+        ExprTree::Abs(_) => Ok(arg(0).abs()),
+        ExprTree::Sqrt(_) => Ok(arg(0).sqrt()),
+        ExprTree::Factorial(_) => factorial(arg(0)),
+        ExprTree::Percent(_) => Ok(arg(0) / 100.0),
+        ExprTree::Add(..) => Ok(arg(0) + arg(1)),
+        ExprTree::Sub(..) => Ok(arg(0) - arg(1)),
+        ExprTree::Mul(..) => Ok(arg(0) * arg(1)),
+        ExprTree::Div(..) => Ok(arg(0) / arg(1)),
+        ExprTree::Mod(..) => {
+            let (lhs, rhs) = (arg(0), arg(1));
+            Ok(lhs - rhs * (lhs / rhs).floor())
+        }
+        ExprTree::IntDiv(..) => Ok((arg(0) / arg(1)).floor()),
+        ExprTree::BitAnd(..) => bitwise(arg(0), arg(1), |a, b| Some(a & b)),
+        ExprTree::BitOr(..) => bitwise(arg(0), arg(1), |a, b| Some(a | b)),
+        ExprTree::BitXor(..) => bitwise(arg(0), arg(1), |a, b| Some(a ^ b)),
+        // Bits shifted out, including into the sign bit, overflow.
+        ExprTree::Shl(..) => bitwise(arg(0), arg(1), |a, b| {
+            let shifted = u32::try_from(b).ok().and_then(|b| a.checked_shl(b))?;
+            Some(shifted).filter(|shifted| shifted >> b == a)
+        }),
+        ExprTree::Shr(..) => bitwise(arg(0), arg(1), |a, b| {
+            u32::try_from(b).ok().and_then(|b| a.checked_shr(b))
+        }),
+        ExprTree::Pow(..) => Ok(arg(0).powf(arg(1))),
+        ExprTree::Eq(..) => Ok(truth(arg(0) == arg(1))),
+        ExprTree::Ne(..) => Ok(truth(arg(0) != arg(1))),
+        ExprTree::Lt(..) => Ok(truth(arg(0) < arg(1))),
+        ExprTree::Le(..) => Ok(truth(arg(0) <= arg(1))),
+        ExprTree::Gt(..) => Ok(truth(arg(0) > arg(1))),
+        ExprTree::Ge(..) => Ok(truth(arg(0) >= arg(1))),
+        ExprTree::Not(_) => Ok(truth(arg(0) == 0.0)),
+        ExprTree::And(..) => Ok(truth(arg(0) != 0.0 && arg(1) != 0.0)),
+        ExprTree::Or(..) => Ok(truth(arg(0) != 0.0 || arg(1) != 0.0)),
+        ExprTree::Call(name, _) => call(ctx, name, values),
+        ExprTree::Cell(sheet, cell) => cell_value(ctx, sheet, cell),
+        ExprTree::Range(..) => Err(Error::UnexpectedRange(
+            tree.display(Dialect::Spreadsheet).to_string(),
+        )),
+    }
+}
+
+/// Fold replacing a variable with a tree.
+struct Substitute<'a> {
+    name: &'a str,
+    value: &'a ExprTree,
+}
+
+impl Fold for Substitute<'_> {
+    type Output = ExprTree;
+
+    fn fold(&mut self, tree: &ExprTree) -> ExprTree {
+        match tree {
+            ExprTree::Var(name) if name == self.name => self.value.clone(),
+            tree => fold_operands(self, tree),
+        }
+    }
+
+    fn fold_node(&mut self, tree: &ExprTree, operands: Folded<ExprTree>) -> ExprTree {
+        tree.with_operands(operands)
+    }
+}
+
+/// Fold evaluating the parts of a tree that are known.
+struct PartialEval<'a, C: ?Sized> {
+    known: &'a C,
+}
+
+impl<C: Context + ?Sized> Fold for PartialEval<'_, C> {
+    type Output = ExprTree;
+
+    fn fold_node(&mut self, tree: &ExprTree, operands: Folded<ExprTree>) -> ExprTree {
+        if constant(tree).is_some() {
+            return tree.clone();
+        }
+        let known = self.known;
+        let value = match tree {
+            ExprTree::Var(name) => variable(known, name),
+            ExprTree::Path(path) => resolve(known, path),
+            ExprTree::Cell(sheet, cell) => cell_value(known, sheet, cell),
+            ExprTree::Range(..) => return tree.clone(),
+            tree => {
+                let values: Option<Vec<f64>> = operands.iter().map(constant).collect();
+                let tree = tree.with_operands(operands);
+                let value = match (&tree, values) {
+                    (ExprTree::Call(name, _), Some(values)) => known.call(name, &values),
+                    (tree, Some(_)) => Some(tree.eval(&HashMap::<String, f64>::new())),
+                    (_, None) => None,
+                };
+                return match value {
+                    Some(Ok(value)) if value.is_finite() => number(value),
+                    _ => tree,
+                };
+            }
+        };
+        value.map_or_else(|_| tree.clone(), number)
+    }
+}
+
+/// Fold computing the depth of a tree.
+struct Depth;
+
+impl Fold for Depth {
+    type Output = usize;
+
+    fn fold_node(&mut self, _tree: &ExprTree, operands: Folded<usize>) -> usize {
+        1 + operands.into_iter().max().unwrap_or(0)
+    }
+}
+
+/// Fold checking if a tree is constant.
+struct Constant;
+
+impl Fold for Constant {
+    type Output = bool;
+
+    fn fold_node(&mut self, tree: &ExprTree, operands: Folded<bool>) -> bool {
+        match tree {
+            ExprTree::Var(..)
+            | ExprTree::Path(..)
+            | ExprTree::Call(..)
+            | ExprTree::Cell(..)
+            | ExprTree::Range(..) => false,
+            _ => operands.into_iter().all(|constant| constant),
+        }
+    }
+}
+
+/// Visitor calling a function for each node, before its operands.
+pub(crate) struct Walk<F>(pub(crate) F);
+
+impl<'a, F: FnMut(&'a ExprTree)> Visitor<'a> for Walk<F> {
+    fn enter(&mut self, tree: &'a ExprTree) -> bool {
+        (self.0)(tree);
+        true
+    }
+}
+
+impl std::fmt::Display for ExprTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(Dialect::Math))
+    }
+}
+
+/// Get the value of a constant, which is a number or a negated
+/// number.
+fn constant(tree: &ExprTree) -> Option<f64> {
+    match tree {
+        ExprTree::Float(num) => Some(*num),
+        ExprTree::Integer(num, _) => Some(*num as f64),
+        ExprTree::Neg(expr) => constant(expr).map(|num| -num),
+        _ => None,
+    }
+}
+
+/// Build a tree for a number, writing a negative number as a negated
+/// literal the way the parser does.
+fn number(value: f64) -> ExprTree {
+    if value.is_sign_negative() && value != 0.0 {
+        ExprTree::Neg(Box::new(ExprTree::Float(-value)))
+    } else {
+        ExprTree::Float(value)
+    }
+}
+
+/// Structural hash of a tree, which is the same for trees that are
+/// equal. Numbers are hashed by their bits, except that `-0` is hashed
+/// like `0` since they are equal.
+impl Hash for ExprTree {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        discriminant(self).hash(state);
+        match self {
+            ExprTree::Var(name) | ExprTree::Call(name, _) => name.hash(state),
+            ExprTree::Path(path) => path.hash(state),
+            ExprTree::Float(num) if *num == 0.0 => 0.0f64.to_bits().hash(state),
+            ExprTree::Float(num) => num.to_bits().hash(state),
+            ExprTree::Integer(num, radix) => (num, radix).hash(state),
+            ExprTree::Cell(sheet, cell) => (sheet, cell).hash(state),
+            ExprTree::Range(sheet, start, end) => (sheet, start, end).hash(state),
+            _ => {}
+        }
+        let operands = self.operands();
+        operands.len().hash(state);
+        for operand in operands {
+            operand.hash(state);
+        }
+    }
+}
+
+/// Convert a truth value to a number.
+pub(crate) fn truth(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Convert a floating-point number to an `i64`, if it is an integer
+/// in range.
+fn to_i64(num: f64) -> Result<i64> {
+    // The upper bound 2^63 is exact as a float, while `i64::MAX` is
+    // not, so the comparison has to be strict.
+    if num.fract() == 0.0 && num >= i64::MIN as f64 && num < 9_223_372_036_854_775_808.0 {
+        Ok(num as i64)
+    } else {
+        Err(Error::NotAnInteger(num))
+    }
+}
+
+/// Apply a bitwise operator to floating-point operands by computing
+/// it using `i64`.
+fn bitwise(lhs: f64, rhs: f64, op: fn(i64, i64) -> Option<i64>) -> Result<f64> {
+    op(to_i64(lhs)?, to_i64(rhs)?)
+        .map(|num| num as f64)
+        .ok_or(Error::Overflow)
+}
+
+/// Factorial extended to real numbers using the gamma function.
+///
+/// Non-negative integers small enough to not overflow are computed
+/// exactly by multiplication, and larger numbers overflow to infinity.
+/// The factorial of a negative integer is a pole of the gamma function,
+/// so it is not defined.
+fn factorial(x: f64) -> Result<f64> {
+    if x < 0.0 && x.fract() == 0.0 {
+        Err(Error::OutOfDomain {
+            function: "factorial".to_string(),
+            value: x,
+        })
+    } else if x > 171.0 {
+        Ok(f64::INFINITY)
+    } else if x >= 0.0 && x.fract() == 0.0 {
+        Ok((1..=x as u32).fold(1.0, |acc, n| acc * f64::from(n)))
+    } else {
+        Ok(gamma(x + 1.0))
+    }
+}
+
+/// Gamma function using the Lanczos approximation, with the
+/// reflection formula for arguments below one half.
+///
+/// The approximation is computed in log space, since its factors
+/// overflow and underflow long before the result does.
+fn gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x))
+    } else {
+        let x = x - 1.0;
+        let t = x + G + 0.5;
+        let sum = COEFFICIENTS[1..]
+            .iter()
+            .enumerate()
+            .fold(COEFFICIENTS[0], |acc, (i, c)| {
+                acc + c / (x + i as f64 + 1.0)
+            });
+        let ln = 0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln();
+        ln.exp()
+    }
+}
+
+/// Look up the value of a variable in the context.
+pub(crate) fn variable<C: Context + ?Sized>(ctx: &C, name: &str) -> Result<f64> {
+    match ctx.lookup(name) {
+        Some(Entry::Number(num)) => Ok(num),
+        _ => Err(Error::NoValue {
+            path: name.to_string(),
+            missing: name.to_string(),
+        }),
+    }
+}
+
+/// Call a function defined by the context.
+pub(crate) fn call<C: Context + ?Sized>(ctx: &C, name: &str, args: &[f64]) -> Result<f64> {
+    ctx.call(name, args)
+        .unwrap_or_else(|| Err(Error::NoFunction(name.to_string())))
+}
+
+/// Look up the value of a cell using the resolver of the context.
+pub(crate) fn cell_value<C: Context + ?Sized>(
+    ctx: &C,
+    sheet: &Option<String>,
+    cell: &CellRef,
+) -> Result<f64> {
+    ctx.resolver()
+        .and_then(|resolver| resolver.cell(sheet.as_deref(), cell))
+        .ok_or_else(|| {
+            Error::NoCell(
+                ExprTree::Cell(sheet.clone(), *cell)
+                    .display(Dialect::Spreadsheet)
+                    .to_string(),
+            )
+        })
+}
+
+/// Look up the values of the cells in a range using the resolver of
+/// the context. Ranges are only allowed as function arguments, where
+/// they are expanded into their values.
+pub(crate) fn range_values<C: Context + ?Sized>(
+    ctx: &C,
+    sheet: &Option<String>,
+    start: &CellRef,
+    end: &CellRef,
+) -> Result<Vec<f64>> {
+    match ctx.resolver() {
+        Some(resolver) => Ok(resolver.range(sheet.as_deref(), start, end)),
+        None => Err(Error::NoCell(
+            ExprTree::Range(sheet.clone(), *start, *end)
+                .display(Dialect::Spreadsheet)
+                .to_string(),
+        )),
+    }
+}
+
+/// Write a path with its members joined by `.`, quoting the members
+/// that are not plain identifiers.
+pub(crate) fn path_name(path: &[String]) -> String {
+    let members: Vec<String> = path
+        .iter()
+        .map(|member| Identifier(member).to_string())
+        .collect();
+    members.join(".")
+}
+
+/// Resolve a path by walking the records of the context one member
+/// at a time.
+pub(crate) fn resolve<C: Context + ?Sized>(ctx: &C, path: &[String]) -> Result<f64> {
+    let no_value = |missing: &String| Error::NoValue {
+        path: path_name(path),
+        missing: missing.clone(),
+    };
+
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Err(no_value(&String::new())),
+    };
+    let mut entry = ctx.lookup(first).ok_or_else(|| no_value(first))?;
+    for member in rest {
+        entry = match entry {
+            Entry::Record(record) => record.lookup(member),
+            Entry::Number(_) => None,
+        }
+        .ok_or_else(|| no_value(member))?;
+    }
+
+    match entry {
+        Entry::Number(num) => Ok(num),
+        Entry::Record(_) => Err(no_value(&path[path.len() - 1])),
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::NoValue(name) => write!(f, "variable '{}' has no value", name),
+            Error::NoValue { path, missing } if path == missing => {
+                write!(f, "variable '{}' has no value", path)
+            }
+            Error::NoValue { path, missing } => {
+                write!(f, "variable '{}' has no value for '{}'", path, missing)
+            }
+            Error::NoFunction(name) => write!(f, "function '{}' is not defined", name),
+            Error::NoCell(name) => write!(f, "cell '{}' has no value", name),
+            Error::UnexpectedRange(name) => {
+                write!(f, "range '{}' can only be a function argument", name)
+            }
+            Error::NoArguments(name) => write!(f, "function '{}' has no values", name),
+            Error::NotAnInteger(num) => write!(f, "{} is not an integer in range", num),
+            Error::NotAFloat(num) => write!(f, "{} has no exact floating-point value", num),
+            Error::OutOfDomain { function, value } => {
+                write!(f, "{} is not defined for {}", function, value)
+            }
+            Error::Overflow => write!(f, "integer overflow"),
+            Error::DivisionByZero => write!(f, "integer division by zero"),
         }
     }
 }
@@ -0,0 +1,382 @@
+//! Traversal and rewriting of expression trees.
+//!
+//! Instead of matching on every variant of [`ExprTree`], a tool can
+//! implement one of the traits below and only handle the nodes it is
+//! interested in. The default methods take care of the operands, so
+//! the tool keeps working when variants are added.
+//!
+//! - [`Visitor`] traverses a tree, calling a hook before and after
+//!   the operands of each node are visited.
+//! - [`VisitorMut`] does the same for a mutable tree, so nodes can be
+//!   changed in place.
+//! - [`Fold`] computes a result bottom-up, combining the results of
+//!   the operands of each node. Folding into an [`ExprTree`] rebuilds
+//!   the tree, which is how rewrites are written.
+//!
+//! The operands of a node are iterated without collecting them, and
+//! the results of folding them are kept in a [`Folded`], which only
+//! uses a vector for calls with more than two arguments, so the
+//! traits are cheap enough for evaluation and printing, which are
+//! written using [`Fold`].
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::parse;
+//! # use expr::tree::ExprTree;
+//! # use expr::visit::{Fold, Folded};
+//! /// Replace every division with a multiplication by the inverse.
+//! struct Inverse;
+//!
+//! impl Fold for Inverse {
+//!     type Output = ExprTree;
+//!
+//!     fn fold_node(&mut self, tree: &ExprTree, operands: Folded<ExprTree>) -> ExprTree {
+//!         match tree.with_operands(operands) {
+//!             ExprTree::Div(lhs, rhs) => ExprTree::Mul(
+//!                 lhs,
+//!                 Box::new(ExprTree::Pow(rhs, Box::new(ExprTree::Float(-1.0)))),
+//!             ),
+//!             tree => tree,
+//!         }
+//!     }
+//! }
+//!
+//! let tree = parse("a / (b / c)").unwrap();
+//! assert_eq!(Inverse.fold(&tree).to_string(), "a * (b * c ^ -1) ^ -1");
+//! ```
+
+use crate::tree::ExprTree;
+use std::ops::{Deref, DerefMut};
+
+/// Traversal of a tree.
+///
+/// The lifetime `'a` is the lifetime of the tree, so a visitor can
+/// keep references to the nodes it visits.
+pub trait Visitor<'a> {
+    /// Visit a node: call [`enter`](Visitor::enter), visit the
+    /// operands unless it returned `false`, and call
+    /// [`leave`](Visitor::leave).
+    fn visit(&mut self, tree: &'a ExprTree) {
+        if self.enter(tree) {
+            walk(self, tree);
+        }
+        self.leave(tree);
+    }
+
+    /// Called before the operands of a node are visited. Return
+    /// `false` to skip the operands.
+    fn enter(&mut self, _tree: &'a ExprTree) -> bool {
+        true
+    }
+
+    /// Called after the operands of a node are visited.
+    fn leave(&mut self, _tree: &'a ExprTree) {}
+}
+
+/// Visit the operands of a node, in order.
+pub fn walk<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, tree: &'a ExprTree) {
+    for operand in tree.operands() {
+        visitor.visit(operand);
+    }
+}
+
+/// Traversal of a mutable tree.
+pub trait VisitorMut {
+    /// Visit a node: call [`enter`](VisitorMut::enter), visit the
+    /// operands unless it returned `false`, and call
+    /// [`leave`](VisitorMut::leave).
+    fn visit_mut(&mut self, tree: &mut ExprTree) {
+        if self.enter(tree) {
+            walk_mut(self, tree);
+        }
+        self.leave(tree);
+    }
+
+    /// Called before the operands of a node are visited. Return
+    /// `false` to skip the operands.
+    fn enter(&mut self, _tree: &mut ExprTree) -> bool {
+        true
+    }
+
+    /// Called after the operands of a node are visited. Replacing the
+    /// node here rewrites the tree bottom-up.
+    fn leave(&mut self, _tree: &mut ExprTree) {}
+}
+
+/// Visit the operands of a mutable node, in order.
+pub fn walk_mut<V: VisitorMut + ?Sized>(visitor: &mut V, tree: &mut ExprTree) {
+    for operand in tree.operands_mut() {
+        visitor.visit_mut(operand);
+    }
+}
+
+/// Bottom-up computation over a tree.
+pub trait Fold {
+    type Output;
+
+    /// Fold a node. By default, the operands are folded first and
+    /// their results are given to [`fold_node`](Fold::fold_node).
+    /// Override this to decide if and when the operands are folded,
+    /// and use [`fold_operands`] for the nodes that need no special
+    /// treatment.
+    fn fold(&mut self, tree: &ExprTree) -> Self::Output {
+        fold_operands(self, tree)
+    }
+
+    /// Combine the results of the operands of a node, in order, into
+    /// the result of the node.
+    fn fold_node(&mut self, tree: &ExprTree, operands: Folded<Self::Output>) -> Self::Output;
+}
+
+/// Fold the operands of a node and combine them using
+/// [`Fold::fold_node`].
+#[inline]
+pub fn fold_operands<F: Fold + ?Sized>(folder: &mut F, tree: &ExprTree) -> F::Output {
+    let folded = match tree.operands().0 {
+        Pair::Two(Some(lhs), Some(rhs)) => {
+            let lhs = folder.fold(lhs);
+            Folded::Two([lhs, folder.fold(rhs)])
+        }
+        Pair::Two(Some(operand), None) => Folded::One(folder.fold(operand)),
+        Pair::Two(..) => Folded::Zero,
+        Pair::Many(args) => fold_args(folder, args.as_slice()),
+    };
+    folder.fold_node(tree, folded)
+}
+
+/// Fold the arguments of a call, which are the only operands that
+/// may need a vector.
+fn fold_args<F: Fold + ?Sized>(folder: &mut F, args: &[ExprTree]) -> Folded<F::Output> {
+    match args {
+        [] => Folded::Zero,
+        [arg] => Folded::One(folder.fold(arg)),
+        [lhs, rhs] => {
+            let lhs = folder.fold(lhs);
+            Folded::Two([lhs, folder.fold(rhs)])
+        }
+        args => Folded::Many(args.iter().map(|arg| folder.fold(arg)).collect()),
+    }
+}
+
+/// Results of folding the operands of a node, in order.
+///
+/// Only calls have more than two operands, so the results are kept
+/// in the value itself except for calls with more arguments. The
+/// results can be used as a slice, or taken using `into_iter`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Folded<T> {
+    Zero,
+    One(T),
+    Two([T; 2]),
+    Many(Vec<T>),
+}
+
+impl<T> Deref for Folded<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Folded::Zero => &[],
+            Folded::One(value) => std::slice::from_ref(value),
+            Folded::Two(values) => values,
+            Folded::Many(values) => values,
+        }
+    }
+}
+
+impl<T> DerefMut for Folded<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            Folded::Zero => &mut [],
+            Folded::One(value) => std::slice::from_mut(value),
+            Folded::Two(values) => values,
+            Folded::Many(values) => values,
+        }
+    }
+}
+
+impl<T> IntoIterator for Folded<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(match self {
+            Folded::Zero => Pair::Two(None, None),
+            Folded::One(value) => Pair::Two(Some(value), None),
+            Folded::Two([lhs, rhs]) => Pair::Two(Some(lhs), Some(rhs)),
+            Folded::Many(values) => Pair::Many(values.into_iter()),
+        })
+    }
+}
+
+/// Iterator taking the results of a [`Folded`].
+#[derive(Debug, Clone)]
+pub struct IntoIter<T>(Pair<T, std::vec::IntoIter<T>>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+/// Operands of a node, which are at most two, except for the
+/// arguments of a call.
+#[derive(Debug, Clone)]
+pub(crate) enum Pair<T, I> {
+    Two(Option<T>, Option<T>),
+    Many(I),
+}
+
+impl<T, I: ExactSizeIterator<Item = T>> Pair<T, I> {
+    #[inline]
+    pub(crate) fn next(&mut self) -> Option<T> {
+        match self {
+            Pair::Two(first, second) => first.take().or_else(|| second.take()),
+            Pair::Many(operands) => operands.next(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Pair::Two(first, second) => first.iter().len() + second.iter().len(),
+            Pair::Many(operands) => operands.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    /// Visitor recording the nodes entered and left.
+    struct Trace {
+        events: Vec<String>,
+        skip_calls: bool,
+    }
+
+    impl<'a> Visitor<'a> for Trace {
+        fn enter(&mut self, tree: &'a ExprTree) -> bool {
+            self.events.push(format!("enter {}", tree));
+            !(self.skip_calls && matches!(tree, ExprTree::Call(..)))
+        }
+
+        fn leave(&mut self, tree: &'a ExprTree) {
+            self.events.push(format!("leave {}", tree));
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let tree = parse("-x + f(y)").unwrap();
+        let mut trace = Trace {
+            events: Vec::new(),
+            skip_calls: false,
+        };
+        trace.visit(&tree);
+        assert_eq!(
+            trace.events,
+            [
+                "enter -x + f(y)",
+                "enter -x",
+                "enter x",
+                "leave x",
+                "leave -x",
+                "enter f(y)",
+                "enter y",
+                "leave y",
+                "leave f(y)",
+                "leave -x + f(y)",
+            ]
+        );
+
+        let mut trace = Trace {
+            events: Vec::new(),
+            skip_calls: true,
+        };
+        trace.visit(&tree);
+        assert!(!trace.events.contains(&"enter y".to_string()));
+        assert_eq!(trace.events.len(), 8);
+    }
+
+    /// Visitor renaming variables in place.
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn leave(&mut self, tree: &mut ExprTree) {
+            if let ExprTree::Var(name) = tree {
+                name.make_ascii_uppercase();
+            }
+        }
+    }
+
+    #[test]
+    fn visitor_mut() {
+        let mut tree = parse("a * f(b, -c)").unwrap();
+        Rename.visit_mut(&mut tree);
+        assert_eq!(tree, parse("A * f(B, -C)").unwrap());
+    }
+
+    /// Fold replacing `x * 1` and `1 * x` with `x`.
+    struct Simplify;
+
+    impl Fold for Simplify {
+        type Output = ExprTree;
+
+        fn fold_node(&mut self, tree: &ExprTree, operands: Folded<ExprTree>) -> ExprTree {
+            match tree.with_operands(operands) {
+                ExprTree::Mul(lhs, rhs) if *rhs == ExprTree::Float(1.0) => *lhs,
+                ExprTree::Mul(lhs, rhs) if *lhs == ExprTree::Float(1.0) => *rhs,
+                tree => tree,
+            }
+        }
+    }
+
+    #[test]
+    fn fold() {
+        let tree = parse("(1 * x) * (y * 1) + g(z * 1) * 1").unwrap();
+        assert_eq!(Simplify.fold(&tree), parse("x * y + g(z)").unwrap());
+
+        let tree = parse("(a + b) * c").unwrap();
+        assert_eq!(
+            tree.with_operands(vec![ExprTree::Float(2.0), ExprTree::Var("d".to_string())]),
+            parse("2 * d").unwrap()
+        );
+    }
+
+    /// Fold collecting the results of the operands of each node.
+    struct Shapes;
+
+    impl Fold for Shapes {
+        type Output = Vec<usize>;
+
+        fn fold_node(&mut self, _tree: &ExprTree, operands: Folded<Vec<usize>>) -> Vec<usize> {
+            let mut shape = vec![operands.len()];
+            shape.extend(operands.into_iter().flatten());
+            shape
+        }
+    }
+
+    #[test]
+    fn folded() {
+        let tree = parse("f(-a, b + c, d) * g()").unwrap();
+        assert_eq!(Shapes.fold(&tree), [2, 3, 1, 0, 2, 0, 0, 0, 0]);
+
+        let mut folded = Folded::Two([1, 2]);
+        folded[0] = 3;
+        assert_eq!(&*folded, [3, 2]);
+        assert_eq!(folded.into_iter().collect::<Vec<_>>(), [3, 2]);
+        assert_eq!(Folded::One(1).into_iter().len(), 1);
+        assert!(Folded::<u32>::Zero.is_empty());
+    }
+}
@@ -0,0 +1,723 @@
+//! Workbooks of named formulas referring to each other.
+//!
+//! Each formula is an expression that can use the values of other
+//! formulas by name. The formulas are evaluated in dependency order,
+//! so every formula is evaluated once, after the formulas it uses.
+//! Variables that are not the name of a formula are looked up in the
+//! context given to the evaluation.
+//!
+//! An [`Engine`] keeps the values of the formulas of a workbook up
+//! to date, recomputing only the formulas affected when an input is
+//! set.
+//!
+//! # Example
+//!
+//! ```
+//! # use expr::workbook::Workbook;
+//! # use std::collections::HashMap;
+//! let mut book = Workbook::new();
+//! book.define("net", "gross - tax").unwrap();
+//! book.define("tax", "gross * rate").unwrap();
+//! book.define("gross", "1000").unwrap();
+//!
+//! let mut vars = HashMap::new();
+//! vars.insert("rate".to_string(), 0.25);
+//! let values = book.eval(&vars).unwrap();
+//! assert_eq!(values["net"], 750.0);
+//! ```
+
+use crate::parser;
+use crate::spreadsheet::Resolver;
+use crate::tree::{self, Context, Entry, ExprTree, Walk};
+use crate::visit::Visitor;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Error {
+    /// The text of a formula could not be parsed.
+    Parse { name: String, error: parser::Error },
+    /// Formulas depend on each other in a cycle. The path starts and
+    /// ends with the same formula, so `x = x + 1` gives the path
+    /// `["x", "x"]`.
+    Cycle(Vec<String>),
+    /// A formula could not be evaluated.
+    Eval { name: String, error: tree::Error },
+    /// An input of an engine was set using the name of a formula.
+    NotAnInput(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Formula of a workbook together with the variables it uses.
+#[derive(Debug, Clone)]
+pub struct Formula {
+    tree: ExprTree,
+    uses: Vec<String>,
+}
+
+impl Formula {
+    /// Expression tree of the formula.
+    pub fn tree(&self) -> &ExprTree {
+        &self.tree
+    }
+
+    /// Names of the variables used by the formula, in the order they
+    /// first occur. Some of them can be other formulas. A path like
+    /// `order.total` uses the name of its first member, `order`, which
+    /// is the name looked up when evaluating it.
+    pub fn uses(&self) -> &[String] {
+        &self.uses
+    }
+}
+
+/// Collection of named formulas.
+#[derive(Debug, Default, Clone)]
+pub struct Workbook {
+    names: Vec<String>,
+    formulas: HashMap<String, Formula>,
+}
+
+/// Mark of a formula while sorting the formulas.
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+/// Get the names a formula looks up in its context, in the order
+/// they first occur.
+fn uses(tree: &ExprTree) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    Walk(|tree: &ExprTree| {
+        let name = match tree {
+            ExprTree::Var(name) => name,
+            ExprTree::Path(path) if !path.is_empty() => &path[0],
+            _ => return,
+        };
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    })
+    .visit(tree);
+    names
+}
+
+impl Workbook {
+    pub fn new() -> Workbook {
+        Workbook::default()
+    }
+
+    /// Parse and define a formula, replacing any existing formula
+    /// with the same name.
+    pub fn define(&mut self, name: &str, text: &str) -> Result<()> {
+        let tree = parser::parse(text).map_err(|error| Error::Parse {
+            name: name.to_string(),
+            error,
+        })?;
+        let uses = uses(&tree);
+        let formula = Formula { tree, uses };
+        if self.formulas.insert(name.to_string(), formula).is_none() {
+            self.names.push(name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Get a formula by name.
+    pub fn formula(&self, name: &str) -> Option<&Formula> {
+        self.formulas.get(name)
+    }
+
+    /// Names of the formulas in the order they were first defined.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    /// Names of the formulas that a formula uses directly.
+    pub fn dependencies(&self, name: &str) -> Vec<&str> {
+        self.formulas.get(name).map_or_else(Vec::new, |formula| {
+            formula
+                .uses
+                .iter()
+                .map(String::as_str)
+                .filter(|name| self.formulas.contains_key(*name))
+                .collect()
+        })
+    }
+
+    /// Names of the formulas in evaluation order, where each formula
+    /// comes after the formulas it uses.
+    ///
+    /// The formulas are sorted using a depth-first search with an
+    /// explicit stack, which also is the path used to report a
+    /// cycle.
+    pub fn order(&self) -> Result<Vec<&str>> {
+        let mut marks: HashMap<&str, Mark> = HashMap::new();
+        let mut order = Vec::with_capacity(self.names.len());
+        for root in &self.names {
+            if marks.contains_key(root.as_str()) {
+                continue;
+            }
+            marks.insert(root, Mark::Visiting);
+            let mut stack = vec![(root.as_str(), 0)];
+            while let Some(&(name, next)) = stack.last() {
+                let uses = &self.formulas[name].uses;
+                let dependency = match uses.get(next) {
+                    Some(dependency) => dependency.as_str(),
+                    None => {
+                        marks.insert(name, Mark::Done);
+                        order.push(name);
+                        stack.pop();
+                        continue;
+                    }
+                };
+                if let Some(top) = stack.last_mut() {
+                    top.1 += 1;
+                }
+                if !self.formulas.contains_key(dependency) {
+                    continue;
+                }
+                match marks.get(dependency) {
+                    Some(Mark::Done) => {}
+                    Some(Mark::Visiting) => {
+                        let start = stack.iter().position(|&(name, _)| name == dependency);
+                        let mut path: Vec<String> = stack[start.unwrap_or(0)..]
+                            .iter()
+                            .map(|&(name, _)| name.to_string())
+                            .collect();
+                        path.push(dependency.to_string());
+                        return Err(Error::Cycle(path));
+                    }
+                    None => {
+                        marks.insert(dependency, Mark::Visiting);
+                        stack.push((dependency, 0));
+                    }
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Evaluate all formulas, giving the value of each formula by
+    /// name.
+    ///
+    /// Variables that are not formulas, as well as functions and
+    /// cells, are looked up in the context.
+    pub fn eval<C: Context + ?Sized>(&self, ctx: &C) -> Result<HashMap<String, f64>> {
+        let mut values = HashMap::with_capacity(self.names.len());
+        for name in self.order()? {
+            let scope = Scope {
+                values: &values,
+                outer: ctx,
+            };
+            let value = self.formulas[name]
+                .tree
+                .eval(&scope)
+                .map_err(|error| Error::Eval {
+                    name: name.to_string(),
+                    error,
+                })?;
+            values.insert(name.to_string(), value);
+        }
+        Ok(values)
+    }
+}
+
+/// Change of the value of a formula, given to the listeners of an
+/// engine. A value is `None` when the formula could not be
+/// evaluated.
+#[derive(Debug, PartialEq)]
+pub struct Change<'a> {
+    pub name: &'a str,
+    pub old: Option<f64>,
+    pub new: Option<f64>,
+}
+
+/// Listener called when a formula changes value.
+type Listener = Box<dyn FnMut(&Change<'_>)>;
+
+/// Engine keeping the values of the formulas of a workbook up to
+/// date when the inputs change.
+///
+/// The engine caches the last value of each formula and tracks which
+/// formulas use each variable. Setting an input only recomputes the
+/// formulas that depend on it, directly or through other formulas,
+/// and a formula is only recomputed if the value of at least one of
+/// the names it uses changed.
+///
+/// Variables that are neither formulas nor inputs, as well as
+/// functions and cells, are looked up in an outer context given to
+/// the engine, which is assumed not to change.
+///
+/// ```
+/// # use expr::workbook::{Engine, Workbook};
+/// # use std::collections::HashMap;
+/// let mut book = Workbook::new();
+/// book.define("tax", "gross * rate").unwrap();
+/// book.define("net", "gross - tax").unwrap();
+/// book.define("label", "rate * 100").unwrap();
+///
+/// let mut engine = Engine::new(book, HashMap::<String, f64>::new()).unwrap();
+/// engine.set("rate", 0.25).unwrap();
+/// assert_eq!(engine.set("gross", 1000.0).unwrap(), ["tax", "net"]);
+/// assert_eq!(engine.value("net"), Some(750.0));
+/// ```
+pub struct Engine<C> {
+    book: Workbook,
+    outer: C,
+    order: Vec<String>,
+    position: HashMap<String, usize>,
+    dependents: HashMap<String, Vec<String>>,
+    inputs: HashMap<String, f64>,
+    values: HashMap<String, f64>,
+    errors: HashMap<String, tree::Error>,
+    listeners: Vec<Listener>,
+}
+
+impl<C: Context> Engine<C> {
+    /// Create an engine for the formulas of a workbook, evaluating
+    /// all of them using the outer context. Formulas using inputs that
+    /// are not set yet get no value until the inputs are set.
+    pub fn new(book: Workbook, outer: C) -> Result<Engine<C>> {
+        let order: Vec<String> = book.order()?.into_iter().map(str::to_string).collect();
+        let position = order
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.clone(), index))
+            .collect();
+        let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+        for name in &order {
+            for used in &book.formulas[name].uses {
+                dependents
+                    .entry(used.clone())
+                    .or_default()
+                    .push(name.clone());
+            }
+        }
+        let mut engine = Engine {
+            book,
+            outer,
+            order,
+            position,
+            dependents,
+            inputs: HashMap::new(),
+            values: HashMap::new(),
+            errors: HashMap::new(),
+            listeners: Vec::new(),
+        };
+        for name in engine.order.clone() {
+            engine.recompute(&name);
+        }
+        Ok(engine)
+    }
+
+    /// Workbook of the engine.
+    pub fn workbook(&self) -> &Workbook {
+        &self.book
+    }
+
+    /// Outer context of the engine.
+    pub fn context(&self) -> &C {
+        &self.outer
+    }
+
+    /// Get the cached value of a formula or the value of an input.
+    pub fn value(&self, name: &str) -> Option<f64> {
+        if self.book.formulas.contains_key(name) {
+            self.values.get(name).cloned()
+        } else {
+            self.inputs.get(name).cloned()
+        }
+    }
+
+    /// Get the error from the last evaluation of a formula, if it
+    /// failed.
+    pub fn error(&self, name: &str) -> Option<&tree::Error> {
+        self.errors.get(name)
+    }
+
+    /// Add a listener that is called for every formula that changes
+    /// value.
+    pub fn on_change<F>(&mut self, listener: F)
+    where
+        F: FnMut(&Change<'_>) + 'static,
+    {
+        self.listeners.push(Box::new(listener));
+    }
+
+    /// Names of the formulas that depend on a name, directly or
+    /// through other formulas, in evaluation order.
+    pub fn affected(&self, name: &str) -> Vec<&str> {
+        let mut found: HashSet<&str> = HashSet::new();
+        let mut queue = vec![name];
+        while let Some(name) = queue.pop() {
+            for dependent in self.dependents.get(name).into_iter().flatten() {
+                if found.insert(dependent) {
+                    queue.push(dependent);
+                }
+            }
+        }
+        let mut found: Vec<&str> = found.into_iter().collect();
+        found.sort_by_key(|name| self.position[*name]);
+        found
+    }
+
+    /// Set the value of an input and recompute the formulas affected
+    /// by it, giving the names of the formulas that changed value in
+    /// evaluation order.
+    ///
+    /// The name of a formula cannot be set, since the formula gives
+    /// its value.
+    pub fn set(&mut self, name: &str, value: f64) -> Result<Vec<String>> {
+        if self.book.formulas.contains_key(name) {
+            return Err(Error::NotAnInput(name.to_string()));
+        }
+        let old = self.inputs.insert(name.to_string(), value);
+        if same(old, Some(value)) {
+            return Ok(Vec::new());
+        }
+        let affected: Vec<String> = self
+            .affected(name)
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let mut stale: HashSet<String> = HashSet::new();
+        stale.insert(name.to_string());
+        let mut changed = Vec::new();
+        for formula in affected {
+            let uses_stale = self.book.formulas[&formula]
+                .uses
+                .iter()
+                .any(|used| stale.contains(used));
+            if uses_stale && self.recompute(&formula) {
+                stale.insert(formula.clone());
+                changed.push(formula);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Evaluate a formula again, notifying the listeners and giving
+    /// `true` if its value changed.
+    fn recompute(&mut self, name: &str) -> bool {
+        let result = self.book.formulas[name].tree.eval(&*self);
+        let old = self.values.get(name).cloned();
+        let new = match result {
+            Ok(value) => {
+                self.errors.remove(name);
+                self.values.insert(name.to_string(), value);
+                Some(value)
+            }
+            Err(error) => {
+                self.errors.insert(name.to_string(), error);
+                self.values.remove(name);
+                None
+            }
+        };
+        if same(old, new) {
+            return false;
+        }
+        let change = Change { name, old, new };
+        for listener in &mut self.listeners {
+            listener(&change);
+        }
+        true
+    }
+}
+
+/// The engine is the context used to evaluate its formulas, giving
+/// the cached values of the formulas and the values of the inputs,
+/// and looking up everything else in the outer context.
+impl<C: Context> Context for Engine<C> {
+    fn lookup(&self, name: &str) -> Option<Entry<'_>> {
+        if self.book.formulas.contains_key(name) {
+            self.values.get(name).map(|value| Entry::Number(*value))
+        } else {
+            match self.inputs.get(name) {
+                Some(value) => Some(Entry::Number(*value)),
+                None => self.outer.lookup(name),
+            }
+        }
+    }
+
+    fn call(&self, name: &str, args: &[f64]) -> Option<tree::Result<f64>> {
+        self.outer.call(name, args)
+    }
+
+    fn resolver(&self) -> Option<&dyn Resolver> {
+        self.outer.resolver()
+    }
+}
+
+/// Check if two values are the same, where NaN is the same as NaN,
+/// so that a formula giving NaN is not changed on every update.
+fn same(lhs: Option<f64>, rhs: Option<f64>) -> bool {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => lhs == rhs || lhs.is_nan() && rhs.is_nan(),
+        (lhs, rhs) => lhs.is_none() && rhs.is_none(),
+    }
+}
+
+/// Context giving the values of the formulas evaluated so far, and
+/// looking up everything else in an outer context.
+struct Scope<'a, C: ?Sized> {
+    values: &'a HashMap<String, f64>,
+    outer: &'a C,
+}
+
+impl<C: Context + ?Sized> Context for Scope<'_, C> {
+    fn lookup(&self, name: &str) -> Option<Entry<'_>> {
+        match self.values.get(name) {
+            Some(value) => Some(Entry::Number(*value)),
+            None => self.outer.lookup(name),
+        }
+    }
+
+    fn call(&self, name: &str, args: &[f64]) -> Option<tree::Result<f64>> {
+        self.outer.call(name, args)
+    }
+
+    fn resolver(&self) -> Option<&dyn Resolver> {
+        self.outer.resolver()
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse { name, error } => write!(f, "formula '{}': {}", name, error),
+            Error::Cycle(path) => write!(f, "formulas depend on each other: {}", path.join(" -> ")),
+            Error::Eval { name, error } => write!(f, "formula '{}': {}", name, error),
+            Error::NotAnInput(name) => write!(f, "'{}' is a formula, not an input", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            Error::Parse { .. } => "parser error",
+            Error::Cycle(_) => "cyclic formulas",
+            Error::Eval { .. } => "eval error",
+            Error::NotAnInput(_) => "not an input",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spreadsheet::{CellRef, Cells};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn workbook(definitions: &[(&str, &str)]) -> Workbook {
+        let mut book = Workbook::new();
+        for (name, text) in definitions {
+            book.define(name, text).unwrap();
+        }
+        book
+    }
+
+    #[test]
+    fn order() {
+        let book = workbook(&[
+            ("net", "gross - tax"),
+            ("tax", "gross * rate"),
+            ("gross", "price * count"),
+        ]);
+        assert_eq!(book.formula("net").unwrap().uses(), ["gross", "tax"]);
+        assert_eq!(book.dependencies("tax"), ["gross"]);
+        assert_eq!(book.dependencies("rate"), Vec::<&str>::new());
+        assert_eq!(book.order(), Ok(vec!["gross", "tax", "net"]));
+
+        let paths = workbook(&[
+            ("order.total", "5"),
+            ("quoted", "`order.total` * 2"),
+            ("path", "order.total * order.count"),
+        ]);
+        assert_eq!(paths.dependencies("quoted"), ["order.total"]);
+        assert_eq!(paths.formula("path").unwrap().uses(), ["order"]);
+        assert_eq!(paths.dependencies("path"), Vec::<&str>::new());
+
+        let mut vars = HashMap::new();
+        vars.insert("price".to_string(), 20.0);
+        vars.insert("count".to_string(), 5.0);
+        vars.insert("rate".to_string(), 0.5);
+        let values = book.eval(&vars).unwrap();
+        assert_eq!(values["gross"], 100.0);
+        assert_eq!(values["tax"], 50.0);
+        assert_eq!(values["net"], 50.0);
+
+        vars.remove("rate");
+        assert_eq!(
+            book.eval(&vars),
+            Err(Error::Eval {
+                name: "tax".to_string(),
+                error: tree::Error::NoValue {
+                    path: "rate".to_string(),
+                    missing: "rate".to_string(),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn redefine() {
+        let mut book = workbook(&[("a", "b + 1"), ("b", "2")]);
+        book.define("a", "b * 10").unwrap();
+        assert_eq!(book.names().collect::<Vec<_>>(), ["a", "b"]);
+        let values = book.eval(&HashMap::<String, f64>::new()).unwrap();
+        assert_eq!(values["a"], 20.0);
+
+        assert!(matches!(
+            book.define("c", "1 +"),
+            Err(Error::Parse { ref name, .. }) if name == "c"
+        ));
+        assert!(book.formula("c").is_none());
+    }
+
+    #[test]
+    fn cycles() {
+        let book = workbook(&[("x", "x + 1")]);
+        assert_eq!(
+            book.order(),
+            Err(Error::Cycle(vec!["x".to_string(), "x".to_string()]))
+        );
+
+        let book = workbook(&[
+            ("total", "a + 1"),
+            ("a", "b * 2"),
+            ("b", "c - 1"),
+            ("c", "a / 2"),
+        ]);
+        let error = book.eval(&HashMap::<String, f64>::new()).unwrap_err();
+        assert_eq!(
+            error,
+            Error::Cycle(vec![
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string(),
+                "a".to_string()
+            ])
+        );
+        assert_eq!(
+            error.to_string(),
+            "formulas depend on each other: a -> b -> c -> a"
+        );
+    }
+
+    #[test]
+    fn long_chain() {
+        let mut book = Workbook::new();
+        for i in (1..10_000).rev() {
+            book.define(&format!("x{}", i), &format!("x{} + 1", i - 1))
+                .unwrap();
+        }
+        book.define("x0", "1").unwrap();
+        let values = book.eval(&HashMap::<String, f64>::new()).unwrap();
+        assert_eq!(values["x9999"], 10_000.0);
+    }
+
+    #[test]
+    fn engine() {
+        let book = workbook(&[
+            ("gross", "price * count"),
+            ("tax", "gross * rate"),
+            ("net", "gross - tax"),
+            ("large", "count > 10"),
+            ("bonus", "large * 100"),
+        ]);
+        let mut engine = Engine::new(book, HashMap::<String, f64>::new()).unwrap();
+        assert_eq!(engine.value("net"), None);
+        assert_eq!(
+            engine.error("gross"),
+            Some(&tree::Error::NoValue {
+                path: "price".to_string(),
+                missing: "price".to_string(),
+            })
+        );
+        assert_eq!(engine.value("bonus"), None);
+        assert_eq!(
+            engine.affected("count"),
+            ["gross", "tax", "net", "large", "bonus"]
+        );
+        assert_eq!(engine.affected("rate"), ["tax", "net"]);
+
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&changes);
+        engine.on_change(move |change| {
+            log.borrow_mut()
+                .push((change.name.to_string(), change.old, change.new))
+        });
+
+        assert_eq!(engine.set("price", 20.0), Ok(Vec::new()));
+        assert_eq!(engine.set("rate", 0.5), Ok(Vec::new()));
+        assert_eq!(
+            engine.set("count", 5.0).unwrap(),
+            ["gross", "tax", "net", "large", "bonus"]
+        );
+        assert_eq!(engine.value("net"), Some(50.0));
+        assert_eq!(engine.error("gross"), None);
+        assert_eq!(
+            changes.borrow()[0],
+            ("gross".to_string(), None, Some(100.0))
+        );
+
+        // The comparison does not change, so the bonus is not
+        // recomputed.
+        assert_eq!(engine.set("count", 6.0).unwrap(), ["gross", "tax", "net"]);
+        assert_eq!(engine.set("count", 6.0), Ok(Vec::new()));
+        assert_eq!(engine.set("rate", 0.25).unwrap(), ["tax", "net"]);
+        assert_eq!(engine.value("tax"), Some(30.0));
+        assert_eq!(
+            engine.set("count", 20.0).unwrap(),
+            ["gross", "tax", "net", "large", "bonus"]
+        );
+        assert_eq!(engine.value("bonus"), Some(100.0));
+        assert_eq!(
+            engine.set("large", 0.0),
+            Err(Error::NotAnInput("large".to_string()))
+        );
+        assert_eq!(changes.borrow().len(), 15);
+        assert_eq!(
+            changes.borrow().last(),
+            Some(&("bonus".to_string(), Some(0.0), Some(100.0)))
+        );
+    }
+
+    #[test]
+    fn engine_context() {
+        let mut cells = Cells::new();
+        cells.set("A1", 2.0).unwrap();
+        let book = workbook(&[("total", "MAX(rate, 5) * 10")]);
+        let mut engine = Engine::new(book, cells).unwrap();
+        assert_eq!(engine.context().cell(None, &CellRef::new(1, 1)), Some(2.0));
+        assert_eq!(engine.set("rate", 2.0).unwrap(), ["total"]);
+        assert_eq!(engine.value("total"), Some(50.0));
+        assert_eq!(engine.set("rate", 8.0).unwrap(), ["total"]);
+        assert_eq!(engine.value("total"), Some(80.0));
+
+        let mut vars = HashMap::new();
+        vars.insert("rate".to_string(), 2.0);
+        let book = workbook(&[("double", "rate * 2")]);
+        let mut engine = Engine::new(book, vars).unwrap();
+        assert_eq!(engine.value("double"), Some(4.0));
+        assert_eq!(engine.value("rate"), None);
+        assert_eq!(engine.set("rate", 5.0).unwrap(), ["double"]);
+        assert_eq!(engine.value("double"), Some(10.0));
+    }
+
+    #[test]
+    fn engine_cycle() {
+        let book = workbook(&[("a", "b"), ("b", "a")]);
+        assert_eq!(
+            Engine::new(book, HashMap::<String, f64>::new()).err(),
+            Some(Error::Cycle(vec![
+                "a".to_string(),
+                "b".to_string(),
+                "a".to_string()
+            ]))
+        );
+    }
+}
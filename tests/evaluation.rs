@@ -2,9 +2,11 @@ extern crate expr;
 extern crate matches;
 
 use expr::eval;
+use expr::parse;
 use expr::parser::Error::*;
 use expr::tokens::Token;
 use expr::tree::Error::*;
+use expr::tree::ExprTree::*;
 use expr::Error::*;
 use matches::assert_matches;
 use std::collections::HashMap;
@@ -42,7 +44,10 @@ fn variable_expressions() {
 
     assert_eq!(
         eval("10 + x + y", &map),
-        Err(Eval(NoValue("y".to_string())))
+        Err(Eval(NoValue {
+            path: "y".to_string(),
+            missing: "y".to_string(),
+        }))
     );
 
     assert_matches!(
@@ -91,3 +96,236 @@ fn variable_expressions() {
         }))
     );
 }
+
+#[test]
+fn path_expressions() {
+    use expr::parse;
+    use expr::tree::Value;
+
+    let eval = |text: &str, map: &HashMap<String, Value>| parse(text).unwrap().eval(map);
+
+    let mut order = HashMap::new();
+    order.insert("total".to_string(), Value::Number(200.0));
+    let mut customer = HashMap::new();
+    customer.insert("discount".to_string(), Value::Number(0.25));
+    let mut map = HashMap::new();
+    map.insert("order".to_string(), Value::Record(order));
+    map.insert("customer".to_string(), Value::Record(customer));
+    map.insert("rate".to_string(), Value::Number(2.0));
+
+    assert_eq!(eval("order.total * customer.discount", &map), Ok(50.0));
+    assert_eq!(eval("rate * order.total", &map), Ok(400.0));
+
+    let no_value = |path: &str, missing: &str| {
+        Err(NoValue {
+            path: path.to_string(),
+            missing: missing.to_string(),
+        })
+    };
+    assert_eq!(eval("order.tax", &map), no_value("order.tax", "tax"));
+    assert_eq!(
+        eval("invoice.total", &map),
+        no_value("invoice.total", "invoice")
+    );
+    assert_eq!(eval("rate.value", &map), no_value("rate.value", "value"));
+    assert_eq!(eval("order + 1", &map), no_value("order", "order"));
+    assert_eq!(
+        eval("order.`unit.price`", &map),
+        no_value("order.`unit.price`", "unit.price")
+    );
+    assert_eq!(
+        eval("order.tax", &map).unwrap_err().to_string(),
+        "variable 'order.tax' has no value for 'tax'"
+    );
+}
+
+#[test]
+fn comment_expressions() {
+    let mut map = HashMap::new();
+    map.insert("gross".to_string(), 100.0);
+    map.insert("rate".to_string(), 0.25);
+    let text = "
+        # Net amount after tax.
+        gross - gross * rate // tax is a fraction of gross
+        /* rounding is done
+           by the caller */
+    ";
+    assert_eq!(eval(text, &map), Ok(75.0));
+}
+
+#[test]
+fn postfix_expressions() {
+    let mut map = HashMap::new();
+    map.insert("price".to_string(), 200.0);
+    map.insert("n".to_string(), 5.0);
+    assert_eq!(eval("price * 15%", &map), Ok(30.0));
+    assert_eq!(eval("n!", &map), Ok(120.0));
+    assert_eq!(eval("0!", &map), Ok(1.0));
+    assert_eq!(eval("-3!", &map), Ok(-6.0));
+    assert_eq!(eval("3!!", &map), Ok(720.0));
+    assert_eq!(eval("50%%", &map), Ok(0.005));
+
+    let half = eval("0.5!", &map).unwrap();
+    assert!((half - std::f64::consts::PI.sqrt() / 2.0).abs() < 1e-12);
+    let negative = eval("(0 - 0.5)!", &map).unwrap();
+    assert!((negative - std::f64::consts::PI.sqrt()).abs() < 1e-12);
+    let largest = eval("170!", &map).unwrap();
+    assert!(largest.is_finite() && largest > 7.25e306);
+    assert!(eval("170.5!", &map).unwrap().is_finite());
+    assert_eq!(eval("171!", &map), Ok(f64::INFINITY));
+    assert_eq!(eval("171.5!", &map), Ok(f64::INFINITY));
+    assert_eq!(eval("1000!", &map), Ok(f64::INFINITY));
+    assert_eq!(eval("1000.5!", &map), Ok(f64::INFINITY));
+    for pole in &["(0 - 1)!", "(0 - 2)!", "(0 - 3)!", "(0 - 100)!"] {
+        assert_matches!(
+            eval(pole, &map),
+            Err(Eval(OutOfDomain {
+                function: ref name,
+                ..
+            })) if name == "factorial"
+        );
+    }
+    let reflected = eval("(0 - 2.5)!", &map).unwrap();
+    assert!((reflected - 4.0 / 3.0 * std::f64::consts::PI.sqrt()).abs() < 1e-12);
+}
+
+#[test]
+fn bitwise_expressions() {
+    let mut map = HashMap::new();
+    map.insert("flags".to_string(), 0xAB as f64);
+    map.insert("mode".to_string(), 3.0);
+    assert_eq!(eval("(flags >> 4) & 0xF | mode << 8", &map), Ok(778.0));
+    assert_eq!(eval("7 mod 3", &map), Ok(1.0));
+    assert_eq!(eval("-7 mod 3", &map), Ok(2.0));
+    assert_eq!(eval("7 div 2", &map), Ok(3.0));
+    assert_eq!(eval("~0", &map), Ok(-1.0));
+    assert_eq!(eval("0.5 & 1", &map), Err(Eval(NotAnInteger(0.5))));
+    assert_eq!(eval("1 << 64", &map), Err(Eval(Overflow)));
+    assert_eq!(eval("1 << 62", &map), Ok(4_611_686_018_427_387_904.0));
+    assert_eq!(eval("1 << 63", &map), Err(Eval(Overflow)));
+    assert_eq!(eval("3 << 62", &map), Err(Eval(Overflow)));
+    assert_eq!(eval("-1 << 63", &map), Ok(-9_223_372_036_854_775_808.0));
+}
+
+#[test]
+fn abs_expressions() {
+    use expr::parser::{parse_with, ParserConfig};
+
+    let bars = ParserConfig::new().with_abs_bars(true);
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), 2.0);
+    map.insert("b".to_string(), -8.0);
+    let eval = |text: &str| parse_with(text, &bars).unwrap().eval(&map);
+    assert_eq!(eval("|a - b| / |b|"), Ok(1.25));
+    assert_eq!(eval("||a| - |b||"), Ok(6.0));
+    assert_eq!(eval("|a| | |b|"), Ok(10.0));
+}
+
+#[test]
+fn unicode_expressions() {
+    let mut map = HashMap::new();
+    map.insert("r".to_string(), 2.0);
+    map.insert("x".to_string(), 9.0);
+    assert_eq!(eval("π × r²", &map), Ok(std::f64::consts::PI * 4.0));
+    assert_eq!(eval("√x ÷ 3 \u{2212} 1", &map), Ok(0.0));
+    assert_eq!(eval("x⁻¹ * 9", &map), Ok(1.0));
+    assert_eq!(eval("2^3^2", &map), Ok(512.0));
+    assert_eq!(eval("-2^2", &map), Ok(-4.0));
+    assert_eq!(eval("r ≤ x", &map), Ok(1.0));
+    assert_eq!(eval("r ≠ 2", &map), Ok(0.0));
+    assert_eq!(eval("r > x", &map), Ok(0.0));
+}
+
+#[test]
+fn call_expressions() {
+    use expr::parse;
+    use expr::parser::{parse_with, Assoc, Binary, ParserConfig};
+    use expr::tree::{Context, Entry, Result};
+
+    struct Functions;
+
+    impl Context for Functions {
+        fn lookup(&self, name: &str) -> Option<Entry<'_>> {
+            match name {
+                "x" => Some(Entry::Number(3.0)),
+                _ => None,
+            }
+        }
+
+        fn call(&self, name: &str, args: &[f64]) -> Option<Result<f64>> {
+            match name {
+                "max" => Some(Ok(args.iter().cloned().fold(f64::NEG_INFINITY, f64::max))),
+                "hypot" => Some(Ok(args[0].hypot(args[1]))),
+                _ => None,
+            }
+        }
+    }
+
+    let ctx = Functions;
+    assert_eq!(parse("max(1, x, 2) * 2").unwrap().eval(&ctx), Ok(6.0));
+    assert_eq!(
+        parse("f(x)").unwrap().eval(&ctx),
+        Err(NoFunction("f".to_string()))
+    );
+
+    let config =
+        ParserConfig::new().with_infix("<->", 90, Assoc::Left, Binary::Call("hypot".to_string()));
+    assert_eq!(parse_with("x <-> 4", &config).unwrap().eval(&ctx), Ok(5.0));
+}
+
+#[test]
+fn logical_expressions() {
+    let mut map = HashMap::new();
+    map.insert("x".to_string(), 2.0);
+    assert_eq!(eval("x > 1 and x < 3", &map), Ok(1.0));
+    assert_eq!(eval("x < 1 or x = 2", &map), Ok(1.0));
+    assert_eq!(eval("not x", &map), Ok(0.0));
+    assert_eq!(eval("not (x - 2)", &map), Ok(1.0));
+
+    // The right operand is only evaluated when needed
+    assert_eq!(eval("x > 1 or y", &map), Ok(1.0));
+    assert_eq!(eval("x < 1 and y", &map), Ok(0.0));
+    assert_matches!(eval("x > 1 and y", &map), Err(Eval(NoValue { .. })));
+}
+
+#[test]
+fn partial_evaluation() {
+    let mut known = HashMap::new();
+    known.insert("a".to_string(), 1.0);
+    known.insert("b".to_string(), 2.0);
+    let check = |text: &str, expected: &str| {
+        let tree = parse(text).unwrap().partial_eval(&known);
+        assert_eq!(tree.to_string(), expected);
+    };
+    check("(a + b) * c", "3 * c");
+    check("c * (a - b * 2)", "c * -3");
+    check("-a ^ 2 + c", "-1 + c");
+    check("(a - b) ^ 2", "1");
+    check("f(a + b, c) + f(1)", "f(3, c) + f(1)");
+    check("c / (a - 1)", "c / 0");
+    check("b / (a - 1) + c", "2 / 0 + c");
+    check("(a << 70) + 1", "(1 << 70) + 1");
+    check("-2 * c", "-2 * c");
+    check("a.b + 1", "a.b + 1");
+
+    let tree = parse("(a + b) * c").unwrap();
+    let residual = tree.partial_eval(&known);
+    known.insert("c".to_string(), 4.0);
+    assert_eq!(residual.eval(&known), tree.eval(&known));
+    assert_eq!(residual.partial_eval(&known), Float(12.0));
+}
+
+#[test]
+fn substitution() {
+    let tree = parse("x * (x + y)").unwrap();
+    let value = parse("a - 1").unwrap();
+    assert_eq!(
+        tree.substitute("x", &value).to_string(),
+        "(a - 1) * (a - 1 + y)"
+    );
+    assert_eq!(tree.substitute("z", &value), tree);
+    assert_eq!(
+        parse("f(x, x.y)").unwrap().substitute("x", &Float(2.0)),
+        parse("f(2, x.y)").unwrap()
+    );
+}
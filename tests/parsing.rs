@@ -1 +1,162 @@
+extern crate expr;
 
+use expr::parse;
+use expr::tree::ExprTree::*;
+
+/// Check that the expression is printed as expected and that the
+/// printed text parses back into the same tree.
+fn check_print(text: &str, expected: &str) {
+    let tree = parse(text).unwrap();
+    assert_eq!(tree.to_string(), expected);
+    assert_eq!(parse(expected), Ok(tree));
+}
+
+#[test]
+fn print_expressions() {
+    check_print("10", "10");
+    check_print("10.5+x", "10.5 + x");
+    check_print("10 + 12 * 20 - 2", "10 + 12 * 20 - 2");
+    check_print("(10 + 12) * 20", "(10 + 12) * 20");
+    check_print("a - (b - c)", "a - (b - c)");
+    check_print("(a - b) - c", "a - b - c");
+    check_print("a / (b * c)", "a / (b * c)");
+    check_print("((x))", "x");
+    check_print("-x * -(y + 1)", "-x * -(y + 1)");
+    check_print(
+        "order.total*customer.discount",
+        "order.total * customer.discount",
+    );
+}
+
+#[test]
+fn print_identifiers() {
+    check_print("`Gross Margin %` * 2", "`Gross Margin %` * 2");
+    check_print("`unit_price` * 2", "unit_price * 2");
+    check_print("`unit-price`.`net value`", "`unit-price`.`net value`");
+    check_print(r"`a\`b` + `c\\d`", r"`a\`b` + `c\\d`");
+    assert_eq!(
+        parse("`Gross Margin %`"),
+        Ok(Var("Gross Margin %".to_string()))
+    );
+}
+
+#[test]
+fn print_constructed() {
+    let tree = Neg(Box::new(Neg(Box::new(Var("x".to_string())))));
+    assert_eq!(tree.to_string(), "-(-x)");
+    let tree = Add(
+        Box::new(Var("a".to_string())),
+        Box::new(Add(
+            Box::new(Var("b".to_string())),
+            Box::new(Var("c".to_string())),
+        )),
+    );
+    assert_eq!(tree.to_string(), "a + (b + c)");
+    assert_eq!(parse(&tree.to_string()), Ok(tree));
+}
+
+#[test]
+fn print_postfix() {
+    check_print("n!", "n!");
+    check_print("price*15%", "price * 15%");
+    check_print("-n!", "-n!");
+    check_print("(-n)!", "(-n)!");
+    check_print("(a + b)!%", "(a + b)!%");
+    check_print("order.total%", "order.total%");
+}
+
+#[test]
+fn print_bitwise() {
+    check_print(
+        "(flags >> 4) & 0xF | mode << 8",
+        "flags >> 4 & 0xf | mode << 8",
+    );
+    check_print("(a | b) & c", "(a | b) & c");
+    check_print("a xor (b xor c)", "a xor (b xor c)");
+    check_print("~(a + 1) mod 3 div 2", "~(a + 1) mod 3 div 2");
+    check_print("-(~a)", "-(~a)");
+    check_print("`mod` * `div`", "`mod` * `div`");
+}
+
+#[test]
+fn print_abs() {
+    use expr::parser::{parse_with, ParserConfig};
+
+    let bars = ParserConfig::new().with_abs_bars(true);
+    for &(text, expected) in &[
+        ("|a - b| / |b|", "abs(a - b) / abs(b)"),
+        ("||a| - |b||", "abs(abs(a) - abs(b))"),
+        ("|(a | b)|", "abs(a | b)"),
+        ("|a xor b|", "abs(a xor b)"),
+        ("-|a|!", "-abs(a)!"),
+    ] {
+        let tree = parse_with(text, &bars).unwrap();
+        assert_eq!(tree.to_string(), expected);
+        assert_eq!(parse(expected), Ok(tree.clone()));
+        assert_eq!(parse_with(expected, &bars), Ok(tree));
+    }
+}
+
+#[test]
+fn print_power_and_compare() {
+    check_print("x^2", "x ^ 2");
+    check_print("-x^2", "-x ^ 2");
+    check_print("(-x)^2", "(-x) ^ 2");
+    check_print("a^b^c", "a ^ b ^ c");
+    check_print("(a^b)^c", "(a ^ b) ^ c");
+    check_print("2^-x", "2 ^ -x");
+    check_print("2^(a+b)", "2 ^ (a + b)");
+    check_print("n!^2", "n! ^ 2");
+    check_print("x²", "x ^ 2");
+    check_print("√x²", "√x ^ 2");
+    check_print("√(a+b)", "√(a + b)");
+    check_print("a+1 ≤ b", "a + 1 <= b");
+    check_print("(a < b) == (c < d)", "(a < b) == (c < d)");
+    check_print("a | b != c", "a | b != c");
+}
+
+#[test]
+fn print_calls() {
+    check_print("max(a,b)", "max(a, b)");
+    check_print("f()", "f()");
+    check_print("-f(x + 1)!", "-f(x + 1)!");
+    check_print("`net value`(x)", "`net value`(x)");
+    check_print("g(a < b, (c))", "g(a < b, c)");
+}
+
+#[test]
+fn print_logical() {
+    check_print("a or b and not c", "a or b and not c");
+    check_print("(a or b) and c", "(a or b) and c");
+    check_print("not (a and b)", "not (a and b)");
+    check_print("not a < b", "not a < b");
+    check_print("(not a) < b", "(not a) < b");
+    check_print("a = b", "a == b");
+    check_print("`and` + `not`", "`and` + `not`");
+}
+
+#[test]
+fn introspection() {
+    let tree = parse("f(x, order.total) * x + g(y) - f(2)").unwrap();
+    assert_eq!(tree.free_variables(), ["x", "order.total", "y"]);
+    assert_eq!(tree.functions(), ["f", "g"]);
+    assert_eq!(tree.node_count(), 11);
+    assert_eq!(tree.depth(), 5);
+    assert!(!tree.is_constant());
+
+    let tree = parse("(1 + 2) * -3!").unwrap();
+    assert_eq!(tree.free_variables(), Vec::<String>::new());
+    assert_eq!(tree.functions(), Vec::<&str>::new());
+    assert_eq!(tree.node_count(), 7);
+    assert_eq!(tree.depth(), 4);
+    assert!(tree.is_constant());
+
+    let tree = parse("order.`unit.price` * `order.unit`.price").unwrap();
+    assert_eq!(
+        tree.free_variables(),
+        ["order.`unit.price`", "`order.unit`.price"]
+    );
+
+    assert_eq!(parse("x").unwrap().depth(), 1);
+    assert!(!parse("f()").unwrap().is_constant());
+}